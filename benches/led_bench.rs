@@ -0,0 +1,73 @@
+//! Controller overhead on a backend that does nothing: the locking and bookkeeping around each GPIO write
+//! Run with `cargo bench`; criterion writes its reports to target/criterion.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use train::{Led, LedBackend, LedController, Result};
+
+/// Accepts every write and keeps nothing, unlike `MockBackend`, whose write log would grow
+/// by millions of entries over a run
+struct NullBackend;
+
+impl LedBackend for NullBackend {
+    fn set(&self, _led: u8, _on: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn controller() -> LedController {
+    LedController::with_backend(Arc::new(NullBackend))
+}
+
+fn led_operations(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let leds = controller();
+    let led = Led::new(5).unwrap();
+
+    c.bench_function("on", |b| b.to_async(&runtime).iter(|| leds.on(led)));
+    c.bench_function("off", |b| b.to_async(&runtime).iter(|| leds.off(led)));
+    c.bench_function("on_off", |b| b.to_async(&runtime).iter(|| async {
+        leds.on(led).await.unwrap();
+        leds.off(led).await.unwrap();
+    }));
+    c.bench_function("blink_start_and_stop", |b| b.to_async(&runtime).iter(|| async {
+        leds.blink(led, 500).await.unwrap();
+        leds.stop_blink(led).await.unwrap();
+    }));
+    c.bench_function("all_off", |b| b.to_async(&runtime).iter(|| leds.all_off()));
+    c.bench_function("set_frame_u32", |b| {
+        let mut bits = 0u32;
+        b.to_async(&runtime).iter(|| {
+            bits = (bits + 0x5a5a5) & 0xff_ffff;
+            leds.set_frame_u32(bits)
+        })
+    });
+}
+
+/// Raw GPIO writes, bypassing the controller: the backend's own per-write cost
+/// Only meaningful on a Pi; skipped when the chip can't be opened or LED 5's line isn't available.
+#[cfg(feature = "hardware")]
+fn gpio_writes(c: &mut Criterion) {
+    let backend = match train::GpioBackend::new_partial() {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("Skipping GPIO benchmarks: {}", e);
+            return;
+        }
+    };
+    if let Some(reason) = backend.failure(5) {
+        eprintln!("Skipping GPIO benchmarks: LED 5 unavailable: {}", reason);
+        return;
+    }
+    c.bench_function("gpio_on_off", |b| b.iter(|| {
+        backend.set(5, true).unwrap();
+        backend.set(5, false).unwrap();
+    }));
+}
+
+#[cfg(not(feature = "hardware"))]
+fn gpio_writes(_c: &mut Criterion) {}
+
+criterion_group!(benches, led_operations, gpio_writes);
+criterion_main!(benches);
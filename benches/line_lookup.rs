@@ -0,0 +1,138 @@
+//! Per-write cost of finding an LED's GPIO line, for the layouts `GpioBackend` has used
+//! The handles are stand-ins (atomic stores in place of the kernel call), so this times only
+//! the lookup and locking around each write. Run with `cargo bench --bench line_lookup`.
+
+use arc_swap::ArcSwap;
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use train::LED_COUNT;
+
+/// Stand-in for a gpio-cdev `LineHandle`
+#[derive(Default)]
+struct Line(AtomicU8);
+
+impl Line {
+    fn set_value(&self, value: u8) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+/// Stand-in for a gpio-cdev `MultiLineHandle`, which sets every line it holds in one call
+struct MultiLine(Vec<AtomicU8>);
+
+impl MultiLine {
+    fn set_values(&self, values: &[u8]) {
+        for (line, &value) in self.0.iter().zip(values) {
+            line.store(value, Ordering::Relaxed);
+        }
+    }
+}
+
+/// One chip's lines on a shared handle, with the last value written to each
+struct Bulk {
+    handle: MultiLine,
+    leds: Vec<u8>,
+    values: Vec<u8>,
+}
+
+impl Bulk {
+    fn new() -> Self {
+        let count = usize::from(LED_COUNT);
+        Self { handle: MultiLine((0..count).map(|_| AtomicU8::new(0)).collect()), leds: (1..=LED_COUNT).collect(), values: vec![0; count] }
+    }
+
+    fn index(&self, led: u8) -> Option<usize> {
+        self.leds.iter().position(|&member| member == led)
+    }
+
+    fn write(&mut self, led: u8, on: bool) {
+        let mut next = self.values.clone();
+        next[self.index(led).unwrap()] = u8::from(on);
+        self.handle.set_values(&next);
+        self.values = next;
+    }
+}
+
+/// Slot kinds for the bulk layouts below
+enum Slot {
+    /// On a handle in the backend-wide list
+    SharedBulk,
+    /// On its chip's handle, behind that chip's lock
+    ChipBulk(Arc<Mutex<Option<Bulk>>>),
+}
+
+fn line_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("line_lookup");
+
+    // Original layout: the whole map behind a lock, plus a per-LED mutex
+    let map: Arc<RwLock<HashMap<u8, Arc<Mutex<Line>>>>> = Arc::new(RwLock::new(
+        (1..=LED_COUNT).map(|led| (led, Arc::new(Mutex::new(Line::default())))).collect()
+    ));
+    group.bench_function("locked_map", |b| b.iter(|| {
+        let handles = map.read().unwrap();
+        let line = handles.get(&black_box(5)).unwrap().lock().unwrap();
+        line.set_value(1);
+    }));
+
+    // Fixed per-LED slots, indexed by LED - 1, each with its own lock
+    let slots: Vec<RwLock<Line>> = (0..LED_COUNT).map(|_| RwLock::new(Line::default())).collect();
+    group.bench_function("locked_slots", |b| b.iter(|| {
+        slots[usize::from(black_box(5u8)) - 1].read().unwrap().set_value(1);
+    }));
+
+    // Current layout: slots read without a lock
+    let swapped: Box<[ArcSwap<Line>]> = (0..LED_COUNT).map(|_| ArcSwap::from_pointee(Line::default())).collect();
+    group.bench_function("arc_swap_slots", |b| b.iter(|| {
+        swapped[usize::from(black_box(5u8)) - 1].load().set_value(1);
+    }));
+
+    group.finish();
+}
+
+/// An LED switched on then off, as the default setup runs it: every line on one chip's bulk handle
+fn on_off(c: &mut Criterion) {
+    let mut group = c.benchmark_group("on_off");
+
+    // Before the slots: map lock, then the LED's own line
+    let map: Arc<RwLock<HashMap<u8, Arc<Mutex<Line>>>>> = Arc::new(RwLock::new(
+        (1..=LED_COUNT).map(|led| (led, Arc::new(Mutex::new(Line::default())))).collect()
+    ));
+    group.bench_function("locked_map", |b| b.iter(|| {
+        for value in [1, 0] {
+            map.read().unwrap().get(&black_box(5)).unwrap().lock().unwrap().set_value(value);
+        }
+    }));
+
+    // Lock-free slots, then one mutex over every chip's handle
+    let slots: Box<[ArcSwap<Slot>]> = (0..LED_COUNT).map(|_| ArcSwap::from_pointee(Slot::SharedBulk)).collect();
+    let bulks = Mutex::new(vec![Bulk::new()]);
+    group.bench_function("global_bulk", |b| b.iter(|| {
+        for on in [true, false] {
+            let led = black_box(5u8);
+            if let Slot::SharedBulk = **slots[usize::from(led) - 1].load() {
+                let mut bulks = bulks.lock().unwrap();
+                bulks.iter_mut().find(|bulk| bulk.index(led).is_some()).unwrap().write(led, on);
+            }
+        }
+    }));
+
+    // Current layout: lock-free slots, each bulk slot holding its own chip's lock
+    let chip = Arc::new(Mutex::new(Some(Bulk::new())));
+    let slots: Box<[ArcSwap<Slot>]> = (0..LED_COUNT).map(|_| ArcSwap::from_pointee(Slot::ChipBulk(Arc::clone(&chip)))).collect();
+    group.bench_function("chip_bulk", |b| b.iter(|| {
+        for on in [true, false] {
+            let led = black_box(5u8);
+            if let Slot::ChipBulk(lines) = &**slots[usize::from(led) - 1].load() {
+                lines.lock().unwrap().as_mut().unwrap().write(led, on);
+            }
+        }
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, line_lookup, on_off);
+criterion_main!(benches);
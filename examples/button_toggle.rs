@@ -0,0 +1,33 @@
+//! A push-button on GPIO 2 (to ground, using the internal pull-up) toggles LED 1
+//!
+//! Run on the Pi with `cargo run --example button_toggle`
+
+use std::time::Duration;
+use train::{Edge, InputController, InputLine, Led, LedController, LedState, Pull};
+
+#[tokio::main]
+async fn main() -> train::Result<()> {
+    let leds = LedController::new()?;
+    let inputs = InputController::new("/dev/gpiochip0", &[InputLine { line: 2, pull: Pull::Up }], Duration::from_millis(20))?;
+    let mut presses = inputs.subscribe();
+    let led = Led::new(1)?;
+
+    println!("Press the button on GPIO 2 to toggle LED 1 (Ctrl+C to quit)");
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            event = presses.recv() => match event {
+                Ok(event) if event.edge == Edge::Falling => {
+                    if leds.state(led).await == LedState::Off {
+                        leds.on(led).await?;
+                    } else {
+                        leds.off(led).await?;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Missed button events: {}", e),
+            },
+        }
+    }
+    leds.all_off().await
+}
@@ -0,0 +1,103 @@
+use crate::events::LedEvent;
+use crate::leds::{Led, LedController, LedState};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+/// Entries kept by `AuditLog`; older ones are dropped
+pub const AUDIT_LOG_CAPACITY: usize = 1000;
+
+/// What a command did to an LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum LedOperation {
+    On,
+    Off,
+    Blink { frequency_ms: u64 },
+    /// A running effect was stopped, leaving the LED off
+    Cancel,
+    /// Any other effect (dimming, flicker, patterns, ...), with the state it put the LED in
+    Effect { state: LedState },
+}
+
+impl LedOperation {
+    /// The operation behind a command event; effect-driven events (blink toggles) aren't commands
+    fn of(event: &LedEvent) -> Option<Self> {
+        if event.effect {
+            return None;
+        }
+        Some(match event.new_state {
+            LedState::On => LedOperation::On,
+            LedState::Off if matches!(event.old_state, LedState::On | LedState::Off) => LedOperation::Off,
+            LedState::Off => LedOperation::Cancel,
+            LedState::Blinking { frequency_ms } => LedOperation::Blink { frequency_ms },
+            state => LedOperation::Effect { state },
+        })
+    }
+}
+
+/// One command in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub led: Led,
+    #[serde(flatten)]
+    pub operation: LedOperation,
+}
+
+/// The most recent LED commands, oldest first, for `GET /api/leds/log`
+/// Commands are recorded whatever issued them (API, scheduler, watchdog timers), from the
+/// controller's event channel. If recording falls behind, the missed commands are skipped.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: Mutex<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    /// Start recording every command `leds` carries out, until its shutdown token is cancelled
+    /// Must be called from within a Tokio runtime.
+    pub fn record(leds: &LedController) -> Arc<Self> {
+        let log = Arc::new(Self::default());
+        let mut events = leds.subscribe();
+        let shutdown = leds.shutdown_token();
+        let recorder = Arc::clone(&log);
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    event = events.recv() => event,
+                };
+                match event {
+                    Ok(event) => {
+                        if let Some(operation) = LedOperation::of(&event) {
+                            recorder.push(AuditEntry { timestamp: event.timestamp.into(), led: event.led, operation });
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "Audit log fell behind; some LED events weren't recorded");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        log
+    }
+
+    /// Add an entry, dropping the oldest once `AUDIT_LOG_CAPACITY` is reached
+    pub fn push(&self, entry: AuditEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == AUDIT_LOG_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The last `count` entries, oldest first
+    pub fn last(&self, count: usize) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.iter().skip(entries.len().saturating_sub(count)).cloned().collect()
+    }
+}
@@ -0,0 +1,265 @@
+use crate::error::{Result, TrainError};
+use crate::leds::LED_COUNT;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+mod composite;
+#[cfg(feature = "hardware")]
+mod gpio;
+mod i2c;
+mod mcp23017;
+mod pca9685;
+mod shift_register;
+
+pub use composite::CompositeBackend;
+#[cfg(feature = "hardware")]
+pub use gpio::GpioBackend;
+pub use i2c::I2cBus;
+#[cfg(feature = "hardware")]
+pub use i2c::LinuxI2cBus;
+pub use mcp23017::Mcp23017Backend;
+pub use pca9685::Pca9685Backend;
+pub use shift_register::{BitOrder, ShiftLine, ShiftLines, ShiftRegisterBackend};
+
+/// Low-level output driver used by `LedController`
+/// Backends only switch outputs; state tracking and effects live in the controller
+pub trait LedBackend: Send + Sync {
+    /// Drive a single LED (1-24) on or off
+    fn set(&self, led: u8, on: bool) -> Result<()>;
+
+    /// Drive several LEDs at once, as (led, on) pairs
+    /// Backends that can switch outputs together do so in one step; the default sets them in order
+    fn set_many(&self, values: &[(u8, bool)]) -> Result<()> {
+        for &(led, on) in values {
+            self.set(led, on)?;
+        }
+        Ok(())
+    }
+
+    /// Why an LED can't be driven, if its output failed to initialize
+    fn failure(&self, _led: u8) -> Option<String> {
+        None
+    }
+
+    /// All LEDs whose outputs failed to initialize, with the reason
+    fn failed_leds(&self) -> Vec<(u8, String)> {
+        (1..=LED_COUNT)
+            .filter_map(|led| self.failure(led).map(|reason| (led, reason)))
+            .collect()
+    }
+
+    /// Give up the output for an LED so another program can use it
+    fn release(&self, _led: u8) -> Result<()> {
+        Err(TrainError::NotSupported)
+    }
+
+    /// Take back an output given up by `release()`, or retry one that failed to initialize
+    fn acquire(&self, _led: u8) -> Result<()> {
+        Err(TrainError::NotSupported)
+    }
+
+    /// Whether an LED's output has been given up by `release()`
+    fn is_released(&self, _led: u8) -> bool {
+        false
+    }
+
+    /// Whether an LED's output can be dimmed in hardware with `set_level()`
+    fn has_pwm(&self, _led: u8) -> bool {
+        false
+    }
+
+    /// Drive an LED at a brightness from 0 (off) to 255 (fully on) using hardware PWM
+    fn set_level(&self, _led: u8, _level: u8) -> Result<()> {
+        Err(TrainError::NotSupported)
+    }
+
+    /// How the kernel reports an LED's output line, for spotting miswired or misconfigured pins
+    fn line_info(&self, _led: u8) -> Result<LineDiagnostics> {
+        Err(TrainError::NotSupported)
+    }
+}
+
+/// Kernel view of the GPIO line behind an LED, from `LedBackend::line_info()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct LineDiagnostics {
+    pub led: u8,
+    pub chip: String,
+    pub line: u8,
+    /// Whether the line is configured as an output
+    pub output: bool,
+    pub active_low: bool,
+    /// `push-pull`, `open-drain` or `open-source`
+    pub drive: String,
+    /// Label of whoever holds the line, if anyone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub consumer: Option<String>,
+    /// Ways the line differs from how LED lines are requested (active-high push-pull outputs under our label)
+    pub warnings: Vec<String>,
+}
+
+impl LineDiagnostics {
+    /// Fill in `warnings` for a line that should be held under the `consumer` label
+    #[cfg(any(feature = "hardware", test))]
+    pub(crate) fn check(mut self, consumer: &str) -> Self {
+        let mut warnings = Vec::new();
+        match self.consumer.as_deref() {
+            Some(holder) if holder == consumer => {}
+            Some(holder) => warnings.push(format!("held by \"{}\", not \"{}\"", holder, consumer)),
+            None => warnings.push("not held by any process".to_string()),
+        }
+        if !self.output {
+            warnings.push("configured as an input".to_string());
+        }
+        if self.active_low {
+            warnings.push("active-low, so the LED lights when switched off".to_string());
+        }
+        if self.drive != "push-pull" {
+            warnings.push(format!("{} rather than push-pull", self.drive));
+        }
+        self.warnings = warnings;
+        self
+    }
+}
+
+/// Error unless `led` is 1-24, for backends with no lines to look up
+fn check_led(led: u8) -> Result<()> {
+    if !(1..=LED_COUNT).contains(&led) {
+        return Err(TrainError::LedOutOfRange { led, max: LED_COUNT });
+    }
+    Ok(())
+}
+
+/// In-memory backend for tests and development off the Pi
+/// Records every write so callers can assert on the exact GPIO traffic
+#[derive(Default)]
+pub struct MockBackend {
+    writes: Mutex<Vec<(u8, bool)>>,
+    released: Mutex<HashSet<u8>>,
+    /// Writes that succeed before every later one fails, if set
+    fail_after: Mutex<Option<usize>>,
+    /// LEDs whose writes always fail
+    failing: Mutex<HashSet<u8>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All writes issued so far, in order, as (led, on)
+    pub fn writes(&self) -> Vec<(u8, bool)> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    /// Fail every write with a GPIO error once `count` writes have succeeded, to simulate a dead line
+    pub fn fail_after(&self, count: usize) {
+        *self.fail_after.lock().unwrap() = Some(count);
+    }
+
+    /// Fail every write to `led` with a GPIO error, to simulate one bad line
+    pub fn fail_led(&self, led: u8) {
+        self.failing.lock().unwrap().insert(led);
+    }
+}
+
+impl LedBackend for MockBackend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        check_led(led)?;
+        if self.is_released(led) {
+            return Err(TrainError::LedReleased(led));
+        }
+        let mut writes = self.writes.lock().unwrap();
+        if self.fail_after.lock().unwrap().is_some_and(|count| writes.len() >= count)
+            || self.failing.lock().unwrap().contains(&led)
+        {
+            return Err(TrainError::GPIO(format!("Simulated failure writing LED {}", led)));
+        }
+        writes.push((led, on));
+        Ok(())
+    }
+
+    fn release(&self, led: u8) -> Result<()> {
+        check_led(led)?;
+        self.released.lock().unwrap().insert(led);
+        Ok(())
+    }
+
+    fn acquire(&self, led: u8) -> Result<()> {
+        check_led(led)?;
+        self.released.lock().unwrap().remove(&led);
+        Ok(())
+    }
+
+    fn is_released(&self, led: u8) -> bool {
+        self.released.lock().unwrap().contains(&led)
+    }
+}
+
+/// Backend that drives nothing, printing each write to stderr instead
+/// Lets command sequences be checked off the Pi (`--dry-run`)
+#[derive(Default)]
+pub struct DryRunBackend {
+    released: Mutex<HashSet<u8>>,
+}
+
+impl DryRunBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LedBackend for DryRunBackend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        check_led(led)?;
+        if self.is_released(led) {
+            return Err(TrainError::LedReleased(led));
+        }
+        eprintln!("[DRY-RUN] LED {} -> {}", led, if on { "ON" } else { "OFF" });
+        Ok(())
+    }
+
+    fn release(&self, led: u8) -> Result<()> {
+        check_led(led)?;
+        self.released.lock().unwrap().insert(led);
+        Ok(())
+    }
+
+    fn acquire(&self, led: u8) -> Result<()> {
+        check_led(led)?;
+        self.released.lock().unwrap().remove(&led);
+        Ok(())
+    }
+
+    fn is_released(&self, led: u8) -> bool {
+        self.released.lock().unwrap().contains(&led)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_diagnostics_flag_lines_that_are_not_ours_or_not_outputs() {
+        let line = LineDiagnostics {
+            led: 3,
+            chip: "/dev/gpiochip0".to_string(),
+            line: 6,
+            output: true,
+            active_low: false,
+            drive: "push-pull".to_string(),
+            consumer: Some("train-led".to_string()),
+            warnings: Vec::new(),
+        };
+        assert_eq!(line.clone().check("train-led").warnings, Vec::<String>::new());
+
+        let miswired = LineDiagnostics { output: false, active_low: true, consumer: Some("other".to_string()), ..line }.check("train-led");
+        assert_eq!(miswired.warnings, vec![
+            "held by \"other\", not \"train-led\"",
+            "configured as an input",
+            "active-low, so the LED lights when switched off",
+        ]);
+    }
+}
@@ -0,0 +1,94 @@
+use super::{check_led, LedBackend, LineDiagnostics};
+use crate::error::{Result, TrainError};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Backend that sends ranges of LEDs to different backends, e.g. the dimmable ones to a PCA9685
+/// and the rest to GPIO. LEDs outside every range go to the fallback.
+pub struct CompositeBackend {
+    fallback: Arc<dyn LedBackend>,
+    routes: Vec<(RangeInclusive<u8>, Arc<dyn LedBackend>)>,
+}
+
+impl CompositeBackend {
+    pub fn new(fallback: Arc<dyn LedBackend>) -> Self {
+        Self { fallback, routes: Vec::new() }
+    }
+
+    /// Send the LEDs in `leds` to `backend`; ranges can't overlap
+    /// LED numbers are passed through unchanged
+    pub fn with_range(mut self, leds: RangeInclusive<u8>, backend: Arc<dyn LedBackend>) -> Result<Self> {
+        if leds.is_empty() {
+            return Err(TrainError::InvalidParameter(format!("LED range {}-{} is empty", leds.start(), leds.end())));
+        }
+        check_led(*leds.start())?;
+        check_led(*leds.end())?;
+        if let Some((other, _)) = self.routes.iter().find(|(other, _)| other.start() <= leds.end() && leds.start() <= other.end()) {
+            return Err(TrainError::InvalidParameter(format!(
+                "LEDs {}-{} overlap {}-{}", leds.start(), leds.end(), other.start(), other.end()
+            )));
+        }
+        self.routes.push((leds, backend));
+        Ok(self)
+    }
+
+    /// Index of the route driving an LED, or `routes.len()` for the fallback
+    fn route(&self, led: u8) -> usize {
+        self.routes.iter()
+            .position(|(leds, _)| leds.contains(&led))
+            .unwrap_or(self.routes.len())
+    }
+
+    fn backend(&self, led: u8) -> &dyn LedBackend {
+        match self.routes.get(self.route(led)) {
+            Some((_, backend)) => backend.as_ref(),
+            None => self.fallback.as_ref(),
+        }
+    }
+}
+
+impl LedBackend for CompositeBackend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        self.backend(led).set(led, on)
+    }
+
+    /// Each backend gets one `set_many()` call with its share of the LEDs
+    fn set_many(&self, values: &[(u8, bool)]) -> Result<()> {
+        let mut shares = vec![Vec::new(); self.routes.len() + 1];
+        for &(led, on) in values {
+            shares[self.route(led)].push((led, on));
+        }
+        for share in shares.iter().filter(|share| !share.is_empty()) {
+            self.backend(share[0].0).set_many(share)?;
+        }
+        Ok(())
+    }
+
+    fn failure(&self, led: u8) -> Option<String> {
+        self.backend(led).failure(led)
+    }
+
+    fn release(&self, led: u8) -> Result<()> {
+        self.backend(led).release(led)
+    }
+
+    fn acquire(&self, led: u8) -> Result<()> {
+        self.backend(led).acquire(led)
+    }
+
+    fn is_released(&self, led: u8) -> bool {
+        self.backend(led).is_released(led)
+    }
+
+    fn line_info(&self, led: u8) -> Result<LineDiagnostics> {
+        self.backend(led).line_info(led)
+    }
+
+    fn has_pwm(&self, led: u8) -> bool {
+        self.backend(led).has_pwm(led)
+    }
+
+    fn set_level(&self, led: u8, level: u8) -> Result<()> {
+        self.backend(led).set_level(led, level)
+    }
+}
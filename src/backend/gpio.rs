@@ -0,0 +1,381 @@
+use super::{LedBackend, LineDiagnostics};
+use crate::config::LedConfig;
+use crate::error::{Result, TrainError};
+use crate::leds::LED_COUNT;
+use arc_swap::ArcSwap;
+use gpio_cdev::{Chip, LineDirection, LineHandle, LineRequestFlags, MultiLineHandle};
+use nix::errno::Errno;
+use std::collections::HashMap;
+use std::error::Error as _;
+use std::sync::{Arc, Mutex};
+
+/// Line request flags used for every LED output
+const LED_LINE_FLAGS: LineRequestFlags = LineRequestFlags::OUTPUT;
+
+/// GPIO line for a single LED
+enum LineSlot {
+    /// Requested and ready to drive
+    Active(LineHandle),
+    /// Requested as part of its chip's shared multi-line handle
+    Bulk(Arc<ChipLines>),
+    /// Could not be requested, with the error
+    Failed(String),
+    /// Handed back to the kernel by `release()`
+    Released,
+}
+
+/// One kernel handle over several LED lines on a chip, so they can all be switched in a single call
+struct BulkLines {
+    /// Path of the chip the lines belong to
+    chip: String,
+    handle: MultiLineHandle,
+    /// LED driven by each line of the handle, in handle order
+    leds: Vec<u8>,
+    /// Last value written to each line, in handle order
+    values: Vec<u8>,
+}
+
+impl BulkLines {
+    /// Position of an LED's line within the handle
+    fn index(&self, led: u8) -> Option<usize> {
+        self.leds.iter().position(|&member| member == led)
+    }
+
+    /// Switch the given LEDs, all on this handle, in a single call
+    fn write(&mut self, values: &[(u8, bool)]) -> Result<()> {
+        let mut next = self.values.clone();
+        for &(led, on) in values {
+            let index = self.index(led).ok_or_else(|| TrainError::GPIO(format!("LED {} lost its GPIO line", led)))?;
+            next[index] = u8::from(on);
+        }
+        self.handle.set_values(&next)
+            .map_err(|e| TrainError::GPIO(format!("Failed to switch LEDs {:?}: {}", self.leds, e)))?;
+        self.values = next;
+        Ok(())
+    }
+}
+
+/// How the lines left on a chip are held once one of them has been released
+enum Regrouped<B, L> {
+    /// On a new multi-line handle
+    Together(B),
+    /// Requested one by one because the handle couldn't be, with each LED's result
+    Separately(Vec<(u8, Result<L>)>),
+}
+
+/// Re-request the lines left on a chip, given as (LED, current value), together if possible
+/// and otherwise each on its own
+fn regroup<B, L>(
+    remaining: &[(u8, u8)],
+    together: impl FnOnce() -> Result<B>,
+    mut separately: impl FnMut(u8, u8) -> Result<L>,
+) -> Regrouped<B, L> {
+    match together() {
+        Ok(bulk) => Regrouped::Together(bulk),
+        Err(e) => {
+            tracing::warn!(error = %e, "Requesting the remaining LED lines one at a time");
+            Regrouped::Separately(remaining.iter().map(|&(led, value)| (led, separately(led, value))).collect())
+        }
+    }
+}
+
+/// A chip's multi-line handle, shared by its `Bulk` slots behind that chip's own lock
+/// `None` while the handle is being re-requested, or once it couldn't be
+type ChipLines = Mutex<Option<BulkLines>>;
+
+/// Backend driving LEDs directly from GPIO lines via the Linux character device
+/// LEDs are numbered 1-24, mapped to GPIO pins by `LedConfig` (4-27 of /dev/gpiochip0 by default)
+pub struct GpioBackend {
+    /// Open GPIO chips by path, kept for re-requesting released lines
+    chips: Mutex<HashMap<String, Chip>>,
+    /// Pin map the lines were requested with
+    config: LedConfig,
+    /// GPIO line for each LED, indexed by LED number - 1
+    /// Writes read their slot without locking; only a `Bulk` line takes a lock, its own chip's, since
+    /// the chip's handle sets all its lines at once. `release()` and `acquire()` swap in a new slot.
+    /// A replaced handle goes back to the kernel once in-flight writes finish.
+    /// Lock order: `slot_changes`, then a chip's `ChipLines`, then `chips`
+    lines: Box<[ArcSwap<LineSlot>]>,
+    /// Held while a slot is replaced, so `release()` and `acquire()` calls for an LED can't interleave
+    slot_changes: Mutex<()>,
+}
+
+impl GpioBackend {
+    /// Open the GPIO chip and request all 24 LED lines as outputs (initially off)
+    /// Fails if any single line can't be requested
+    pub fn new() -> Result<Self> {
+        Self::with_config(&LedConfig::default())
+    }
+
+    /// Like `new()`, but keeps going when individual lines can't be requested
+    /// Failed LEDs are reported by `failed_leds()`; only failing to open the chip is fatal
+    pub fn new_partial() -> Result<Self> {
+        Self::with_config(&LedConfig { strict: false, ..LedConfig::default() })
+    }
+
+    /// Open the configured chips and request each LED's line as an output (initially off)
+    /// Each chip is opened once. Its lines are requested as one multi-line handle when possible; if that
+    /// fails they are requested one by one, which pinpoints the failing LEDs (and allows degraded mode)
+    pub fn with_config(config: &LedConfig) -> Result<Self> {
+        config.validate()?;
+
+        let mut chips = HashMap::new();
+        let mut slots = HashMap::new();
+
+        for path in config.chips() {
+            // Usually /dev/gpiochip0 on Raspberry Pi
+            let mut chip = Chip::new(path)
+                .map_err(|e| TrainError::GPIO(format!("Failed to open GPIO chip {}: {}", path, e)))?;
+            let leds: Vec<u8> = (1..=LED_COUNT)
+                .filter(|&led| config.gpio_line(led).is_ok_and(|(chip, _)| chip == path))
+                .collect();
+
+            let values = vec![0; leds.len()];
+            match Self::request_bulk(&mut chip, path, config, leds.clone(), values) {
+                Ok(lines) => {
+                    let lines = Arc::new(Mutex::new(Some(lines)));
+                    slots.extend(leds.iter().map(|&led| (led, LineSlot::Bulk(Arc::clone(&lines)))));
+                }
+                Err(e) => {
+                    tracing::debug!(chip = path, error = %e, "Requesting LED lines one at a time");
+                    for led_num in leds {
+                        match Self::request_line(&mut chip, config, led_num, 0) {
+                            Ok(handle) => slots.insert(led_num, LineSlot::Active(handle)),
+                            Err(e) if config.strict => return Err(e),
+                            Err(e) => slots.insert(led_num, LineSlot::Failed(e.to_string())),
+                        };
+                    }
+                }
+            }
+            chips.insert(path.to_string(), chip);
+        }
+
+        let lines = (1..=LED_COUNT)
+            .map(|led| slots.remove(&led).unwrap_or_else(|| LineSlot::Failed(format!("No GPIO pin configured for LED {}", led))))
+            .map(ArcSwap::from_pointee)
+            .collect();
+
+        Ok(Self {
+            chips: Mutex::new(chips),
+            config: config.clone(),
+            lines,
+            slot_changes: Mutex::new(()),
+        })
+    }
+
+    /// Line slot for an LED (1-24)
+    fn slot(&self, led: u8) -> Result<&ArcSwap<LineSlot>> {
+        led.checked_sub(1)
+            .and_then(|index| self.lines.get(index as usize))
+            .ok_or(TrainError::LedOutOfRange { led, max: LED_COUNT })
+    }
+
+    /// Request the lines for `leds` (all on the chip at `path`) as one handle, initially driven to `values`
+    fn request_bulk(chip: &mut Chip, path: &str, config: &LedConfig, leds: Vec<u8>, values: Vec<u8>) -> Result<BulkLines> {
+        let pins = leds.iter()
+            .map(|&led| config.gpio_pin(led).map(u32::from))
+            .collect::<Result<Vec<u32>>>()?;
+        let handle = chip.get_lines(&pins)
+            .and_then(|lines| lines.request(LED_LINE_FLAGS, &values, &config.consumer))
+            .map_err(|e| TrainError::GPIO(format!(
+                "Failed to request GPIO lines {:?} of {} together as \"{}\": {}", pins, path, config.consumer, e
+            )))?;
+        Ok(BulkLines { chip: path.to_string(), handle, leds, values })
+    }
+
+    /// Write LEDs that are all on one chip's bulk handle, with a single call
+    fn write_bulk(lines: &ChipLines, values: &[(u8, bool)]) -> Result<()> {
+        match lines.lock().unwrap().as_mut() {
+            Some(bulk) => bulk.write(values),
+            None => Err(TrainError::GPIO(format!("LEDs {:?} lost their GPIO lines", values.iter().map(|&(led, _)| led).collect::<Vec<_>>()))),
+        }
+    }
+
+    /// Run `f` on the open chip an LED's line belongs to
+    fn with_chip<T>(&self, led: u8, f: impl FnOnce(&mut Chip) -> Result<T>) -> Result<T> {
+        let (path, _) = self.config.gpio_line(led)?;
+        let mut chips = self.chips.lock().unwrap();
+        let chip = chips.get_mut(path)
+            .ok_or_else(|| TrainError::GPIO(format!("GPIO chip {} for LED {} is not open", path, led)))?;
+        f(chip)
+    }
+
+    /// Request one LED's line as an output, initially driven to `value`
+    fn request_line(chip: &mut Chip, config: &LedConfig, led_num: u8, value: u8) -> Result<LineHandle> {
+        let (path, gpio_pin) = config.gpio_line(led_num)?;
+        let line = chip.get_line(gpio_pin as u32)
+            .map_err(|e| TrainError::GPIO(format!("Failed to get GPIO line {} of {} for LED {}: {}", gpio_pin, path, led_num, e)))?;
+
+        line.request(LED_LINE_FLAGS, value, &config.consumer)
+            .map_err(|e| {
+                // EBUSY means another process (or another instance of this one) holds the line
+                if e.source().and_then(|cause| cause.downcast_ref::<Errno>()) == Some(&Errno::EBUSY) {
+                    let holder = line.info().ok()
+                        .and_then(|info| info.consumer().map(|consumer| format!("\"{}\"", consumer)))
+                        .unwrap_or_else(|| "another process".to_string());
+                    TrainError::DeviceBusy { pin: gpio_pin, holder, consumer: config.consumer.clone() }
+                } else {
+                    TrainError::GPIO(format!(
+                        "Failed to request GPIO line {} for LED {} as \"{}\": {}", gpio_pin, led_num, config.consumer, e
+                    ))
+                }
+            })
+    }
+}
+
+impl GpioBackend {
+    /// Take an LED's line out of its chip's bulk handle
+    /// A handle can't give up a single line, so it is dropped and the remaining lines
+    /// re-requested together at their current values. If that fails (another process may have
+    /// taken one in the gap), each is requested on its own, and any that can't be becomes `Failed`.
+    fn release_from_bulk(&self, led: u8, lines: &ChipLines) {
+        let mut lines = lines.lock().unwrap();
+        let Some(old) = lines.take() else {
+            return;
+        };
+        let remaining: Vec<(u8, u8)> = old.leds.iter().zip(&old.values)
+            .filter(|&(&member, _)| member != led)
+            .map(|(&member, &value)| (member, value))
+            .collect();
+        let path = old.chip.clone();
+        drop(old);
+        if remaining.is_empty() {
+            return;
+        }
+
+        let (leds, values) = remaining.iter().copied().unzip();
+        let regrouped = regroup(
+            &remaining,
+            || self.with_chip(led, |chip| Self::request_bulk(chip, &path, &self.config, leds, values)),
+            |member, value| self.with_chip(member, |chip| Self::request_line(chip, &self.config, member, value)),
+        );
+        match regrouped {
+            Regrouped::Together(bulk) => *lines = Some(bulk),
+            Regrouped::Separately(results) => {
+                for (member, result) in results {
+                    let slot = match result {
+                        Ok(handle) => LineSlot::Active(handle),
+                        Err(e) => LineSlot::Failed(e.to_string()),
+                    };
+                    self.lines[usize::from(member) - 1].store(Arc::new(slot));
+                }
+            }
+        }
+    }
+}
+
+impl LedBackend for GpioBackend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        match &**self.slot(led)?.load() {
+            LineSlot::Active(handle) => handle.set_value(if on { 1 } else { 0 })
+                .map_err(|e| TrainError::GPIO(format!("Failed to turn {} LED {}: {}", if on { "on" } else { "off" }, led, e))),
+            LineSlot::Bulk(lines) => Self::write_bulk(lines, &[(led, on)]),
+            LineSlot::Failed(reason) => Err(TrainError::LedUnavailable(led, reason.clone())),
+            LineSlot::Released => Err(TrainError::LedReleased(led)),
+        }
+    }
+
+    fn set_many(&self, values: &[(u8, bool)]) -> Result<()> {
+        // One call per chip for the bulk lines, then the rest line by line
+        let mut chips: Vec<(Arc<ChipLines>, Vec<_>)> = Vec::new();
+        let mut rest = Vec::new();
+        for &(led, on) in values {
+            match &**self.slot(led)?.load() {
+                LineSlot::Bulk(lines) => match chips.iter_mut().find(|(chip, _)| Arc::ptr_eq(chip, lines)) {
+                    Some((_, chip_values)) => chip_values.push((led, on)),
+                    None => chips.push((Arc::clone(lines), vec![(led, on)])),
+                },
+                _ => rest.push((led, on)),
+            }
+        }
+        for (lines, chip_values) in &chips {
+            Self::write_bulk(lines, chip_values)?;
+        }
+        for (led, on) in rest {
+            self.set(led, on)?;
+        }
+        Ok(())
+    }
+
+    fn failure(&self, led: u8) -> Option<String> {
+        match &**self.slot(led).ok()?.load() {
+            LineSlot::Failed(reason) => Some(reason.clone()),
+            _ => None,
+        }
+    }
+
+    fn release(&self, led: u8) -> Result<()> {
+        let _changing = self.slot_changes.lock().unwrap();
+        let slot = self.slot(led)?;
+        // Dropping the handle hands the line back to the kernel
+        if let LineSlot::Bulk(lines) = &**slot.load() {
+            self.release_from_bulk(led, lines);
+        }
+        slot.store(Arc::new(LineSlot::Released));
+        Ok(())
+    }
+
+    fn acquire(&self, led: u8) -> Result<()> {
+        let _changing = self.slot_changes.lock().unwrap();
+        let slot = self.slot(led)?;
+        if let LineSlot::Active(_) | LineSlot::Bulk(_) = **slot.load() {
+            return Ok(());
+        }
+        slot.store(Arc::new(LineSlot::Active(self.with_chip(led, |chip| Self::request_line(chip, &self.config, led, 0))?)));
+        Ok(())
+    }
+
+    fn line_info(&self, led: u8) -> Result<LineDiagnostics> {
+        let (chip, line) = self.config.gpio_line(led)?;
+        let info = self.with_chip(led, |chip| {
+            chip.get_line(u32::from(line))
+                .and_then(|line| line.info())
+                .map_err(|e| TrainError::GPIO(format!("Failed to read GPIO line {} info for LED {}: {}", line, led, e)))
+        })?;
+        let drive = if info.is_open_drain() {
+            "open-drain"
+        } else if info.is_open_source() {
+            "open-source"
+        } else {
+            "push-pull"
+        };
+        Ok(LineDiagnostics {
+            led,
+            chip: chip.to_string(),
+            line,
+            output: matches!(info.direction(), LineDirection::Out),
+            active_low: info.is_active_low(),
+            drive: drive.to_string(),
+            consumer: info.consumer().map(str::to_string),
+            warnings: Vec::new(),
+        }.check(&self.config.consumer))
+    }
+
+    fn is_released(&self, led: u8) -> bool {
+        self.slot(led)
+            .map(|slot| matches!(**slot.load(), LineSlot::Released))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_left_on_a_chip_are_requested_one_by_one_when_the_handle_fails() {
+        let remaining = [(2, 1), (3, 0), (4, 1)];
+        let busy = || Err::<(), _>(TrainError::DeviceBusy { pin: 6, holder: "\"other\"".into(), consumer: "train".into() });
+        let regrouped = regroup(&remaining, busy, |led, value| match led {
+            3 => Err(TrainError::GPIO("Line went away".into())),
+            _ => Ok((led, value)),
+        });
+        let Regrouped::Separately(results) = regrouped else {
+            panic!("expected single-line requests");
+        };
+        assert!(matches!(results[..], [(2, Ok((2, 1))), (3, Err(TrainError::GPIO(_))), (4, Ok((4, 1)))]));
+
+        let regrouped = regroup(&remaining, || Ok("handle"), |_, _| -> Result<()> { panic!("the handle was requested") });
+        assert!(matches!(regrouped, Regrouped::Together("handle")));
+    }
+}
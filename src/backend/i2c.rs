@@ -0,0 +1,92 @@
+use crate::error::Result;
+
+#[cfg(feature = "hardware")]
+pub use linux::LinuxI2cBus;
+
+/// Register writes to devices on one I2C bus
+/// Lets the expander backends be driven by a recording bus in tests
+pub trait I2cBus: Send {
+    /// Write `bytes` (register address first) to the device at `address` in one transaction
+    /// Fails with `DeviceNotFound` if nothing acknowledges the address
+    fn write(&mut self, address: u16, bytes: &[u8]) -> Result<()>;
+}
+
+#[cfg(feature = "hardware")]
+mod linux {
+    use super::I2cBus;
+    use crate::error::{Result, TrainError};
+    use i2cdev::core::I2CDevice;
+    use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
+    use nix::errno::Errno;
+    use std::collections::HashMap;
+    use std::collections::hash_map::Entry;
+    use std::path::PathBuf;
+
+    /// A Linux I2C bus (e.g. /dev/i2c-1), opening a device handle per address on first use
+    pub struct LinuxI2cBus {
+        path: PathBuf,
+        devices: HashMap<u16, LinuxI2CDevice>,
+    }
+
+    impl LinuxI2cBus {
+        pub fn new(path: impl Into<PathBuf>) -> Self {
+            Self { path: path.into(), devices: HashMap::new() }
+        }
+    }
+
+    impl I2cBus for LinuxI2cBus {
+        fn write(&mut self, address: u16, bytes: &[u8]) -> Result<()> {
+            let device = match self.devices.entry(address) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let device = LinuxI2CDevice::new(&self.path, address).map_err(|e| {
+                        TrainError::I2C(format!("Failed to open {} for device 0x{:02x}: {}", self.path.display(), address, e))
+                    })?;
+                    entry.insert(device)
+                }
+            };
+            device.write(bytes).map_err(|e| {
+                if no_ack(&e) {
+                    tracing::warn!(address = format!("0x{:02x}", address), "No I2C device acknowledged");
+                    TrainError::DeviceNotFound
+                } else {
+                    TrainError::I2C(format!("Write to device 0x{:02x} failed: {}", address, e))
+                }
+            })
+        }
+    }
+
+    /// Whether a write failed because no device acknowledged its address
+    fn no_ack(error: &LinuxI2CError) -> bool {
+        let errno = match error {
+            LinuxI2CError::Errno(errno) => Some(*errno),
+            LinuxI2CError::Io(e) => e.raw_os_error(),
+        };
+        errno.is_some_and(|errno| errno == Errno::ENXIO as i32 || errno == Errno::EREMOTEIO as i32)
+    }
+}
+
+/// Writes made through a `RecordingBus`, as (address, bytes)
+#[cfg(test)]
+type Writes = std::sync::Arc<std::sync::Mutex<Vec<(u16, Vec<u8>)>>>;
+
+/// Bus that records every write instead of sending it; clones share the record
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct RecordingBus(Writes);
+
+#[cfg(test)]
+impl RecordingBus {
+    /// Writes recorded since the last call
+    pub(crate) fn take(&self) -> Vec<(u16, Vec<u8>)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+impl I2cBus for RecordingBus {
+    fn write(&mut self, address: u16, bytes: &[u8]) -> Result<()> {
+        self.0.lock().unwrap().push((address, bytes.to_vec()));
+        Ok(())
+    }
+}
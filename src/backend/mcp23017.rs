@@ -0,0 +1,140 @@
+use super::i2c::I2cBus;
+#[cfg(feature = "hardware")]
+use super::i2c::LinuxI2cBus;
+use super::{check_led, LedBackend};
+use crate::error::{Result, TrainError};
+use crate::leds::LED_COUNT;
+#[cfg(feature = "hardware")]
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Port A direction register (IOCON.BANK = 0, the power-on default); port B's follows it
+const IODIRA: u8 = 0x00;
+/// Port A output latch; port B's follows it, so both can be written in one sequential transaction
+const OLATA: u8 = 0x14;
+/// Outputs per expander: two 8-bit ports
+const OUTPUTS_PER_CHIP: usize = 16;
+
+/// Bus and the last value latched on each port, as `[A, B]` per chip
+struct Expanders {
+    bus: Box<dyn I2cBus>,
+    latches: Vec<[u8; 2]>,
+}
+
+impl Expanders {
+    /// Write one or both ports of a chip; both go in a single transaction using register auto-increment
+    fn flush(&mut self, chip: usize, ports: [bool; 2], address: u16) -> Result<()> {
+        let [a, b] = self.latches[chip];
+        match ports {
+            [true, true] => self.bus.write(address, &[OLATA, a, b]),
+            [true, false] => self.bus.write(address, &[OLATA, a]),
+            [false, true] => self.bus.write(address, &[OLATA + 1, b]),
+            [false, false] => Ok(()),
+        }
+    }
+}
+
+/// Backend driving LEDs from MCP23017 16-bit I2C port expanders
+/// LED 1 is bit 0 of port A on the first address, LED 9 bit 0 of port B, LED 17 bit 0 of port A
+/// on the second address, and so on. Every pin is set as an output and driven low at construction.
+pub struct Mcp23017Backend {
+    addresses: Vec<u16>,
+    expanders: Mutex<Expanders>,
+}
+
+impl Mcp23017Backend {
+    /// Drive expanders at `addresses` (0x20-0x27) on the I2C bus at `bus_path`, e.g. /dev/i2c-1
+    /// Two expanders are needed for the 24 LEDs.
+    #[cfg(feature = "hardware")]
+    pub fn new(bus_path: impl Into<PathBuf>, addresses: &[u16]) -> Result<Self> {
+        Self::with_bus(Box::new(LinuxI2cBus::new(bus_path)), addresses)
+    }
+
+    /// Like `new()`, over any bus
+    pub fn with_bus(bus: Box<dyn I2cBus>, addresses: &[u16]) -> Result<Self> {
+        let needed = (LED_COUNT as usize).div_ceil(OUTPUTS_PER_CHIP);
+        if addresses.len() < needed {
+            return Err(TrainError::InvalidParameter(format!(
+                "{} LEDs need {} MCP23017 addresses, got {}", LED_COUNT, needed, addresses.len()
+            )));
+        }
+        if let Some(&address) = addresses.iter().find(|&&address| !(0x20..=0x27).contains(&address)) {
+            return Err(TrainError::InvalidParameter(format!("0x{:02x} is not an MCP23017 address (0x20-0x27)", address)));
+        }
+
+        let addresses = addresses[..needed].to_vec();
+        let mut expanders = Expanders { bus, latches: vec![[0, 0]; needed] };
+        for &address in &addresses {
+            // Latch low before switching the pins to outputs, so no LED flashes on
+            expanders.bus.write(address, &[OLATA, 0, 0])?;
+            expanders.bus.write(address, &[IODIRA, 0, 0])?;
+        }
+        Ok(Self { addresses, expanders: Mutex::new(expanders) })
+    }
+
+    /// Chip index, port (0 = A, 1 = B) and bit for an LED
+    fn locate(led: u8) -> (usize, usize, u8) {
+        let output = (led - 1) as usize;
+        let chip = output / OUTPUTS_PER_CHIP;
+        let port = output % OUTPUTS_PER_CHIP / 8;
+        (chip, port, (output % 8) as u8)
+    }
+}
+
+impl LedBackend for Mcp23017Backend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        self.set_many(&[(led, on)])
+    }
+
+    /// Updates the latches for every LED, then writes each changed chip once
+    fn set_many(&self, values: &[(u8, bool)]) -> Result<()> {
+        for &(led, _) in values {
+            check_led(led)?;
+        }
+        let mut expanders = self.expanders.lock().unwrap();
+        let mut dirty = vec![[false, false]; self.addresses.len()];
+        for &(led, on) in values {
+            let (chip, port, bit) = Self::locate(led);
+            let latch = &mut expanders.latches[chip][port];
+            if on {
+                *latch |= 1 << bit;
+            } else {
+                *latch &= !(1 << bit);
+            }
+            dirty[chip][port] = true;
+        }
+        for (chip, ports) in dirty.into_iter().enumerate() {
+            expanders.flush(chip, ports, self.addresses[chip])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::i2c::RecordingBus;
+
+    #[test]
+    fn batches_write_each_port_once() {
+        let bus = RecordingBus::default();
+        let backend = Mcp23017Backend::with_bus(Box::new(bus.clone()), &[0x20, 0x21]).unwrap();
+        assert_eq!(bus.take(), vec![
+            (0x20, vec![OLATA, 0, 0]), (0x20, vec![IODIRA, 0, 0]),
+            (0x21, vec![OLATA, 0, 0]), (0x21, vec![IODIRA, 0, 0]),
+        ]);
+
+        backend.set(10, true).unwrap();
+        backend.set_many(&[(1, true), (3, true), (9, true), (17, true)]).unwrap();
+        backend.set(3, false).unwrap();
+        assert_eq!(bus.take(), vec![
+            (0x20, vec![OLATA + 1, 0b10]),
+            (0x20, vec![OLATA, 0b101, 0b11]),
+            (0x21, vec![OLATA, 0b1]),
+            (0x20, vec![OLATA, 0b1]),
+        ]);
+
+        assert!(matches!(Mcp23017Backend::with_bus(Box::new(RecordingBus::default()), &[0x20]), Err(TrainError::InvalidParameter(_))));
+        assert!(matches!(backend.set(25, true), Err(TrainError::LedOutOfRange { .. })));
+    }
+}
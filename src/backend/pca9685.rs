@@ -0,0 +1,171 @@
+use super::i2c::I2cBus;
+#[cfg(feature = "hardware")]
+use super::i2c::LinuxI2cBus;
+use super::LedBackend;
+use crate::error::{Result, TrainError};
+use crate::leds::LED_COUNT;
+use std::ops::RangeInclusive;
+#[cfg(feature = "hardware")]
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const MODE1: u8 = 0x00;
+const MODE2: u8 = 0x01;
+/// First of the four ON_L, ON_H, OFF_L, OFF_H registers for channel 0; each channel's follow the last
+const LED0_ON_L: u8 = 0x06;
+/// ON/OFF registers shared by every channel
+const ALL_LED_ON_L: u8 = 0xFA;
+const PRE_SCALE: u8 = 0xFE;
+
+/// MODE1: low-power mode, needed to change the prescaler
+const SLEEP: u8 = 0x10;
+/// MODE1: register auto-increment, so a channel's four registers go in one write
+const AUTO_INCREMENT: u8 = 0x20;
+/// MODE2: totem-pole outputs, for driving LEDs directly
+const OUTDRV: u8 = 0x04;
+/// Bit 4 of ON_H or OFF_H: channel fully on or fully off, ignoring the counts
+const FULL: u8 = 0x10;
+
+/// Prescaler for a PWM frequency of about 1kHz from the 25MHz internal oscillator
+const PRESCALE_1KHZ: u8 = 5;
+/// Time for the oscillator to settle after leaving sleep
+const OSCILLATOR_SETTLE: Duration = Duration::from_micros(500);
+/// Channels on one chip
+const CHANNELS: usize = 16;
+
+/// Backend driving up to 16 LEDs from a PCA9685 12-bit PWM controller over I2C
+/// The LEDs in `leds` are channels 0 onwards, in order. Unlike software PWM, brightness set with
+/// `set_level()` is steady and costs nothing after the write. Combine with `CompositeBackend`
+/// to keep the other LEDs on GPIO.
+pub struct Pca9685Backend {
+    address: u16,
+    leds: RangeInclusive<u8>,
+    bus: Mutex<Box<dyn I2cBus>>,
+}
+
+impl Pca9685Backend {
+    /// Drive the LEDs in `leds` from the PCA9685 at `address` (0x40-0x7f) on the I2C bus at `bus_path`
+    /// Every channel starts fully off. Fails with `DeviceNotFound` if the chip doesn't acknowledge.
+    #[cfg(feature = "hardware")]
+    pub fn new(bus_path: impl Into<PathBuf>, address: u16, leds: RangeInclusive<u8>) -> Result<Self> {
+        Self::with_bus(Box::new(LinuxI2cBus::new(bus_path)), address, leds)
+    }
+
+    /// Like `new()`, over any bus
+    pub fn with_bus(mut bus: Box<dyn I2cBus>, address: u16, leds: RangeInclusive<u8>) -> Result<Self> {
+        if !(0x40..=0x7f).contains(&address) {
+            return Err(TrainError::InvalidParameter(format!("0x{:02x} is not a PCA9685 address (0x40-0x7f)", address)));
+        }
+        if leds.is_empty() || *leds.start() < 1 || *leds.end() > LED_COUNT || leds.len() > CHANNELS {
+            return Err(TrainError::InvalidParameter(format!(
+                "A PCA9685 drives 1-{} of LEDs 1-{}, got {}-{}", CHANNELS, LED_COUNT, leds.start(), leds.end()
+            )));
+        }
+
+        bus.write(address, &[MODE1, SLEEP | AUTO_INCREMENT])?;
+        bus.write(address, &[PRE_SCALE, PRESCALE_1KHZ])?;
+        bus.write(address, &[MODE2, OUTDRV])?;
+        bus.write(address, &[ALL_LED_ON_L, 0, 0, 0, FULL])?;
+        bus.write(address, &[MODE1, AUTO_INCREMENT])?;
+        std::thread::sleep(OSCILLATOR_SETTLE);
+
+        Ok(Self { address, leds, bus: Mutex::new(bus) })
+    }
+
+    /// First register of an LED's channel
+    fn register(&self, led: u8) -> Result<u8> {
+        if !(1..=LED_COUNT).contains(&led) {
+            return Err(TrainError::LedOutOfRange { led, max: LED_COUNT });
+        }
+        match self.failure(led) {
+            Some(reason) => Err(TrainError::LedUnavailable(led, reason)),
+            None => Ok(LED0_ON_L + 4 * (led - self.leds.start())),
+        }
+    }
+
+    /// Write a channel's ON_L, ON_H, OFF_L and OFF_H registers
+    fn write_channel(&self, led: u8, counts: [u8; 4]) -> Result<()> {
+        let register = self.register(led)?;
+        let [on_l, on_h, off_l, off_h] = counts;
+        self.bus.lock().unwrap().write(self.address, &[register, on_l, on_h, off_l, off_h])
+    }
+}
+
+impl LedBackend for Pca9685Backend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        self.set_level(led, if on { u8::MAX } else { 0 })
+    }
+
+    fn failure(&self, led: u8) -> Option<String> {
+        (!self.leds.contains(&led)).then(|| format!("Not wired to the PCA9685 at 0x{:02x}", self.address))
+    }
+
+    fn has_pwm(&self, led: u8) -> bool {
+        self.leds.contains(&led)
+    }
+
+    /// Levels map linearly onto the 12-bit duty cycle; 0 and 255 use the full-off and full-on bits
+    fn set_level(&self, led: u8, level: u8) -> Result<()> {
+        let counts = match level {
+            0 => [0, 0, 0, FULL],
+            u8::MAX => [0, FULL, 0, 0],
+            _ => {
+                let off = (u32::from(level) * 4095 + 127) / 255;
+                [0, 0, (off & 0xff) as u8, (off >> 8) as u8]
+            }
+        };
+        self.write_channel(led, counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::i2c::RecordingBus;
+    use crate::backend::{CompositeBackend, MockBackend};
+    use crate::leds::{Led, LedController, LedState};
+    use std::sync::Arc;
+
+    #[test]
+    fn levels_use_the_full_bits_at_the_extremes() {
+        let bus = RecordingBus::default();
+        let backend = Pca9685Backend::with_bus(Box::new(bus.clone()), 0x40, 13..=24).unwrap();
+        assert_eq!(bus.take().last(), Some(&(0x40, vec![MODE1, AUTO_INCREMENT])));
+
+        backend.set(13, true).unwrap();
+        backend.set_level(14, 128).unwrap();
+        backend.set(24, false).unwrap();
+        assert_eq!(bus.take(), vec![
+            (0x40, vec![LED0_ON_L, 0, FULL, 0, 0]),
+            (0x40, vec![LED0_ON_L + 4, 0, 0, 0x08, 0x08]),
+            (0x40, vec![LED0_ON_L + 44, 0, 0, 0, FULL]),
+        ]);
+
+        assert!(backend.has_pwm(13) && !backend.has_pwm(12));
+        assert!(matches!(backend.set(1, true), Err(TrainError::LedUnavailable(1, _))));
+        assert!(Pca9685Backend::with_bus(Box::new(bus), 0x40, 1..=17).is_err());
+    }
+
+    #[tokio::test]
+    async fn controller_dims_pca_leds_in_hardware() {
+        let bus = RecordingBus::default();
+        let pca = Pca9685Backend::with_bus(Box::new(bus.clone()), 0x40, 13..=24).unwrap();
+        let gpio = Arc::new(MockBackend::new());
+        let backend = CompositeBackend::new(gpio.clone()).with_range(13..=24, Arc::new(pca)).unwrap();
+        let leds = LedController::with_backend(Arc::new(backend));
+        bus.take();
+
+        leds.set_brightness(Led::new(13).unwrap(), 128).await.unwrap();
+        assert_eq!(leds.state(Led::new(13).unwrap()).await, LedState::Dimmed { level: 128 });
+        leds.set_brightness(Led::new(1).unwrap(), 128).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        leds.all_off().await.unwrap();
+
+        let writes = bus.take();
+        assert_eq!(writes[0], (0x40, vec![LED0_ON_L, 0, 0, 0x08, 0x08]));
+        assert_eq!(writes.len(), 1 + 12, "one level write, then all_off turns each channel off");
+        assert!(gpio.writes().iter().all(|&(led, _)| led <= 12));
+        assert!(gpio.writes().contains(&(1, true)), "GPIO LEDs still dim in software");
+    }
+}
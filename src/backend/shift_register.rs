@@ -0,0 +1,255 @@
+use super::{check_led, LedBackend};
+use crate::error::{Result, TrainError};
+use crate::leds::LED_COUNT;
+use std::sync::Mutex;
+
+/// Order in which each register's bits are clocked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Bit 7 first, so bit n lands on output Qn
+    MsbFirst,
+    /// Bit 0 first, for boards wired with Q7 as the first LED
+    LsbFirst,
+}
+
+/// The three control lines of a 74HC595 chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShiftLine {
+    /// Serial data in (DS)
+    Data,
+    /// Shift clock (SHCP); data is sampled on the rising edge
+    Clock,
+    /// Storage latch (STCP); the outputs update on the rising edge
+    Latch,
+}
+
+/// Drives the control lines; lets the bitstream be captured in tests
+pub trait ShiftLines: Send {
+    fn set(&mut self, line: ShiftLine, high: bool) -> Result<()>;
+}
+
+#[cfg(feature = "hardware")]
+mod gpio_lines {
+    use super::{ShiftLine, ShiftLines};
+    use crate::error::{Result, TrainError};
+    use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+
+    /// GPIO label for the shift register's control lines
+    const CONSUMER: &str = "train-shift";
+
+    /// Control lines requested from a GPIO chip
+    pub(super) struct GpioShiftLines {
+        data: LineHandle,
+        clock: LineHandle,
+        latch: LineHandle,
+    }
+
+    impl GpioShiftLines {
+        /// Request the three lines of the chip at `chip_path` as outputs, initially low
+        pub(super) fn request(chip_path: &str, data: u8, clock: u8, latch: u8) -> Result<Self> {
+            let mut chip = Chip::new(chip_path)
+                .map_err(|e| TrainError::GPIO(format!("Failed to open GPIO chip {}: {}", chip_path, e)))?;
+            let mut request = |pin: u8| {
+                chip.get_line(u32::from(pin))
+                    .and_then(|line| line.request(LineRequestFlags::OUTPUT, 0, CONSUMER))
+                    .map_err(|e| TrainError::GPIO(format!("Failed to request GPIO line {} of {}: {}", pin, chip_path, e)))
+            };
+            Ok(Self { data: request(data)?, clock: request(clock)?, latch: request(latch)? })
+        }
+    }
+
+    impl ShiftLines for GpioShiftLines {
+        fn set(&mut self, line: ShiftLine, high: bool) -> Result<()> {
+            let handle = match line {
+                ShiftLine::Data => &self.data,
+                ShiftLine::Clock => &self.clock,
+                ShiftLine::Latch => &self.latch,
+            };
+            handle.set_value(u8::from(high))
+                .map_err(|e| TrainError::GPIO(format!("Failed to drive shift register {:?} line: {}", line, e)))
+        }
+    }
+}
+
+/// Output bits and the lines to clock them out on
+struct Chain {
+    lines: Box<dyn ShiftLines>,
+    /// One byte per register; register 0 is nearest the Pi and drives LEDs 1-8
+    bits: Vec<u8>,
+}
+
+/// Backend driving LEDs from daisy-chained 74HC595 shift registers on three GPIO lines
+/// LED n is bit (n - 1) % 8 of register (n - 1) / 8. Every change clocks the whole chain out, so
+/// a single-LED write costs as much as a full frame: prefer batch writes (`set_many()`).
+pub struct ShiftRegisterBackend {
+    order: BitOrder,
+    chain: Mutex<Chain>,
+}
+
+impl ShiftRegisterBackend {
+    /// Drive `registers` chained 74HC595s from the `data`, `clock` and `latch` lines of the GPIO chip
+    /// at `chip_path`. All outputs start off.
+    #[cfg(feature = "hardware")]
+    pub fn new(chip_path: &str, data: u8, clock: u8, latch: u8, registers: usize, order: BitOrder) -> Result<Self> {
+        let lines = gpio_lines::GpioShiftLines::request(chip_path, data, clock, latch)?;
+        Self::with_lines(Box::new(lines), registers, order)
+    }
+
+    /// Like `new()`, over any control lines
+    pub fn with_lines(lines: Box<dyn ShiftLines>, registers: usize, order: BitOrder) -> Result<Self> {
+        if registers * 8 < LED_COUNT as usize {
+            return Err(TrainError::InvalidParameter(format!(
+                "{} LEDs need at least {} shift registers, got {}", LED_COUNT, (LED_COUNT as usize).div_ceil(8), registers
+            )));
+        }
+        let mut chain = Chain { lines, bits: vec![0; registers] };
+        Self::shift_out(&mut chain, order)?;
+        Ok(Self { order, chain: Mutex::new(chain) })
+    }
+
+    /// Clock every register out, farthest first, then latch the outputs
+    fn shift_out(chain: &mut Chain, order: BitOrder) -> Result<()> {
+        let lines = &mut chain.lines;
+        lines.set(ShiftLine::Latch, false)?;
+        for &byte in chain.bits.iter().rev() {
+            for i in 0..8 {
+                let bit = match order {
+                    BitOrder::MsbFirst => 7 - i,
+                    BitOrder::LsbFirst => i,
+                };
+                lines.set(ShiftLine::Data, byte & (1 << bit) != 0)?;
+                lines.set(ShiftLine::Clock, true)?;
+                lines.set(ShiftLine::Clock, false)?;
+            }
+        }
+        lines.set(ShiftLine::Latch, true)
+    }
+}
+
+impl LedBackend for ShiftRegisterBackend {
+    fn set(&self, led: u8, on: bool) -> Result<()> {
+        self.set_many(&[(led, on)])
+    }
+
+    /// Updates every bit, then clocks the chain out once (not at all if nothing changed)
+    /// If clocking fails the bits are put back, so a retry clocks the chain again
+    fn set_many(&self, values: &[(u8, bool)]) -> Result<()> {
+        for &(led, _) in values {
+            check_led(led)?;
+        }
+        let mut chain = self.chain.lock().unwrap();
+        let before = chain.bits.clone();
+        for &(led, on) in values {
+            let (register, bit) = (((led - 1) / 8) as usize, (led - 1) % 8);
+            if on {
+                chain.bits[register] |= 1 << bit;
+            } else {
+                chain.bits[register] &= !(1 << bit);
+            }
+        }
+        if chain.bits == before {
+            return Ok(());
+        }
+        let result = Self::shift_out(&mut chain, self.order);
+        if result.is_err() {
+            chain.bits = before;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Simulated 74HC595 chain: samples data on each clock rising edge and copies the shift
+    /// register to the outputs on each latch rising edge
+    #[derive(Clone, Default)]
+    struct MockChain(Arc<Mutex<MockState>>);
+
+    #[derive(Default)]
+    struct MockState {
+        data: bool,
+        clock: bool,
+        latch: bool,
+        /// Bits clocked in since the last latch, first bit first
+        shifted: Vec<bool>,
+        latched: Vec<Vec<bool>>,
+        /// Line writes left before every write fails, if set
+        failing_after: Option<usize>,
+    }
+
+    impl ShiftLines for MockChain {
+        fn set(&mut self, line: ShiftLine, high: bool) -> Result<()> {
+            let mut state = self.0.lock().unwrap();
+            match state.failing_after {
+                Some(0) => return Err(TrainError::GPIO("Mock line write failed".to_string())),
+                Some(ref mut left) => *left -= 1,
+                None => {}
+            }
+            match line {
+                ShiftLine::Data => state.data = high,
+                ShiftLine::Clock => {
+                    if high && !state.clock {
+                        let bit = state.data;
+                        state.shifted.push(bit);
+                    }
+                    state.clock = high;
+                }
+                ShiftLine::Latch => {
+                    if high && !state.latch {
+                        let bits = std::mem::take(&mut state.shifted);
+                        state.latched.push(bits);
+                    }
+                    state.latch = high;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Bitstream as a string of 0s and 1s, split into bytes
+    fn pattern(bits: &[bool]) -> String {
+        bits.chunks(8)
+            .map(|byte| byte.iter().map(|&bit| if bit { '1' } else { '0' }).collect::<String>())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn clocks_the_whole_chain_in_the_configured_bit_order() {
+        let chain = MockChain::default();
+        let backend = ShiftRegisterBackend::with_lines(Box::new(chain.clone()), 3, BitOrder::MsbFirst).unwrap();
+        backend.set_many(&[(1, true), (10, true), (24, true)]).unwrap();
+        backend.set(10, true).unwrap();
+        let latched = std::mem::take(&mut chain.0.lock().unwrap().latched);
+        assert_eq!(latched.len(), 2, "initial clear, then one frame; the repeated write is skipped");
+        assert_eq!(pattern(&latched[1]), "10000000 00000010 00000001");
+
+        let chain = MockChain::default();
+        let backend = ShiftRegisterBackend::with_lines(Box::new(chain.clone()), 3, BitOrder::LsbFirst).unwrap();
+        backend.set(1, true).unwrap();
+        assert_eq!(pattern(chain.0.lock().unwrap().latched.last().unwrap()), "00000000 00000000 10000000");
+
+        assert!(ShiftRegisterBackend::with_lines(Box::new(MockChain::default()), 2, BitOrder::MsbFirst).is_err());
+    }
+
+    #[test]
+    fn a_write_that_fails_mid_chain_is_clocked_again_on_retry() {
+        let chain = MockChain::default();
+        let backend = ShiftRegisterBackend::with_lines(Box::new(chain.clone()), 3, BitOrder::MsbFirst).unwrap();
+        chain.0.lock().unwrap().failing_after = Some(10);
+        assert!(backend.set(1, true).is_err());
+        assert_eq!(chain.0.lock().unwrap().latched.len(), 1, "only the initial clear was latched");
+
+        let mut state = chain.0.lock().unwrap();
+        state.failing_after = None;
+        state.shifted.clear();
+        drop(state);
+        backend.set(1, true).unwrap();
+        let latched = std::mem::take(&mut chain.0.lock().unwrap().latched);
+        assert_eq!(latched.len(), 2);
+        assert_eq!(pattern(&latched[1]), "00000000 00000000 00000001");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::backend::LedBackend;
+use crate::error::{Result, TrainError};
+use crate::leds::{Led, LedController};
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
+
+/// Synchronous wrapper around `LedController`, for scripts and FFI callers without an async runtime
+/// Runs the controller on an internal Tokio runtime; blinks keep going in the background between
+/// calls, until the wrapper is dropped.
+///
+/// ```ignore
+/// let leds = BlockingLedController::new()?;
+/// leds.blink(5, 500)?;
+/// ```
+pub struct BlockingLedController {
+    controller: LedController,
+    runtime: Runtime,
+}
+
+impl BlockingLedController {
+    /// Drive the LEDs from GPIO, like `LedController::new()`
+    pub fn new() -> Result<Self> {
+        Self::build(LedController::new)
+    }
+
+    /// Drive the LEDs through `backend`, like `LedController::with_backend()`
+    pub fn with_backend(backend: Arc<dyn LedBackend>) -> Result<Self> {
+        Self::build(|| Ok(LedController::with_backend(backend)))
+    }
+
+    /// Start the runtime, then create the controller inside it
+    fn build(controller: impl FnOnce() -> Result<LedController>) -> Result<Self> {
+        // One worker keeps effect tasks running while the caller isn't inside a call
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("train-leds")
+            .enable_all()
+            .build()
+            .map_err(|e| TrainError::Hardware(format!("Failed to start the LED runtime: {}", e)))?;
+        let controller = {
+            let _guard = runtime.enter();
+            controller()?
+        };
+        Ok(Self { controller, runtime })
+    }
+
+    /// The wrapped controller, for operations without a blocking version; run them with `block_on()`
+    pub fn controller(&self) -> &LedController {
+        &self.controller
+    }
+
+    /// Run a future on the internal runtime, waiting for it to finish
+    pub fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn on(&self, led: u8) -> Result<()> {
+        self.block_on(self.controller.on(Led::new(led)?))
+    }
+
+    pub fn off(&self, led: u8) -> Result<()> {
+        self.block_on(self.controller.off(Led::new(led)?))
+    }
+
+    /// Start blinking and return at once; the blink runs until changed or the wrapper is dropped
+    pub fn blink(&self, led: u8, frequency_ms: u64) -> Result<()> {
+        self.block_on(self.controller.blink(Led::new(led)?, frequency_ms))
+    }
+
+    pub fn all_off(&self) -> Result<()> {
+        self.block_on(self.controller.all_off())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::leds::LedState;
+    use std::time::Duration;
+
+    #[test]
+    fn blinks_run_between_blocking_calls() {
+        let backend = Arc::new(MockBackend::new());
+        let leds = BlockingLedController::with_backend(backend.clone()).unwrap();
+
+        leds.on(1).unwrap();
+        leds.blink(2, 50).unwrap();
+        std::thread::sleep(Duration::from_millis(180));
+        let toggles = backend.writes().iter().filter(|&&(led, _)| led == 2).count();
+        assert!(toggles >= 3, "blink kept toggling while no call was running, got {}", toggles);
+        assert_eq!(leds.block_on(leds.controller().state(Led::new(1).unwrap())), LedState::On);
+
+        leds.all_off().unwrap();
+        assert!(matches!(leds.off(25), Err(TrainError::LedOutOfRange { .. })));
+    }
+}
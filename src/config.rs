@@ -0,0 +1,276 @@
+use crate::error::{Result, TrainError};
+use crate::leds::{LedState, LED_COUNT};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// GPIO line driving a single LED
+/// In a pin map this is either a bare line number on the default chip (`4`)
+/// or a line on another chip (`{ "chip": "/dev/gpiochip1", "line": 4 }`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LedPin {
+    /// Line on `LedConfig::chip`
+    Line(u8),
+    /// Line on the given chip
+    OnChip { chip: String, line: u8 },
+}
+
+impl From<u8> for LedPin {
+    fn from(line: u8) -> Self {
+        LedPin::Line(line)
+    }
+}
+
+/// Hardware configuration for the LED controller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LedConfig {
+    /// GPIO chip device path, for pins that don't name their own chip
+    pub chip: String,
+    /// Consumer label the LED lines are requested under, as shown by `gpioinfo` (at most 31 bytes)
+    pub consumer: String,
+    /// GPIO line for each LED, in LED order (index 0 = LED 1)
+    /// Lines may be spread over several chips; each chip is opened once
+    pub pins: Vec<LedPin>,
+    /// Fail construction if any LED's line can't be requested
+    pub strict: bool,
+    /// Print each LED write to stderr instead of driving GPIO (no chip is opened)
+    pub dry_run: bool,
+    /// Minimum interval between effect-driven events (e.g. blink toggles) per LED, in milliseconds
+    /// Direct commands are always published immediately
+    pub event_coalesce_ms: u64,
+    /// Publish effect-driven events (e.g. each blink toggle) at all
+    /// Turn off when subscribers only care about commands; effects that stop on an error are still reported
+    pub effect_events: bool,
+    /// Shortest allowed blink interval in milliseconds
+    pub min_blink_ms: u64,
+    /// Longest allowed blink interval in milliseconds
+    pub max_blink_ms: u64,
+    /// How long a single GPIO write may take before failing with `Timeout`, in milliseconds
+    pub gpio_timeout_ms: u64,
+    /// Background effect tasks (blinks, PWM, patterns, animations, auto-off timers) allowed at once
+    /// A blink shared by several LEDs counts once
+    pub max_effects: usize,
+    /// Named sets of LEDs addressed as a unit, e.g. `platform1 = [1, 2, 7]`
+    pub zones: HashMap<String, Vec<u8>>,
+    /// State of each LED at startup, in LED order (index 0 = LED 1); LEDs past the end start off
+    /// A saved state restored by `LedController::with_persistence()` takes precedence over this.
+    pub initial: Vec<LedState>,
+}
+
+impl Default for LedConfig {
+    /// LEDs 1-24 on GPIO pins 4-27 of /dev/gpiochip0
+    fn default() -> Self {
+        Self {
+            chip: "/dev/gpiochip0".to_string(),
+            consumer: "train-led".to_string(),
+            pins: (1..=LED_COUNT).map(|led| LedPin::Line(led + 3)).collect(),
+            strict: true,
+            dry_run: false,
+            event_coalesce_ms: 100,
+            effect_events: true,
+            min_blink_ms: 20,
+            max_blink_ms: 60_000,
+            gpio_timeout_ms: 500,
+            max_effects: 32,
+            zones: HashMap::new(),
+            initial: Vec::new(),
+        }
+    }
+}
+
+impl LedConfig {
+    /// Check the pin map covers every LED exactly once
+    pub fn validate(&self) -> Result<()> {
+        if self.pins.len() != LED_COUNT as usize {
+            return Err(TrainError::InvalidParameter(
+                format!("Pin map must have {} entries, got {}", LED_COUNT, self.pins.len())
+            ));
+        }
+        if self.min_blink_ms == 0 || self.min_blink_ms > self.max_blink_ms {
+            return Err(TrainError::InvalidParameter(
+                format!("Blink limits must satisfy 0 < min ({}) <= max ({})", self.min_blink_ms, self.max_blink_ms)
+            ));
+        }
+        if self.initial.len() > LED_COUNT as usize {
+            return Err(TrainError::InvalidParameter(
+                format!("Initial states are given for {} LEDs, but there are only {}", self.initial.len(), LED_COUNT)
+            ));
+        }
+        if self.max_effects == 0 {
+            return Err(TrainError::InvalidParameter("max_effects must be at least 1".to_string()));
+        }
+        if self.consumer.is_empty() || self.consumer.len() > 31 {
+            return Err(TrainError::InvalidParameter(
+                format!("GPIO consumer label must be 1-31 bytes, got \"{}\"", self.consumer)
+            ));
+        }
+        for (name, members) in &self.zones {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(TrainError::InvalidParameter(
+                    format!("Zone name '{}' must be non-empty and contain only letters, digits, '-' or '_'", name)
+                ));
+            }
+            if members.is_empty() {
+                return Err(TrainError::InvalidParameter(format!("Zone '{}' has no LEDs", name)));
+            }
+            if let Some(led) = members.iter().find(|led| !(1..=LED_COUNT).contains(*led)) {
+                return Err(TrainError::InvalidParameter(
+                    format!("Zone '{}' lists LED {}, but LEDs are numbered 1-{}", name, led, LED_COUNT)
+                ));
+            }
+        }
+        let lines: Vec<(&str, u8)> = self.pins.iter().map(|pin| self.resolve(pin)).collect();
+        for (i, (chip, line)) in lines.iter().enumerate() {
+            if chip.is_empty() {
+                return Err(TrainError::InvalidParameter(format!("GPIO chip path for LED {} is empty", i + 1)));
+            }
+            if lines[..i].contains(&(chip, *line)) {
+                return Err(TrainError::InvalidParameter(
+                    format!("GPIO line {} of {} is assigned to more than one LED", line, chip)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps LED number (1-24) to its GPIO pin (the line offset within its chip)
+    pub fn gpio_pin(&self, led: u8) -> Result<u8> {
+        self.gpio_line(led).map(|(_, line)| line)
+    }
+
+    /// Maps LED number (1-24) to its chip path and line offset
+    pub fn gpio_line(&self, led: u8) -> Result<(&str, u8)> {
+        if !(1..=LED_COUNT).contains(&led) {
+            return Err(TrainError::LedOutOfRange { led, max: LED_COUNT });
+        }
+        self.pins.get(led as usize - 1)
+            .map(|pin| self.resolve(pin))
+            .ok_or_else(|| TrainError::InvalidParameter(format!("No GPIO pin configured for LED {}", led)))
+    }
+
+    /// Distinct chips used by the pin map, in order of first use
+    pub fn chips(&self) -> Vec<&str> {
+        let mut chips: Vec<&str> = Vec::new();
+        for pin in &self.pins {
+            let (chip, _) = self.resolve(pin);
+            if !chips.contains(&chip) {
+                chips.push(chip);
+            }
+        }
+        chips
+    }
+
+    fn resolve<'a>(&'a self, pin: &'a LedPin) -> (&'a str, u8) {
+        match pin {
+            LedPin::Line(line) => (&self.chip, *line),
+            LedPin::OnChip { chip, line } => (chip, *line),
+        }
+    }
+}
+
+/// Settings for `train server`, loaded from a TOML or JSON file with `--config`
+/// Every field is optional; command-line flags override values from the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to bind to
+    pub host: String,
+    pub port: u16,
+    /// Bearer token required on POST/PUT/DELETE requests
+    pub api_token: Option<String>,
+    /// File the LED state is saved to and restored from
+    pub state_file: Option<PathBuf>,
+    /// Requests per second allowed from each client IP (0 disables rate limiting)
+    pub rate_limit_rps: u32,
+    /// Fail to start if any LED's GPIO line can't be requested (overrides `leds.strict`)
+    pub strict: bool,
+    pub compression: bool,
+    pub http2: bool,
+    /// Largest request body accepted, in bytes
+    pub body_limit: usize,
+    /// Connections served at once; more are answered with 503
+    pub max_connections: usize,
+    /// Chip, pin map and timing for the LEDs
+    pub leds: LedConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            api_token: None,
+            state_file: None,
+            rate_limit_rps: 20,
+            strict: false,
+            compression: false,
+            http2: false,
+            body_limit: crate::server::DEFAULT_BODY_LIMIT,
+            max_connections: crate::server::DEFAULT_MAX_CONNECTIONS,
+            leds: LedConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read a config file: TOML if its name ends in `.toml`, JSON otherwise
+    /// The LED settings are validated, so a bad pin map is reported against the file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let error = |reason: String| TrainError::Config { path: path.display().to_string(), reason };
+
+        let text = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+        let config: Self = if path.extension().is_some_and(|extension| extension == "toml") {
+            toml::from_str(&text).map_err(|e| error(e.to_string()))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| error(e.to_string()))?
+        };
+        config.leds.validate().map_err(|e| error(e.to_string()))?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_pins_cover_leds_1_to_24_only() {
+        let config = LedConfig::default();
+        assert_eq!(config.gpio_pin(1).unwrap(), 4);
+        assert_eq!(config.gpio_pin(24).unwrap(), 27);
+        for led in [0, 25] {
+            assert!(matches!(config.gpio_pin(led), Err(TrainError::LedOutOfRange { max: 24, .. })), "LED {}", led);
+        }
+    }
+
+    #[test]
+    fn server_config_loads_toml_and_reports_bad_files() {
+        let path = std::env::temp_dir().join(format!("train-config-{}.toml", std::process::id()));
+        let pins: Vec<String> = (5..=27).map(|pin| pin.to_string()).collect();
+        std::fs::write(&path, format!(
+            "port = 9000\napi_token = \"secret\"\n\n[leds]\npins = [{}, {{ chip = \"/dev/gpiochip1\", line = 3 }}]\n",
+            pins.join(", ")
+        )).unwrap();
+        let config = ServerConfig::load(&path).unwrap();
+        assert_eq!((config.port, config.host.as_str(), config.api_token.as_deref()), (9000, "0.0.0.0", Some("secret")));
+        assert_eq!(config.leds.gpio_line(24).unwrap(), ("/dev/gpiochip1", 3));
+
+        std::fs::write(&path, "[leds]\ninitial = [{ state = \"on\" }, { state = \"blinking\", frequency_ms = 500 }]\n").unwrap();
+        assert_eq!(ServerConfig::load(&path).unwrap().leds.initial, vec![LedState::On, LedState::Blinking { frequency_ms: 500 }]);
+
+        std::fs::write(&path, "[leds.zones]\nplatform1 = [1, 2, 7]\n").unwrap();
+        assert_eq!(ServerConfig::load(&path).unwrap().leds.zones["platform1"], vec![1, 2, 7]);
+        std::fs::write(&path, "[leds.zones]\nplatform1 = [1, 25]\n").unwrap();
+        let error = ServerConfig::load(&path).unwrap_err();
+        assert!(error.to_string().contains("Zone 'platform1' lists LED 25"), "{}", error);
+
+        std::fs::write(&path, "port = \"eighty\"\n").unwrap();
+        let error = ServerConfig::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, TrainError::Config { .. }));
+        assert!(error.to_string().contains("port"));
+    }
+}
@@ -14,6 +14,49 @@ pub enum TrainError {
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
 
+    #[error("LED {led} does not exist (LEDs are numbered 1-{max})")]
+    LedOutOfRange { led: u8, max: u8 },
+
+    #[error("Position {position} is out of range (1-{max})")]
+    PositionOutOfRange { position: u8, max: u8 },
+
+    #[error("Invalid blink frequency {frequency_ms}ms (allowed range {min_ms}-{max_ms}ms)")]
+    InvalidFrequency { frequency_ms: u64, min_ms: u64, max_ms: u64 },
+
+    #[error("LED {0} is not blinking")]
+    BlinkNotActive(u8),
+
+    #[error("LED {0} is unavailable: {1}")]
+    LedUnavailable(u8, String),
+
+    #[error("LED {0} has been released and must be acquired before use")]
+    LedReleased(u8),
+
+    #[error("Operation timed out: {0}")]
+    Timeout(String),
+
+    /// From `LedController::wait_for()`
+    #[error("LED {led} didn't reach the requested state within {waited_ms}ms")]
+    StateWaitTimeout { led: u8, waited_ms: u64 },
+
+    /// Starting another background effect would exceed `LedConfig::max_effects`
+    #[error("Too many background effects running (limit {max}); stop one first")]
+    TooManyEffects { max: usize },
+
+    #[error("Busy: {0}")]
+    Busy(String),
+
+    /// `holder` names the line's current consumer when the kernel reports one
+    #[error("GPIO pin {pin} is already in use by {holder}; run `gpioinfo` to see which process holds it (this program's lines are labelled \"{consumer}\")")]
+    DeviceBusy { pin: u8, holder: String, consumer: String },
+
+    /// Every LED in a bulk operation was tried; these (LED, reason) pairs failed
+    #[error("{} LED(s) failed: {}", .0.len(), describe_failures(.0))]
+    PartialFailure(Vec<(u8, String)>),
+
+    #[error("Invalid config file {path}: {reason}")]
+    Config { path: String, reason: String },
+
     #[error("Device not found or not responding")]
     DeviceNotFound,
 
@@ -23,8 +66,77 @@ pub enum TrainError {
 
 pub type Result<T> = std::result::Result<T, TrainError>;
 
+/// "LED 3 (reason), LED 5 (reason)"
+fn describe_failures(failures: &[(u8, String)]) -> String {
+    failures.iter()
+        .map(|(led, reason)| format!("LED {} ({})", led, reason))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(feature = "hardware")]
 impl From<gpio_cdev::Error> for TrainError {
     fn from(err: gpio_cdev::Error) -> Self {
         TrainError::GPIO(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_variant_displays_its_message() {
+        let cases = [
+            (TrainError::Hardware("foo".into()), "Hardware interface error: foo"),
+            (TrainError::I2C("foo".into()), "I2C communication error: foo"),
+            (TrainError::GPIO("foo".into()), "GPIO error: foo"),
+            (TrainError::InvalidParameter("foo".into()), "Invalid parameter: foo"),
+            (TrainError::LedOutOfRange { led: 25, max: 24 }, "LED 25 does not exist (LEDs are numbered 1-24)"),
+            (TrainError::PositionOutOfRange { position: 7, max: 6 }, "Position 7 is out of range (1-6)"),
+            (
+                TrainError::InvalidFrequency { frequency_ms: 5, min_ms: 10, max_ms: 10000 },
+                "Invalid blink frequency 5ms (allowed range 10-10000ms)",
+            ),
+            (TrainError::BlinkNotActive(3), "LED 3 is not blinking"),
+            (TrainError::LedUnavailable(4, "line busy".into()), "LED 4 is unavailable: line busy"),
+            (TrainError::LedReleased(5), "LED 5 has been released and must be acquired before use"),
+            (TrainError::Timeout("foo".into()), "Operation timed out: foo"),
+            (
+                TrainError::StateWaitTimeout { led: 7, waited_ms: 500 },
+                "LED 7 didn't reach the requested state within 500ms",
+            ),
+            (
+                TrainError::TooManyEffects { max: 32 },
+                "Too many background effects running (limit 32); stop one first",
+            ),
+            (TrainError::Busy("foo".into()), "Busy: foo"),
+            (
+                TrainError::DeviceBusy { pin: 17, holder: "\"other\"".into(), consumer: "train".into() },
+                "GPIO pin 17 is already in use by \"other\"; run `gpioinfo` to see which process holds it (this program's lines are labelled \"train\")",
+            ),
+            (
+                TrainError::PartialFailure(vec![(3, "timeout".into()), (5, "busy".into())]),
+                "2 LED(s) failed: LED 3 (timeout), LED 5 (busy)",
+            ),
+            (
+                TrainError::Config { path: "train.toml".into(), reason: "bad port".into() },
+                "Invalid config file train.toml: bad port",
+            ),
+            (TrainError::DeviceNotFound, "Device not found or not responding"),
+            (TrainError::NotSupported, "Operation not supported"),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[cfg(feature = "hardware")]
+    #[test]
+    fn gpio_cdev_errors_become_gpio_errors() {
+        let cause = gpio_cdev::Error::from(std::io::Error::other("line request failed"));
+        let error = TrainError::from(cause);
+        assert!(matches!(&error, TrainError::GPIO(message) if message == "line request failed"));
+        assert_eq!(error.to_string(), "GPIO error: line request failed");
+    }
+}
@@ -0,0 +1,93 @@
+use crate::leds::{Led, LedState};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
+
+/// Number of events a subscriber can fall behind before it starts missing them
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// A change to a single LED, published to `LedController::subscribe()` receivers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LedEvent {
+    pub led: Led,
+    /// Commanded state before the change
+    pub old_state: LedState,
+    /// Commanded state after the change
+    pub new_state: LedState,
+    /// Whether the LED is physically lit after the change
+    pub lit: bool,
+    /// True for changes made by a background effect (e.g. a blink toggle) rather than a direct command
+    pub effect: bool,
+    /// Why a background effect stopped on its own, when a GPIO write failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub timestamp: SystemTime,
+}
+
+/// Publishing side of the LED event channel
+///
+/// Commands (on/off/blink/all_off) are always published immediately. Effect-driven
+/// changes are throttled per LED to at most one event every `coalesce` interval, so a
+/// fast blink doesn't flood subscribers. The tradeoff is that the `lit` value a
+/// subscriber last saw for an effect-driven LED can be up to one interval stale;
+/// `new_state` (e.g. `Blinking`) is always accurate because commands are never dropped.
+/// With `effects` off, effect-driven changes aren't published at all.
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<LedEvent>,
+    coalesce: Duration,
+    effects: bool,
+    /// When each LED last published an effect event
+    last_effect: Mutex<HashMap<Led, Instant>>,
+}
+
+impl EventBus {
+    pub(crate) fn new(coalesce: Duration, effects: bool) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            sender,
+            coalesce,
+            effects,
+            last_effect: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<LedEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Publish a user-initiated change; never throttled
+    pub(crate) fn command(&self, led: Led, old_state: LedState, new_state: LedState, lit: bool) {
+        self.publish(LedEvent { led, old_state, new_state, lit, effect: false, error: None, timestamp: SystemTime::now() });
+    }
+
+    /// Publish an effect-driven change, dropping it if this LED published one within the coalesce interval
+    pub(crate) fn effect(&self, led: Led, state: LedState, lit: bool) {
+        if !self.effects {
+            return;
+        }
+        let now = Instant::now();
+        {
+            let mut last_effect = self.last_effect.lock().unwrap();
+            if let Some(last) = last_effect.get(&led)
+                && now.duration_since(*last) < self.coalesce
+            {
+                return;
+            }
+            last_effect.insert(led, now);
+        }
+        self.publish(LedEvent { led, old_state: state, new_state: state, lit, effect: true, error: None, timestamp: SystemTime::now() });
+    }
+
+    /// Publish that an effect stopped because a GPIO write failed; never throttled
+    /// Whether the LED is lit afterwards is unknown, so `lit` is reported as false
+    pub(crate) fn error(&self, led: Led, old_state: LedState, new_state: LedState, error: String) {
+        self.publish(LedEvent { led, old_state, new_state, lit: false, effect: true, error: Some(error), timestamp: SystemTime::now() });
+    }
+
+    fn publish(&self, event: LedEvent) {
+        // No receivers is fine; lagged receivers just miss events
+        let _ = self.sender.send(event);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::error::{Result, TrainError};
+use futures::StreamExt;
+use gpio_cdev::{Chip, EventRequestFlags, LineRequestFlags};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+/// Number of input events a subscriber can fall behind before it starts missing them
+const INPUT_CHANNEL_CAPACITY: usize = 64;
+/// GPIO label for input lines
+const CONSUMER: &str = "train-input";
+
+/// Kernel bias flags (GPIOHANDLE_REQUEST_BIAS_*, Linux 5.5+), which gpio-cdev doesn't name
+const BIAS_PULL_UP: u32 = 1 << 5;
+const BIAS_PULL_DOWN: u32 = 1 << 6;
+
+/// Direction of a debounced input change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    /// Low to high
+    Rising,
+    /// High to low (a press, for a button to ground with a pull-up)
+    Falling,
+}
+
+/// Internal pull resistor for an input line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pull {
+    /// Leave the line floating (external resistor)
+    #[default]
+    None,
+    Up,
+    Down,
+}
+
+/// An input line to watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputLine {
+    /// Line offset on the chip
+    pub line: u8,
+    #[serde(default)]
+    pub pull: Pull,
+}
+
+/// A debounced change on an input line, from `InputController::subscribe()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputEvent {
+    pub line: u8,
+    pub edge: Edge,
+    /// When the line settled at its new level
+    pub timestamp: SystemTime,
+}
+
+/// Turns raw, bouncing edges into clean level changes
+/// A new level is only reported once the line has stayed at it for the whole window.
+struct Debouncer {
+    window: Duration,
+    /// Last reported level
+    stable: bool,
+    /// Level seen on the latest raw edge, and when
+    pending: Option<(bool, Instant)>,
+}
+
+impl Debouncer {
+    fn new(window: Duration, level: bool) -> Self {
+        Self { window, stable: level, pending: None }
+    }
+
+    /// Record a raw edge leaving the line at `level`
+    fn edge(&mut self, level: bool, at: Instant) {
+        self.pending = Some((level, at));
+    }
+
+    /// When the pending level will have settled, if there is one
+    fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, at)| at + self.window)
+    }
+
+    /// The edge to report once the window has passed without further bouncing
+    /// A bounce that ends back at the stable level reports nothing.
+    fn settle(&mut self, now: Instant) -> Option<Edge> {
+        let (level, at) = self.pending?;
+        if now < at + self.window {
+            return None;
+        }
+        self.pending = None;
+        if level == self.stable {
+            return None;
+        }
+        self.stable = level;
+        Some(if level { Edge::Rising } else { Edge::Falling })
+    }
+}
+
+/// Watches GPIO input lines (buttons, track sensors) and publishes debounced edges
+/// Each line has a background task; they stop when the controller is dropped.
+pub struct InputController {
+    events: broadcast::Sender<InputEvent>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl InputController {
+    /// Request `lines` of the GPIO chip at `chip_path` as inputs with edge events
+    /// An edge is reported once its line has been steady for `debounce`. Must be called from
+    /// within a Tokio runtime. Pull resistors need Linux 5.5 or later.
+    pub fn new(chip_path: &str, lines: &[InputLine], debounce: Duration) -> Result<Self> {
+        let mut chip = Chip::new(chip_path)
+            .map_err(|e| TrainError::GPIO(format!("Failed to open GPIO chip {}: {}", chip_path, e)))?;
+        let (events, _) = broadcast::channel(INPUT_CHANNEL_CAPACITY);
+        let mut controller = Self { events, tasks: Vec::new() };
+
+        for input in lines {
+            let bias = match input.pull {
+                Pull::None => 0,
+                Pull::Up => BIAS_PULL_UP,
+                Pull::Down => BIAS_PULL_DOWN,
+            };
+            let flags = LineRequestFlags::INPUT | LineRequestFlags::from_bits_retain(bias);
+            let mut handle = chip.get_line(u32::from(input.line))
+                .and_then(|line| line.async_events(flags, EventRequestFlags::BOTH_EDGES, CONSUMER))
+                .map_err(|e| TrainError::GPIO(format!("Failed to request GPIO input {} of {}: {}", input.line, chip_path, e)))?;
+            let level = handle.as_ref().get_value()
+                .map_err(|e| TrainError::GPIO(format!("Failed to read GPIO input {}: {}", input.line, e)))?;
+
+            let line = input.line;
+            let events = controller.events.clone();
+            let mut debouncer = Debouncer::new(debounce, level != 0);
+            controller.tasks.push(tokio::spawn(async move {
+                loop {
+                    let settle = debouncer.deadline();
+                    tokio::select! {
+                        event = handle.next() => match event {
+                            Some(Ok(_)) => match handle.as_ref().get_value() {
+                                Ok(level) => debouncer.edge(level != 0, Instant::now()),
+                                Err(e) => tracing::warn!(line, error = %e, "Failed to read GPIO input"),
+                            },
+                            Some(Err(e)) => {
+                                tracing::warn!(line, error = %e, "GPIO input stopped");
+                                return;
+                            }
+                            None => return,
+                        },
+                        _ = tokio::time::sleep_until(settle.unwrap_or_else(Instant::now)), if settle.is_some() => {
+                            if let Some(edge) = debouncer.settle(Instant::now()) {
+                                // No subscribers is fine
+                                let _ = events.send(InputEvent { line, edge, timestamp: SystemTime::now() });
+                            }
+                        }
+                    }
+                }
+            }));
+        }
+        Ok(controller)
+    }
+
+    /// Receive debounced edges from every watched line
+    pub fn subscribe(&self) -> broadcast::Receiver<InputEvent> {
+        self.events.subscribe()
+    }
+}
+
+impl Drop for InputController {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounces_settle_into_one_edge() {
+        let window = Duration::from_millis(20);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::new(window, true);
+
+        // A press that bounces for 10ms
+        debouncer.edge(false, at(0));
+        debouncer.edge(true, at(3));
+        debouncer.edge(false, at(10));
+        assert_eq!(debouncer.settle(at(25)), None, "still inside the window after the last bounce");
+        assert_eq!(debouncer.deadline(), Some(at(30)));
+        assert_eq!(debouncer.settle(at(30)), Some(Edge::Falling));
+        assert_eq!(debouncer.settle(at(60)), None);
+
+        // A glitch that ends where it started
+        debouncer.edge(true, at(100));
+        debouncer.edge(false, at(102));
+        assert_eq!(debouncer.settle(at(200)), None);
+
+        debouncer.edge(true, at(300));
+        assert_eq!(debouncer.settle(at(320)), Some(Edge::Rising));
+    }
+}
@@ -1,9 +1,27 @@
+use crate::backend::{DryRunBackend, LedBackend, LineDiagnostics};
+#[cfg(feature = "hardware")]
+use crate::backend::GpioBackend;
+#[cfg(not(feature = "hardware"))]
+use crate::backend::MockBackend;
+use crate::config::LedConfig;
 use crate::error::{Result, TrainError};
-use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
-use std::collections::HashMap;
+use crate::events::{EventBus, LedEvent};
+use crate::persist;
+use crate::schedule::{self, LedCommand, ScheduleId, ScheduledCommand, Schedules};
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
-use tokio::time::{interval, Duration, MissedTickBehavior};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{broadcast, watch, Notify, RwLock};
+use tokio::task::{AbortHandle, Id as TaskId};
+use tokio::time::{interval, interval_at, Duration, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
 
 /// LED subsets
 pub const GREEN_LEDS: std::ops::RangeInclusive<u8> = 1..=6;
@@ -13,164 +31,2402 @@ pub const RED_LEDS: std::ops::RangeInclusive<u8> = 13..=24;
 /// Total number of LEDs
 pub const LED_COUNT: u8 = 24;
 
-/// LED state for set_led_by_color function
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whole-panel image for `set_frame()`: whether each LED is lit, LED 1 first
+pub type Frame = [bool; LED_COUNT as usize];
+
+/// Time between heartbeat double-blips
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(2);
+/// Length of each heartbeat blip and the gap between them
+const HEARTBEAT_BLIP: Duration = Duration::from_millis(100);
+/// Blink interval for the red LEDs in `panic_mode()`
+pub const PANIC_BLINK_MS: u64 = 500;
+
+/// A validated LED number (1-24)
+/// Keeps LED numbers from being mixed up with positions or GPIO pins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct Led(u8);
+
+impl Led {
+    /// Fails with `LedOutOfRange` unless the number is 1-24
+    pub fn new(led: u8) -> Result<Self> {
+        if !(1..=LED_COUNT).contains(&led) {
+            return Err(TrainError::LedOutOfRange { led, max: LED_COUNT });
+        }
+        Ok(Self(led))
+    }
+
+    /// Every LED, in order
+    pub fn all() -> impl Iterator<Item = Led> {
+        (1..=LED_COUNT).map(Led)
+    }
+
+    /// The LED number (1-24)
+    pub fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u8> for Led {
+    type Error = TrainError;
+
+    fn try_from(led: u8) -> Result<Self> {
+        Self::new(led)
+    }
+}
+
+impl From<Led> for u8 {
+    fn from(led: Led) -> u8 {
+        led.0
+    }
+}
+
+impl fmt::Display for Led {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// LED colour groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LedColor {
+    Green,
+    Amber,
+    Red,
+}
+
+impl LedColor {
+    pub const ALL: [LedColor; 3] = [LedColor::Green, LedColor::Amber, LedColor::Red];
+
+    /// LED numbers belonging to this colour
+    pub fn range(self) -> std::ops::RangeInclusive<u8> {
+        match self {
+            LedColor::Green => GREEN_LEDS,
+            LedColor::Amber => AMBER_LEDS,
+            LedColor::Red => RED_LEDS,
+        }
+    }
+
+    /// Colour of an LED and its 1-based position within that colour
+    pub fn of(led: Led) -> Option<(LedColor, u8)> {
+        let led = led.get();
+        Self::ALL.into_iter()
+            .find(|color| color.range().contains(&led))
+            .map(|color| (color, led - color.range().start() + 1))
+    }
+}
+
+impl fmt::Display for LedColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LedColor::Green => "green",
+            LedColor::Amber => "amber",
+            LedColor::Red => "red",
+        })
+    }
+}
+
+impl std::str::FromStr for LedColor {
+    type Err = TrainError;
+
+    /// Parse a colour name, ignoring case
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "green" => Ok(LedColor::Green),
+            "amber" => Ok(LedColor::Amber),
+            "red" => Ok(LedColor::Red),
+            _ => Err(TrainError::InvalidParameter(
+                format!("Unknown colour '{}' (expected green, amber or red)", name)
+            )),
+        }
+    }
+}
+
+/// Physical wiring of a single LED
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LedMapping {
+    pub led: Led,
+    /// GPIO chip device path
+    pub chip: String,
+    /// Line offset within the chip
+    pub gpio: u8,
+    pub color: LedColor,
+    pub position: u8,
+}
+
+/// LED state, as tracked by the controller and accepted by set_led_by_color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "state", rename_all = "lowercase")]
 pub enum LedState {
     On,
     Off,
+    /// Blinking with the given toggle interval in milliseconds
+    Blinking { frequency_ms: u64 },
+    /// Flickering irregularly like a failing lamp
+    Flickering { intensity: FlickerIntensity },
+    /// Brightness rising and falling smoothly between `min` and `max` (0-255) every `period_ms`
+    Breathing { period_ms: u64, min: u8, max: u8 },
+    /// Held at a brightness between off (0) and full (255) by software PWM
+    Dimmed { level: u8 },
+    /// A single pulse: brightness ramping up over `rise_ms`, then down over `fall_ms`, then off
+    Pulsing { rise_ms: u64, fall_ms: u64 },
+    /// Ramping to full brightness (in) or off (out) over `duration_ms`, then staying there
+    Fading { direction: FadeDirection, duration_ms: u64 },
+}
+
+/// Which way a fade goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FadeDirection {
+    /// From off up to full brightness
+    In,
+    /// From the current brightness down to off
+    Out,
+}
+
+/// How badly a flickering LED misbehaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FlickerIntensity {
+    /// Mostly lit, with occasional dropouts
+    Light,
+    /// Lit about half the time
+    Medium,
+    /// Mostly dark, with occasional bursts of light
+    Heavy,
+}
+
+impl FlickerIntensity {
+    /// Chance of the LED being lit at each flicker step
+    fn lit_probability(self) -> f64 {
+        match self {
+            FlickerIntensity::Light => 0.85,
+            FlickerIntensity::Medium => 0.5,
+            FlickerIntensity::Heavy => 0.2,
+        }
+    }
+}
+
+/// Software PWM period; each period the LED is lit for a share proportional to its duty cycle
+const PWM_PERIOD: Duration = Duration::from_millis(10);
+/// Shortest allowed breathe period in milliseconds
+const MIN_BREATHE_MS: u64 = 100;
+/// Gamma applied to brightness levels, so equal steps in level look like equal steps in brightness
+const GAMMA: f64 = 2.2;
+
+/// Shortest gap between flicker steps
+const FLICKER_MIN_GAP_MS: u64 = 30;
+/// Longest gap between flicker steps
+const FLICKER_MAX_GAP_MS: u64 = 300;
+
+/// Saved state of a single LED within a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LedSnapshotEntry {
+    pub led: Led,
+    #[serde(flatten)]
+    pub state: LedState,
+    /// Human-readable label, if one has been set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Point-in-time copy of every LED's state, for saving to disk and restoring later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LedSnapshot {
+    pub leds: Vec<LedSnapshotEntry>,
+    /// LED aliases (name -> LED number)
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Led>,
+    /// Named LED groups (name -> member LEDs)
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<Led>>,
+    /// Commands waiting to run, soonest first
+    #[serde(default)]
+    pub schedules: Vec<ScheduledCommand>,
+}
+
+/// Drive an LED on a blocking thread, giving up after `timeout`
+/// A wedged GPIO line then fails the one operation instead of stalling the runtime
+async fn write_led(backend: &Arc<dyn LedBackend>, led: Led, on: bool, timeout: Duration) -> Result<()> {
+    let backend = Arc::clone(backend);
+    let write = tokio::task::spawn_blocking(move || backend.set(led.get(), on));
+    match tokio::time::timeout(timeout, write).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(TrainError::Hardware(format!("GPIO write for LED {} panicked: {}", led, e))),
+        Err(_) => Err(TrainError::Timeout(format!(
+            "Turning {} LED {} took longer than {}ms",
+            if on { "on" } else { "off" }, led, timeout.as_millis()
+        ))),
+    }
+}
+
+/// Set an LED's hardware PWM level on a blocking thread, giving up after `timeout`
+async fn write_level(backend: &Arc<dyn LedBackend>, led: Led, level: u8, timeout: Duration) -> Result<()> {
+    let backend = Arc::clone(backend);
+    let write = tokio::task::spawn_blocking(move || backend.set_level(led.get(), level));
+    match tokio::time::timeout(timeout, write).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(TrainError::Hardware(format!("PWM write for LED {} panicked: {}", led, e))),
+        Err(_) => Err(TrainError::Timeout(format!(
+            "Setting LED {} to level {} took longer than {}ms", led, level, timeout.as_millis()
+        ))),
+    }
+}
+
+/// Wind up an effect task stopped by a failed GPIO write, instead of leaving it looping on a dead line
+/// Removes the calling task's handle entries (unless a newer effect has replaced them), marks `led`
+/// off, and publishes the error. Returns without changes if the task had already been replaced.
+async fn end_failed_effect(
+    blink_handles: &RwLock<HashMap<Led, AbortHandle>>,
+    states: &RwLock<HashMap<Led, LedState>>,
+    events: &EventBus,
+    led: Led,
+    error: TrainError,
+) {
+    let mut handles = blink_handles.write().await;
+    let id = tokio::task::id();
+    if handles.get(&led).is_none_or(|handle| handle.id() != id) {
+        return;
+    }
+    handles.retain(|_, handle| handle.id() != id);
+    drop(handles);
+
+    tracing::warn!(%led, error = %error, "LED effect stopped");
+    let mut states = states.write().await;
+    let old = states.insert(led, LedState::Off).unwrap_or(LedState::Off);
+    events.error(led, old, LedState::Off, error.to_string());
+}
+
+/// `end_failed_effect()` for a task switching several LEDs together
+/// Removes all of the calling task's handle entries, marks `leds` off and publishes the error for each.
+async fn end_failed_group(
+    blink_handles: &RwLock<HashMap<Led, AbortHandle>>,
+    shared_blinks: &RwLock<HashSet<TaskId>>,
+    states: &RwLock<HashMap<Led, LedState>>,
+    events: &EventBus,
+    leds: Vec<Led>,
+    error: TrainError,
+) {
+    let id = tokio::task::id();
+    let mut handles = blink_handles.write().await;
+    handles.retain(|_, handle| handle.id() != id);
+    shared_blinks.write().await.remove(&id);
+    drop(handles);
+
+    tracing::warn!(error = %error, "LED effect stopped");
+    let mut states = states.write().await;
+    for led in leds {
+        let old = states.insert(led, LedState::Off).unwrap_or(LedState::Off);
+        events.error(led, old, LedState::Off, error.to_string());
+    }
+}
+
+/// `frequency_ms` shifted by a uniform random offset within ±`jitter_ms`, never less than 1ms
+fn jittered_interval(rng: &mut impl Rng, frequency_ms: u64, jitter_ms: u64) -> Duration {
+    let jitter = i128::from(jitter_ms);
+    let interval = i128::from(frequency_ms) + rng.gen_range(-jitter..=jitter);
+    Duration::from_millis(interval.clamp(1, i128::from(u64::MAX)) as u64)
+}
+
+/// Fraction of each PWM period an LED at `level` (0-255) is lit, after gamma correction
+fn duty_cycle(level: f64) -> f64 {
+    (level / 255.0).clamp(0.0, 1.0).powf(GAMMA)
+}
+
+/// Run one software PWM period at the given duty cycle (0.0-1.0)
+/// Only changes are written, so fully on or off periods cost no GPIO traffic. `lit` tracks
+/// the last value written, `None` if unknown.
+async fn pwm_period(backend: &Arc<dyn LedBackend>, led: Led, duty: f64, timeout: Duration, lit: &mut Option<bool>) -> Result<()> {
+    let on_time = PWM_PERIOD.mul_f64(duty.clamp(0.0, 1.0));
+    for (on, time) in [(true, on_time), (false, PWM_PERIOD - on_time)] {
+        if time.is_zero() {
+            continue;
+        }
+        if *lit != Some(on) {
+            write_led(backend, led, on, timeout).await?;
+            *lit = Some(on);
+        }
+        tokio::time::sleep(time).await;
+    }
+    Ok(())
+}
+
+/// Drive several LEDs together on a blocking thread, giving up after `timeout`
+/// Backends with multi-line handles switch them all in a single call
+async fn write_led_batch(backend: &Arc<dyn LedBackend>, values: Vec<(Led, bool)>, timeout: Duration) -> Result<()> {
+    let backend = Arc::clone(backend);
+    let count = values.len();
+    let write = tokio::task::spawn_blocking(move || {
+        let values: Vec<(u8, bool)> = values.into_iter().map(|(led, on)| (led.get(), on)).collect();
+        backend.set_many(&values)
+    });
+    match tokio::time::timeout(timeout, write).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(TrainError::Hardware(format!("GPIO write for {} LEDs panicked: {}", count, e))),
+        Err(_) => Err(TrainError::Timeout(format!(
+            "Switching {} LEDs took longer than {}ms", count, timeout.as_millis()
+        ))),
+    }
+}
+
+/// An LED that failed the self-test
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SelfTestFailure {
+    pub led: Led,
+    pub error: String,
+}
+
+/// Result of `LedController::self_test()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SelfTestReport {
+    /// Number of LEDs walked
+    pub tested: usize,
+    /// LEDs whose GPIO writes failed, in LED order
+    pub failures: Vec<SelfTestFailure>,
+    /// The walk was stopped before reaching every LED
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+impl SelfTestReport {
+    /// True if every LED switched on and off cleanly
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Capture every LED's state and name plus the aliases and groups, ordered by LED number
+/// Display names are saved with their LED's entry rather than among the aliases.
+async fn snapshot_of(
+    states: &RwLock<HashMap<Led, LedState>>,
+    aliases: &RwLock<HashMap<String, Alias>>,
+    groups: &RwLock<HashMap<String, Vec<Led>>>,
+    schedules: &Schedules,
+) -> LedSnapshot {
+    let all_aliases = aliases.read().await;
+    let name_of = |led: Led| all_aliases.iter()
+        .find(|&(_, alias)| alias.label && alias.led == led)
+        .map(|(name, _)| name.clone());
+    let mut leds: Vec<LedSnapshotEntry> = states.read().await.iter()
+        .map(|(&led, &state)| LedSnapshotEntry { led, state, name: name_of(led) })
+        .collect();
+    leds.sort_by_key(|entry| entry.led);
+    let aliases = all_aliases.iter()
+        .filter(|&(_, alias)| !alias.label)
+        .map(|(name, alias)| (name.clone(), alias.led))
+        .collect();
+    let groups = groups.read().await.iter()
+        .map(|(name, members)| (name.clone(), members.clone()))
+        .collect();
+    LedSnapshot { leds, aliases, groups, schedules: schedules.list().await }
+}
+
+/// A frame animation started by `play_frames()`
+pub struct AnimationHandle {
+    task: AbortHandle,
+    blink_handles: Arc<RwLock<HashMap<Led, AbortHandle>>>,
+    /// Closed when the task ends, however it ends
+    done: watch::Receiver<()>,
 }
 
-/// Maps LED number (1-24) to GPIO pin (4-27)
-fn led_to_gpio_pin(led: u8) -> Result<u8> {
-    if led < 1 || led > LED_COUNT {
-        return Err(TrainError::InvalidParameter(
-            format!("LED number must be between 1 and {}, got {}", LED_COUNT, led)
-        ));
+impl AnimationHandle {
+    /// Stop the animation, leaving the LEDs as the current frame has them
+    pub async fn stop(&self) {
+        let mut handles = self.blink_handles.write().await;
+        self.task.abort();
+        handles.retain(|_, handle| handle.id() != self.task.id());
+    }
+
+    /// Wait until the animation ends: played through, stopped, or replaced by other commands
+    /// A repeating animation only ends when it is stopped or replaced.
+    pub async fn finished(&self) {
+        let mut done = self.done.clone();
+        // Only ever errors, once the sender is dropped
+        while done.changed().await.is_ok() {}
     }
-    // LED 1 -> GPIO 4, LED 2 -> GPIO 5, ..., LED 24 -> GPIO 27
-    Ok(led + 3)
 }
 
-/// LED controller using direct GPIO access
-/// LEDs are numbered 1-24, mapped to GPIO pins 4-27
+/// What an alias refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Alias {
+    led: Led,
+    /// Whether this is the LED's display name (`set_name()`), of which each LED has at most one
+    label: bool,
+}
+
+/// Running liveness heartbeat
+struct Heartbeat {
+    led: Led,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// LED controller
+/// LEDs are numbered 1-24; the backend decides how they are driven (GPIO pins 4-27 by default)
 pub struct LedController {
-    /// GPIO line handles for each LED (1-24)
-    handles: Arc<RwLock<HashMap<u8, Arc<Mutex<LineHandle>>>>>,
-    /// Track which LEDs are currently blinking and their task handles
-    blink_handles: Arc<RwLock<HashMap<u8, tokio::task::JoinHandle<()>>>>,
+    /// Hardware configuration (pin map etc.)
+    config: LedConfig,
+    /// Output driver for the LEDs
+    backend: Arc<dyn LedBackend>,
+    /// Last commanded state of each LED
+    states: Arc<RwLock<HashMap<Led, LedState>>>,
+    /// Background tasks (blinks, traffic cycles) driving each LED
+    /// A task driving several LEDs has an entry for each of them
+    blink_handles: Arc<RwLock<HashMap<Led, AbortHandle>>>,
+    /// Tasks in `blink_handles` that blink their LEDs in unison; commanding one member only drops
+    /// it from the task, which keeps blinking the rest
+    /// Lock order: `blink_handles`, then `shared_blinks`
+    shared_blinks: Arc<RwLock<HashSet<TaskId>>>,
+    /// When each LED turned on by `on_for()` is due to go off; its timer task is in `blink_handles`
+    /// Lock order: `blink_handles`, then `expiries`
+    expiries: Arc<RwLock<HashMap<Led, Instant>>>,
+    /// Whether each blinking or flickering LED is lit right now, as last written by its task
+    /// Only meaningful while the LED's state says an effect is running
+    effect_lit: Arc<RwLock<HashMap<Led, bool>>>,
+    /// Cancelled on process shutdown; stops all background blink tasks
+    shutdown: CancellationToken,
+    /// Parent of the tokens handed to long-running operations (self-test, sequences)
+    /// A child of `shutdown`; `stop_all()` cancels it and puts a fresh one in its place
+    operations: RwLock<CancellationToken>,
+    /// Human-friendly names for LEDs (name -> LED), including each LED's display name
+    /// A name refers to one LED, so display names are unique and usable wherever an alias is
+    aliases: Arc<RwLock<HashMap<String, Alias>>>,
+    /// Named sets of LEDs (name -> members in LED order); groups may overlap
+    groups: Arc<RwLock<HashMap<String, Vec<Led>>>>,
+    /// Woken when aliases, names, groups or schedules change, which (unlike LED states) publish no event
+    labels_changed: Arc<Notify>,
+    /// LED running the liveness heartbeat, and its task handle
+    heartbeat: Arc<RwLock<Option<Heartbeat>>>,
+    /// Commands waiting to run at a set time; see `run_scheduler()`
+    schedules: Arc<Schedules>,
+    /// Set by `panic_mode()`, cleared by `all_off()`; scheduled commands are dropped while set
+    panicking: AtomicBool,
+    /// State-change events for subscribers
+    events: Arc<EventBus>,
 }
 
 impl LedController {
     /// Create a new LED controller
     /// Initializes all 24 LEDs on GPIO pins 4-27
     pub fn new() -> Result<Self> {
-        let mut handles = HashMap::new();
-        
-        // Open GPIO chip (usually /dev/gpiochip0 on Raspberry Pi)
-        let mut chip = Chip::new("/dev/gpiochip0")
-            .map_err(|e| TrainError::GPIO(format!("Failed to open GPIO chip: {}", e)))?;
-        
-        // Initialize GPIO lines for LEDs 1-24 (GPIO pins 4-27)
-        for led_num in 1..=LED_COUNT {
-            let gpio_pin = led_to_gpio_pin(led_num)?;
-            let line = chip.get_line(gpio_pin as u32)
-                .map_err(|e| TrainError::GPIO(format!("Failed to get GPIO line {} for LED {}: {}", gpio_pin, led_num, e)))?;
-            
-            let handle = line.request(LineRequestFlags::OUTPUT, 0, "train-led")
-                .map_err(|e| TrainError::GPIO(format!("Failed to request GPIO line {} for LED {}: {}", gpio_pin, led_num, e)))?;
-            
-            handles.insert(led_num, Arc::new(Mutex::new(handle)));
-        }
-
-        Ok(Self {
-            handles: Arc::new(RwLock::new(handles)),
+        Self::with_config(LedConfig::default())
+    }
+
+    /// Create a new LED controller in degraded mode if some GPIO lines are unavailable
+    /// LEDs whose lines can't be requested are listed by `failed_leds()` and reject all operations
+    pub fn new_partial() -> Result<Self> {
+        Self::with_config(LedConfig { strict: false, ..LedConfig::default() })
+    }
+
+    /// Create a new LED controller driving GPIO lines according to a custom config
+    /// With `dry_run` set no GPIO is touched; writes are printed to stderr instead. Built without
+    /// the `hardware` feature, the LEDs are driven by an in-memory `MockBackend`.
+    pub fn with_config(config: LedConfig) -> Result<Self> {
+        let backend: Arc<dyn LedBackend> = if config.dry_run {
+            config.validate()?;
+            Arc::new(DryRunBackend::new())
+        } else {
+            Self::hardware_backend(&config)?
+        };
+        Ok(Self::with_backend_and_config(backend, config))
+    }
+
+    #[cfg(feature = "hardware")]
+    fn hardware_backend(config: &LedConfig) -> Result<Arc<dyn LedBackend>> {
+        Ok(Arc::new(GpioBackend::with_config(config)?))
+    }
+
+    #[cfg(not(feature = "hardware"))]
+    fn hardware_backend(config: &LedConfig) -> Result<Arc<dyn LedBackend>> {
+        config.validate()?;
+        tracing::warn!("Built without the hardware feature; LEDs are simulated in memory");
+        Ok(Arc::new(MockBackend::new()))
+    }
+
+    /// Create a controller on top of an existing backend
+    /// All LEDs are assumed to start off
+    pub fn with_backend(backend: Arc<dyn LedBackend>) -> Self {
+        Self::with_backend_and_config(backend, LedConfig::default())
+    }
+
+    /// Create a controller on top of an existing backend, described by `config`
+    pub fn with_backend_and_config(backend: Arc<dyn LedBackend>, config: LedConfig) -> Self {
+        let states = Led::all().map(|led| (led, LedState::Off)).collect();
+        let events = Arc::new(EventBus::new(Duration::from_millis(config.event_coalesce_ms), config.effect_events));
+        let shutdown = CancellationToken::new();
+
+        Self {
+            config,
+            backend,
+            states: Arc::new(RwLock::new(states)),
             blink_handles: Arc::new(RwLock::new(HashMap::new())),
-        })
+            shared_blinks: Arc::new(RwLock::new(HashSet::new())),
+            effect_lit: Arc::new(RwLock::new(HashMap::new())),
+            expiries: Arc::new(RwLock::new(HashMap::new())),
+            operations: RwLock::new(shutdown.child_token()),
+            shutdown,
+            aliases: Arc::new(RwLock::new(HashMap::new())),
+            groups: Arc::new(RwLock::new(HashMap::new())),
+            labels_changed: Arc::new(Notify::new()),
+            heartbeat: Arc::new(RwLock::new(None)),
+            schedules: Arc::new(Schedules::default()),
+            panicking: AtomicBool::new(false),
+            events,
+        }
     }
 
-    /// Turn on a specific LED (1-24)
-    pub async fn on(&self, led: u8) -> Result<()> {
-        // Cancel blinking if this LED is blinking
+    /// Create a controller like `new()` that saves its state to `path` and restores it on startup
+    /// See `with_persistence()`
+    pub async fn new_with_persistence(path: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self::new()?.with_persistence(path).await)
+    }
+
+    /// Re-apply the state saved in `path` (restarting blinks), then keep the file up to date
+    /// The file is rewritten at most once a second after a command, alias, name, group or schedule change;
+    /// blink toggles alone don't trigger writes. A saved state takes precedence over
+    /// `LedConfig::initial`; with a missing, corrupt or unusable file the LEDs start in the
+    /// configured initial state instead (dark if there is none). Saving stops when the shutdown
+    /// token is cancelled, after a final write, so the `all_off()` that follows shutdown isn't saved.
+    pub async fn with_persistence(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let restored = match persist::load(&path).await {
+            Some(snapshot) => match self.restore(&snapshot).await {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), error = %e, "Saved LED state couldn't be restored");
+                    let _ = self.all_off().await;
+                    false
+                }
+            },
+            None => false,
+        };
+        if !restored && let Err(e) = self.apply_initial().await {
+            tracing::warn!(error = %e, "Initial LED state couldn't be applied, starting dark");
+            let _ = self.all_off().await;
+        }
+
+        let mut events = self.subscribe();
+        let states = Arc::clone(&self.states);
+        let aliases = Arc::clone(&self.aliases);
+        let groups = Arc::clone(&self.groups);
+        let schedules = Arc::clone(&self.schedules);
+        let labels_changed = Arc::clone(&self.labels_changed);
+        let shutdown = self.shutdown.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = labels_changed.notified() => {}
+                    event = events.recv() => match event {
+                        Ok(event) if event.effect => continue,
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                }
+                // Let a burst of changes settle into a single write
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(persist::PERSIST_INTERVAL) => {}
+                }
+                // Changes made while waiting are covered by this write
+                events = events.resubscribe();
+                persist::save(&path, &snapshot_of(&states, &aliases, &groups, &schedules).await).await;
+            }
+            persist::save(&path, &snapshot_of(&states, &aliases, &groups, &schedules).await).await;
+        });
+
+        self
+    }
+
+    /// Subscribe to LED state changes
+    /// Slow receivers miss events rather than holding up GPIO operations
+    pub fn subscribe(&self) -> broadcast::Receiver<LedEvent> {
+        self.events.subscribe()
+    }
+
+    /// Subscribe to LED state changes, along with the state of every LED as the subscription starts
+    /// Commands publish their events while holding the state lock, and this subscribes under the
+    /// same lock, so the receiver gets exactly the changes made after the snapshot: none are
+    /// missed and none are already reflected in it. Effect events (blink toggles) never change
+    /// a commanded state, so they can't contradict the snapshot either.
+    pub async fn subscribe_with_snapshot(&self) -> (Vec<(Led, LedState)>, broadcast::Receiver<LedEvent>) {
+        let states = self.states.read().await;
+        let receiver = self.events.subscribe();
+        let mut all: Vec<(Led, LedState)> = states.iter()
+            .map(|(&led, &state)| (led, state))
+            .collect();
+        all.sort_by_key(|&(led, _)| led);
+        (all, receiver)
+    }
+
+    /// Get the shutdown token shared with all background blink tasks
+    /// Cancelling it stops every blink task; callers should follow up with `all_off()`
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Token for a long-running operation, cancelled by `stop_all()`, `panic_mode()` or shutdown
+    /// Operations should check it between steps. Once it's cancelled the panel belongs to whoever
+    /// cancelled it, so an operation should stop without tidying up (e.g. no `all_off()`).
+    pub async fn operation_token(&self) -> CancellationToken {
+        self.operations.read().await.child_token()
+    }
+
+    /// Stop everything: cancel running operations (like `self_test()`), then `all_off()`
+    /// Operations started afterwards run normally
+    pub async fn stop_all(&self) -> Result<()> {
+        let fresh = self.shutdown.child_token();
+        std::mem::replace(&mut *self.operations.write().await, fresh).cancel();
+        self.all_off().await
+    }
+
+    /// LEDs that failed to initialize, with the reason
+    pub fn failed_leds(&self) -> Vec<(u8, String)> {
+        self.backend.failed_leds()
+    }
+
+    /// How the kernel reports each LED's line, for backends that can tell (GPIO); empty otherwise
+    /// Lines whose info can't be read are logged and left out.
+    pub fn diagnostics(&self) -> Vec<LineDiagnostics> {
+        Led::all()
+            .filter_map(|led| match self.backend.line_info(led.get()) {
+                Ok(info) => Some(info),
+                Err(TrainError::NotSupported) => None,
+                Err(e) => {
+                    tracing::warn!(led = led.get(), error = %e, "Couldn't read GPIO line info");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Error if the LED's output failed to initialize or has been released
+    fn check_available(&self, led: Led) -> Result<()> {
+        if self.backend.is_released(led.get()) {
+            return Err(TrainError::LedReleased(led.get()));
+        }
+        match self.backend.failure(led.get()) {
+            Some(reason) => Err(TrainError::LedUnavailable(led.get(), reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Error unless the blink interval is within the configured limits
+    fn check_frequency(&self, frequency_ms: u64) -> Result<()> {
+        let (min_ms, max_ms) = self.blink_limits();
+        if !(min_ms..=max_ms).contains(&frequency_ms) {
+            return Err(TrainError::InvalidFrequency { frequency_ms, min_ms, max_ms });
+        }
+        Ok(())
+    }
+
+    /// Error unless the breathe range is in order and the period long enough to be smooth
+    fn check_breathe(period_ms: u64, min: u8, max: u8) -> Result<()> {
+        if min > max {
+            return Err(TrainError::InvalidParameter(
+                format!("Breathe minimum ({}) must not be above the maximum ({})", min, max)
+            ));
+        }
+        if period_ms < MIN_BREATHE_MS {
+            return Err(TrainError::InvalidParameter(
+                format!("Breathe period must be at least {}ms, got {}ms", MIN_BREATHE_MS, period_ms)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Shortest and longest allowed blink intervals in milliseconds
+    pub fn blink_limits(&self) -> (u64, u64) {
+        (self.config.min_blink_ms, self.config.max_blink_ms)
+    }
+
+    /// How long a single GPIO write may take
+    fn gpio_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.gpio_timeout_ms)
+    }
+
+    /// Drive an LED, failing with `Timeout` if the GPIO write hangs
+    async fn write(&self, led: Led, on: bool) -> Result<()> {
+        write_led(&self.backend, led, on, self.gpio_timeout()).await
+    }
+
+    /// Write several LEDs in one backend call, bounded by the GPIO timeout
+    async fn write_many(&self, values: Vec<(Led, bool)>) -> Result<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        write_led_batch(&self.backend, values, self.gpio_timeout()).await
+    }
+
+    /// Release an LED's GPIO line so another program can use the pin
+    /// Cancels any blink; operations on the LED fail with `LedReleased` until `acquire()`
+    pub async fn release(&self, led: Led) -> Result<()> {
         self.cancel_blink(led).await?;
-        
-        let handles = self.handles.read().await;
-        let handle = handles.get(&led)
-            .ok_or_else(|| TrainError::InvalidParameter(format!("LED {} not found", led)))?;
-        
-        let handle_guard = handle.lock().await;
-        handle_guard.set_value(1)
-            .map_err(|e| TrainError::GPIO(format!("Failed to turn on LED {}: {}", led, e)))?;
-        
+        self.backend.release(led.get())?;
+        self.set_tracked_state(led, LedState::Off).await;
+        Ok(())
+    }
+
+    /// Re-request a released LED's GPIO line with its original settings (initially off)
+    pub async fn acquire(&self, led: Led) -> Result<()> {
+        self.backend.acquire(led.get())?;
+        self.set_tracked_state(led, LedState::Off).await;
         Ok(())
     }
 
-    /// Turn off a specific LED (1-24)
-    pub async fn off(&self, led: u8) -> Result<()> {
+    /// Record a new state without touching the GPIO line, publishing an event if it changed
+    async fn set_tracked_state(&self, led: Led, state: LedState) {
+        let mut states = self.states.write().await;
+        let old = states.insert(led, state).unwrap_or(LedState::Off);
+        if old != state {
+            self.events.command(led, old, state, state == LedState::On);
+        }
+    }
+
+    /// Get the last commanded state of a specific LED
+    pub async fn state(&self, led: Led) -> LedState {
+        self.states.read().await.get(&led).copied().unwrap_or(LedState::Off)
+    }
+
+    /// Wait until an LED's commanded state is `state`, e.g. for another task's pattern to turn it off
+    /// Returns straight away if it's already there. Listens for state-change events rather than
+    /// polling, and fails with `StateWaitTimeout` if the state isn't reached within `timeout`.
+    pub async fn wait_for(&self, led: Led, state: LedState, timeout: Duration) -> Result<()> {
+        // Subscribe before checking, so a change in between isn't missed
+        let mut events = self.subscribe();
+        let reached = async {
+            loop {
+                if self.state(led).await == state {
+                    return;
+                }
+                loop {
+                    match events.recv().await {
+                        Ok(event) if event.led == led && event.new_state == state => return,
+                        Ok(_) => {}
+                        // Missed events might include ours; look at the state again
+                        Err(broadcast::error::RecvError::Lagged(_)) => break,
+                        Err(broadcast::error::RecvError::Closed) => std::future::pending::<()>().await,
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(timeout, reached).await.map_err(|_| TrainError::StateWaitTimeout {
+            led: led.get(),
+            waited_ms: timeout.as_millis() as u64,
+        })
+    }
+
+    /// Turn on a specific LED
+    /// Does not touch the GPIO line if the LED is already on
+    pub async fn on(&self, led: Led) -> Result<()> {
+        self.set(led, true).await
+    }
+
+    /// Turn off a specific LED
+    /// Does not touch the GPIO line if the LED is already off
+    pub async fn off(&self, led: Led) -> Result<()> {
+        self.set(led, false).await
+    }
+
+    /// Switch a steady LED on or off, skipping the write when it's already in that state
+    async fn set(&self, led: Led, on: bool) -> Result<()> {
+        self.check_available(led)?;
+
         // Cancel blinking if this LED is blinking
         self.cancel_blink(led).await?;
-        
-        let handles = self.handles.read().await;
-        let handle = handles.get(&led)
-            .ok_or_else(|| TrainError::InvalidParameter(format!("LED {} not found", led)))?;
-        
-        let handle_guard = handle.lock().await;
-        handle_guard.set_value(0)
-            .map_err(|e| TrainError::GPIO(format!("Failed to turn off LED {}: {}", led, e)))?;
-        
+
+        let target = if on { LedState::On } else { LedState::Off };
+        let mut states = self.states.write().await;
+        let current = states.entry(led).or_insert(LedState::Off);
+        if *current == target {
+            return Ok(());
+        }
+
+        self.write(led, on).await?;
+        let old = std::mem::replace(current, target);
+        self.events.command(led, old, target, on);
+
+        Ok(())
+    }
+
+    /// Turn on a specific LED for `ttl_ms`, then turn it off again
+    /// Calling this again before it goes off restarts the timer instead of stacking another one;
+    /// `off()`, `blink()` or a plain `on()` cancel the pending turn-off. The timer isn't persisted.
+    pub async fn on_for(&self, led: Led, ttl_ms: u64) -> Result<()> {
+        if ttl_ms == 0 {
+            return Err(TrainError::InvalidParameter("TTL must be greater than 0".to_string()));
+        }
+        self.check_effect_capacity(&[led], 1).await?;
+        // Cancels any earlier timer along with blinks
+        self.on(led).await?;
+
+        let ttl = Duration::from_millis(ttl_ms);
+        let backend = Arc::clone(&self.backend);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let expiries = Arc::clone(&self.expiries);
+        let states = Arc::clone(&self.states);
+
+        let mut handles = self.blink_handles.write().await;
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = tokio::time::sleep(ttl) => {}
+            }
+
+            // Remove our own entries, unless a newer command has already replaced them
+            let mut handles = blink_handles.write().await;
+            if handles.get(&led).is_none_or(|handle| handle.id() != tokio::task::id()) {
+                return;
+            }
+            handles.remove(&led);
+            expiries.write().await.remove(&led);
+            drop(handles);
+
+            let mut states = states.write().await;
+            if states.get(&led) == Some(&LedState::On) {
+                match write_led(&backend, led, false, gpio_timeout).await {
+                    Ok(()) => events.command(led, LedState::On, LedState::Off, false),
+                    Err(e) => {
+                        tracing::warn!(%led, error = %e, "Timed turn-off failed");
+                        events.error(led, LedState::On, LedState::Off, e.to_string());
+                    }
+                }
+                states.insert(led, LedState::Off);
+            }
+        });
+        handles.insert(led, task.abort_handle());
+        self.expiries.write().await.insert(led, Instant::now() + ttl);
+
+        Ok(())
+    }
+
+    /// Whether an LED is lit right now, following blink and flicker toggles
+    /// `None` for an LED under PWM (breathing, dimmed, pulsing or fading), which is neither on nor off
+    pub async fn is_lit(&self, led: Led) -> Option<bool> {
+        match self.state(led).await {
+            LedState::On => Some(true),
+            LedState::Off => Some(false),
+            LedState::Blinking { .. } | LedState::Flickering { .. } => {
+                Some(self.effect_lit.read().await.get(&led).copied().unwrap_or(false))
+            }
+            LedState::Breathing { .. } | LedState::Dimmed { .. } | LedState::Pulsing { .. } | LedState::Fading { .. } => None,
+        }
+    }
+
+    /// Time left before an LED turned on with `on_for()` goes off, if a turn-off is pending
+    pub async fn expires_in(&self, led: Led) -> Option<Duration> {
+        self.expiries.read().await.get(&led)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Switch several steady LEDs on or off together, in a single backend write
+    /// Blinks on them are cancelled; LEDs already in that state aren't written
+    pub(crate) async fn set_many(&self, leds: &[Led], on: bool) -> Result<()> {
+        let values: Vec<(Led, bool)> = leds.iter().map(|&led| (led, on)).collect();
+        self.set_values(&values).await
+    }
+
+    /// Switch several LEDs to the given values together, in a single backend write
+    /// Blinks on them are cancelled; LEDs already in the right state aren't written
+    async fn set_values(&self, values: &[(Led, bool)]) -> Result<()> {
+        for &(led, _) in values {
+            self.check_available(led)?;
+        }
+        for &(led, _) in values {
+            self.cancel_blink(led).await?;
+        }
+
+        let mut states = self.states.write().await;
+        let changed: Vec<(Led, bool)> = values.iter().copied()
+            .filter(|&(led, on)| states.get(&led).copied().unwrap_or(LedState::Off) != Self::steady(on))
+            .collect();
+        self.write_many(changed.clone()).await?;
+        for (led, on) in changed {
+            let target = Self::steady(on);
+            let old = states.insert(led, target).unwrap_or(LedState::Off);
+            self.events.command(led, old, target, on);
+        }
+
         Ok(())
     }
 
-    /// Blink a specific LED (1-24) with given frequency in milliseconds
-    /// The LED will toggle on/off at the specified interval
-    pub async fn blink(&self, led: u8, frequency_ms: u64) -> Result<()> {
-        if frequency_ms == 0 {
-            return Err(TrainError::InvalidParameter(
-                "Blink frequency must be greater than 0".to_string()
-            ));
+    fn steady(on: bool) -> LedState {
+        if on { LedState::On } else { LedState::Off }
+    }
+
+    /// Flip every steadily on or off LED, in a single backend write
+    /// LEDs running an effect (blinking, flickering, dimmed and so on) are left alone rather than
+    /// having their phase inverted, as are LEDs that failed to initialize or have been released.
+    pub async fn invert(&self) -> Result<()> {
+        let values: Vec<(Led, bool)> = self.get_all_states().await.into_iter()
+            .filter(|&(led, _)| self.check_available(led).is_ok())
+            .filter_map(|(led, state)| match state {
+                LedState::On => Some((led, false)),
+                LedState::Off => Some((led, true)),
+                _ => None,
+            })
+            .collect();
+        self.set_values(&values).await
+    }
+
+    /// Show `value` in binary on `leds`, least significant bit on the first LED, in a single backend write
+    /// Fails with `InvalidParameter` rather than truncating if the value needs more bits than there are
+    /// LEDs, so a wrong reading is never shown. Effects on the LEDs are cancelled first.
+    pub async fn display_binary(&self, value: u32, leds: &[Led]) -> Result<()> {
+        for (i, led) in leds.iter().enumerate() {
+            if leds[..i].contains(led) {
+                return Err(TrainError::InvalidParameter(format!("LED {} is listed more than once", led)));
+            }
+        }
+        let needed = u32::BITS - value.leading_zeros();
+        if needed as usize > leds.len() {
+            return Err(TrainError::InvalidParameter(
+                format!("{} needs {} bits but only {} LEDs were given", value, needed, leds.len())
+            ));
+        }
+        let values: Vec<(Led, bool)> = leds.iter().enumerate()
+            .map(|(bit, &led)| (led, bit < 32 && value >> bit & 1 == 1))
+            .collect();
+        self.set_values(&values).await
+    }
+
+    /// Switch every LED to `frame` in a single backend write, cancelling any effects
+    /// LEDs that failed to initialize or have been released are skipped.
+    pub async fn set_frame(&self, frame: &Frame) -> Result<()> {
+        let values: Vec<(Led, bool)> = Led::all()
+            .zip(frame)
+            .filter(|&(led, _)| self.check_available(led).is_ok())
+            .map(|(led, &on)| (led, on))
+            .collect();
+        self.set_values(&values).await
+    }
+
+    /// The panel as last commanded, from tracked state rather than reading the GPIO lines back
+    /// A blinking or flickering LED counts as lit if it is in the lit half of its cycle; a dimmed
+    /// or fading LED counts as unlit.
+    pub async fn get_frame(&self) -> Frame {
+        let mut frame = [false; LED_COUNT as usize];
+        for (lit, led) in frame.iter_mut().zip(Led::all()) {
+            *lit = self.is_lit(led).await == Some(true);
+        }
+        frame
+    }
+
+    /// `set_frame()` with the frame packed into bits, LED 1 in bit 0
+    /// Fails with `InvalidParameter` if any bit above LED 24 is set.
+    pub async fn set_frame_u32(&self, bits: u32) -> Result<()> {
+        if bits >> LED_COUNT != 0 {
+            return Err(TrainError::InvalidParameter(
+                format!("Frame 0x{:08x} sets bits beyond LED {}", bits, LED_COUNT)
+            ));
+        }
+        self.set_frame(&std::array::from_fn(|bit| bits >> bit & 1 == 1)).await
+    }
+
+    /// `get_frame()` packed into bits, LED 1 in bit 0
+    pub async fn get_frame_u32(&self) -> u32 {
+        self.get_frame().await.iter().rev().fold(0, |bits, &lit| bits << 1 | u32::from(lit))
+    }
+
+    /// Play pre-computed frames across the whole panel at `fps` frames per second
+    /// Runs in the background, looping if `repeat` is set, and otherwise leaving the last frame
+    /// showing. If GPIO writes fall behind, frames are skipped to stay on time rather than played
+    /// late. Commanding any LED, or `all_off()`, stops the animation. LEDs that failed to
+    /// initialize or have been released are left out.
+    pub async fn play_frames(&self, frames: Vec<Frame>, fps: u8, repeat: bool) -> Result<AnimationHandle> {
+        if fps == 0 {
+            return Err(TrainError::InvalidParameter("Animation frame rate must be greater than 0".to_string()));
+        }
+        if frames.is_empty() {
+            return Err(TrainError::InvalidParameter("Animation has no frames".to_string()));
+        }
+        let leds: Vec<Led> = Led::all().filter(|&led| self.check_available(led).is_ok()).collect();
+        self.check_effect_capacity(&leds, 1).await?;
+        for &led in &leds {
+            self.cancel_blink(led).await?;
+        }
+
+        let backend = Arc::clone(&self.backend);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let shared_blinks = Arc::clone(&self.shared_blinks);
+        let states = Arc::clone(&self.states);
+        let members = leds.clone();
+        let (done_tx, done) = watch::channel(());
+
+        let mut handles = self.blink_handles.write().await;
+
+        let handle_task = tokio::spawn(async move {
+            let _done = done_tx;
+            let frame_time = Duration::from_secs_f64(1.0 / f64::from(fps));
+            let start = Instant::now();
+            let mut interval = interval_at(start, frame_time);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                let at = tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    at = interval.tick() => at,
+                };
+                // From the tick time, so ticks skipped while writing skip their frames too
+                let index = ((at - start).as_secs_f64() * f64::from(fps)).round() as usize;
+                if !repeat && index >= frames.len() {
+                    break;
+                }
+                let frame = &frames[index % frames.len()];
+
+                let current = states.read().await.clone();
+                let changed: Vec<(Led, bool)> = members.iter()
+                    .map(|&led| (led, frame[usize::from(led.get() - 1)]))
+                    .filter(|&(led, on)| current.get(&led) != Some(&Self::steady(on)))
+                    .collect();
+                if changed.is_empty() {
+                    continue;
+                }
+                if let Err(e) = write_led_batch(&backend, changed.clone(), gpio_timeout).await {
+                    end_failed_group(&blink_handles, &shared_blinks, &states, &events, members, e).await;
+                    return;
+                }
+                let mut states = states.write().await;
+                for (led, on) in changed {
+                    states.insert(led, Self::steady(on));
+                    events.effect(led, Self::steady(on), on);
+                }
+            }
+
+            // Played through: the LEDs keep the last frame as steady states
+            let id = tokio::task::id();
+            blink_handles.write().await.retain(|_, handle| handle.id() != id);
+        });
+
+        for &led in &leds {
+            handles.insert(led, handle_task.abort_handle());
+        }
+
+        Ok(AnimationHandle { task: handle_task.abort_handle(), blink_handles: Arc::clone(&self.blink_handles), done })
+    }
+
+    /// Blink a specific LED with given frequency in milliseconds
+    /// The LED will toggle on/off at the specified interval, which must be within `blink_limits()`
+    pub async fn blink(&self, led: Led, frequency_ms: u64) -> Result<()> {
+        self.start_blink(led, frequency_ms, None, 0, 0).await
+    }
+
+    /// Blink a specific LED `hz` times a second, like `blink()`
+    /// `hz` counts full on-off cycles, so the LED toggles every `1000 / (2 * hz)` ms; see `interval_for_hz()`.
+    pub async fn blink_hz(&self, led: Led, hz: f64) -> Result<()> {
+        self.blink(led, Self::interval_for_hz(hz)?).await
+    }
+
+    /// Toggle interval in milliseconds for blinking `hz` full on-off cycles a second, rounded
+    /// to the nearest millisecond
+    /// Fails with `InvalidParameter` unless `hz` is a positive number; the result is checked
+    /// against `blink_limits()` when the blink starts.
+    pub fn interval_for_hz(hz: f64) -> Result<u64> {
+        if !(hz.is_finite() && hz > 0.0) {
+            return Err(TrainError::InvalidParameter(format!("Blink rate must be a positive number of Hz, got {}", hz)));
+        }
+        Ok((1000.0 / (2.0 * hz)).round() as u64)
+    }
+
+    /// Blink a specific LED for a fixed number of on/off cycles, then leave it off
+    /// Returns once the blink has started; the LED's state goes back to `Off` when it finishes
+    pub async fn blink_count(&self, led: Led, frequency_ms: u64, cycles: u32) -> Result<()> {
+        if cycles == 0 {
+            return Err(TrainError::InvalidParameter("Blink cycles must be greater than 0".to_string()));
+        }
+        self.start_blink(led, frequency_ms, Some(cycles), 0, 0).await
+    }
+
+    /// Blink a specific LED like `blink()`, delaying its first toggle by `phase_ms`
+    /// The phase is reduced modulo the full on/off period (2 x `frequency_ms`), so a phase of
+    /// `frequency_ms` blinks exactly out of step with an LED started at phase 0 - a wig-wag
+    /// crossing flasher. Each LED still has its own timer task, so two blinks can drift apart
+    /// slightly over long runs; start them back to back to keep the offset tight.
+    pub async fn blink_with_phase(&self, led: Led, frequency_ms: u64, phase_ms: u64) -> Result<()> {
+        self.start_blink(led, frequency_ms, None, phase_ms, 0).await
+    }
+
+    /// Blink a specific LED like `blink()`, varying each interval by a random offset within ±`jitter_ms`
+    /// Irregular blinking looks less mechanical, e.g. for distant town lights. Intervals are clamped
+    /// to at least 1ms. Each blink draws from its own RNG, so jittered LEDs don't keep in step, and
+    /// the LED's state reports the base `frequency_ms`. A jitter of 0 is exactly `blink()`.
+    pub async fn blink_jitter(&self, led: Led, frequency_ms: u64, jitter_ms: u64) -> Result<()> {
+        self.start_blink(led, frequency_ms, None, 0, jitter_ms).await
+    }
+
+    /// Start a blink task, running forever or for `cycles` on/off cycles, first toggling after `phase_ms`
+    /// With a `jitter_ms`, each interval after the first toggle is randomized; see `blink_jitter()`.
+    async fn start_blink(&self, led: Led, frequency_ms: u64, cycles: Option<u32>, phase_ms: u64, jitter_ms: u64) -> Result<()> {
+        self.check_frequency(frequency_ms)?;
+        self.check_available(led)?;
+        self.check_effect_capacity(&[led], 1).await?;
+
+        // Cancel any existing blink for this LED
+        self.cancel_blink(led).await?;
+
+        let mut states = self.states.write().await;
+        let current = states.entry(led).or_insert(LedState::Off);
+        let blinking = LedState::Blinking { frequency_ms };
+        let old = std::mem::replace(current, blinking);
+        drop(states);
+        self.effect_lit.write().await.insert(led, old == LedState::On);
+        self.events.command(led, old, blinking, old == LedState::On);
+
+        let backend = Arc::clone(&self.backend);
+        let effect_lit = Arc::clone(&self.effect_lit);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let states = Arc::clone(&self.states);
+
+        // Hold the handle map until the handle is stored, so a finite blink can't finish
+        // and look for its entry before it exists
+        let mut handles_write = self.blink_handles.write().await;
+
+        let first_toggle = Instant::now() + Duration::from_millis(phase_ms % (frequency_ms * 2));
+
+        // Spawn a task to handle blinking
+        let handle_task = tokio::spawn(async move {
+            let mut interval = interval_at(first_toggle, Duration::from_millis(frequency_ms));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let mut state = false;
+            // Toggles left for a finite blink (two per cycle)
+            let mut remaining = cycles.map(|cycles| u64::from(cycles) * 2);
+            let mut jitter = (jitter_ms > 0).then(StdRng::from_entropy);
+
+            while remaining != Some(0) {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+                if let Some(rng) = jitter.as_mut() {
+                    interval.reset_after(jittered_interval(rng, frequency_ms, jitter_ms));
+                }
+                state = !state;
+                if let Err(e) = write_led(&backend, led, state, gpio_timeout).await {
+                    end_failed_effect(&blink_handles, &states, &events, led, e).await;
+                    return;
+                }
+                effect_lit.write().await.insert(led, state);
+                events.effect(led, blinking, state);
+                if let Some(remaining) = remaining.as_mut() {
+                    *remaining -= 1;
+                }
+            }
+
+            // Finished naturally, with the LED off: remove our own entry,
+            // unless a newer blink has already replaced it
+            let mut handles = blink_handles.write().await;
+            if handles.get(&led).is_none_or(|handle| handle.id() != tokio::task::id()) {
+                return;
+            }
+            handles.remove(&led);
+            drop(handles);
+
+            let mut states = states.write().await;
+            if states.get(&led) == Some(&blinking) {
+                states.insert(led, LedState::Off);
+                events.command(led, blinking, LedState::Off, false);
+            }
+        });
+
+        // Store the handle
+        handles_write.insert(led, handle_task.abort_handle());
+
+        Ok(())
+    }
+
+    /// Blink every available LED together from a single task, so they flash in unison
+    /// Commanding one LED afterwards takes just that LED out of the blink; `all_off()` stops it.
+    /// LEDs that failed to initialize or have been released are left alone.
+    pub async fn blink_all(&self, frequency_ms: u64) -> Result<()> {
+        let leds: Vec<Led> = Led::all().filter(|&led| self.check_available(led).is_ok()).collect();
+        self.start_shared_blink(&leds, frequency_ms).await
+    }
+
+    /// Counterpart of `all_off()`: the same shared blink as `blink_all()`
+    pub async fn all_blink(&self, frequency_ms: u64) -> Result<()> {
+        self.blink_all(frequency_ms).await
+    }
+
+    /// Start one blink task toggling all of `leds` together, replacing any effects on them
+    /// Each tick the task switches whichever LEDs still have its handle entry in a single batch,
+    /// and ends once none are left.
+    async fn start_shared_blink(&self, leds: &[Led], frequency_ms: u64) -> Result<()> {
+        self.check_frequency(frequency_ms)?;
+        for &led in leds {
+            self.check_available(led)?;
+        }
+        self.check_effect_capacity(leds, 1).await?;
+        self.spawn_shared_blink(leds, frequency_ms).await
+    }
+
+    /// `start_shared_blink()` without the checks, for callers that have made their own
+    async fn spawn_shared_blink(&self, leds: &[Led], frequency_ms: u64) -> Result<()> {
+        for &led in leds {
+            self.cancel_blink(led).await?;
+        }
+
+        let blinking = LedState::Blinking { frequency_ms };
+        let mut states = self.states.write().await;
+        let mut effect_lit = self.effect_lit.write().await;
+        for &led in leds {
+            let old = states.insert(led, blinking).unwrap_or(LedState::Off);
+            effect_lit.insert(led, old == LedState::On);
+            self.events.command(led, old, blinking, old == LedState::On);
+        }
+        drop(effect_lit);
+        drop(states);
+
+        let backend = Arc::clone(&self.backend);
+        let effect_lit = Arc::clone(&self.effect_lit);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let shared_blinks = Arc::clone(&self.shared_blinks);
+        let states = Arc::clone(&self.states);
+
+        let mut handles = self.blink_handles.write().await;
+        let mut shared = self.shared_blinks.write().await;
+
+        let handle_task = tokio::spawn(async move {
+            let id = tokio::task::id();
+            let mut interval = interval(Duration::from_millis(frequency_ms));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            let mut state = false;
+
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+                state = !state;
+
+                // Hold the handle map while writing, so an LED taken out of the blink can't be
+                // switched by its new command and then overwritten here
+                let handles = blink_handles.read().await;
+                let members: Vec<Led> = handles.iter()
+                    .filter(|(_, handle)| handle.id() == id)
+                    .map(|(&led, _)| led)
+                    .collect();
+                if members.is_empty() {
+                    drop(handles);
+                    shared_blinks.write().await.remove(&id);
+                    return;
+                }
+                let values = members.iter().map(|&led| (led, state)).collect();
+                if let Err(e) = write_led_batch(&backend, values, gpio_timeout).await {
+                    drop(handles);
+                    end_failed_group(&blink_handles, &shared_blinks, &states, &events, members, e).await;
+                    return;
+                }
+                let mut lit = effect_lit.write().await;
+                for &led in &members {
+                    lit.insert(led, state);
+                    events.effect(led, blinking, state);
+                }
+            }
+        });
+
+        shared.insert(handle_task.id());
+        for &led in leds {
+            handles.insert(led, handle_task.abort_handle());
+        }
+
+        Ok(())
+    }
+
+    /// Flicker an LED like a failing fluorescent tube, until it is commanded again
+    /// Each step picks lit or dark at random (weighted by `intensity`) and holds it for 30-300ms.
+    /// With a `seed` the sequence is reproducible. Like a blink, this replaces any running effect on the LED.
+    pub async fn flicker(&self, led: Led, intensity: FlickerIntensity, seed: Option<u64>) -> Result<()> {
+        self.check_available(led)?;
+        self.check_effect_capacity(&[led], 1).await?;
+        self.cancel_blink(led).await?;
+
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let flickering = LedState::Flickering { intensity };
+        let mut states = self.states.write().await;
+        let old = states.insert(led, flickering).unwrap_or(LedState::Off);
+        drop(states);
+        self.effect_lit.write().await.insert(led, old == LedState::On);
+        self.events.command(led, old, flickering, old == LedState::On);
+
+        let backend = Arc::clone(&self.backend);
+        let effect_lit = Arc::clone(&self.effect_lit);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let states = Arc::clone(&self.states);
+
+        let mut handles = self.blink_handles.write().await;
+
+        let handle_task = tokio::spawn(async move {
+            // Unknown until the first write, since a replaced effect may have left the LED either way
+            let mut lit = None;
+            loop {
+                let next = rng.gen_bool(intensity.lit_probability());
+                if lit != Some(next) {
+                    if let Err(e) = write_led(&backend, led, next, gpio_timeout).await {
+                        end_failed_effect(&blink_handles, &states, &events, led, e).await;
+                        return;
+                    }
+                    lit = Some(next);
+                    effect_lit.write().await.insert(led, next);
+                    events.effect(led, flickering, next);
+                }
+                let gap = Duration::from_millis(rng.gen_range(FLICKER_MIN_GAP_MS..=FLICKER_MAX_GAP_MS));
+                tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    _ = tokio::time::sleep(gap) => {}
+                }
+            }
+        });
+
+        handles.insert(led, handle_task.abort_handle());
+
+        Ok(())
+    }
+
+    /// Show `percent` as a bar on one colour's LEDs: the first LEDs proportionally lit, the rest off
+    /// e.g. 50% on the 12 reds lights 6. The count is rounded to the nearest LED, and percentages
+    /// above 100 count as 100. All the colour's LEDs are written in one batch, so a lower value
+    /// never leaves LEDs lit from a previous higher one.
+    pub async fn show_progress(&self, subset: LedColor, percent: u8) -> Result<()> {
+        let leds: Vec<Led> = subset.range().map(Led).collect();
+        self.fill(&leds, percent).await
+    }
+
+    /// Show `percent` as a level meter: greens fill first, then ambers, then reds
+    /// The meter spans every colour range in order, so its scale follows the layout. The LED count
+    /// is rounded, percentages above 100 count as 100, and 0 clears the meter. Only LEDs that
+    /// change are written, in a single batch.
+    pub async fn show_level(&self, percent: u8) -> Result<()> {
+        let leds: Vec<Led> = LedColor::ALL.into_iter().flat_map(|color| color.range()).map(Led).collect();
+        self.fill(&leds, percent).await
+    }
+
+    /// Light the first `percent` (rounded, capped at 100) of `leds` and turn the rest off, in one batch
+    async fn fill(&self, leds: &[Led], percent: u8) -> Result<()> {
+        let lit = (usize::from(percent.min(100)) * leds.len() + 50) / 100;
+        let values: Vec<(Led, bool)> = leds.iter().enumerate().map(|(i, &led)| (led, i < lit)).collect();
+        self.set_values(&values).await
+    }
+
+    /// Set an LED's brightness, from 0 (off) to 255 (fully on)
+    /// Levels in between use the backend's hardware PWM when it has it for this LED (see
+    /// `LedBackend::has_pwm()`); otherwise they are simulated with software PWM: a background task
+    /// lights the LED for `level / 255` of every 10ms period. Like a blink, this replaces any
+    /// running effect on the LED.
+    pub async fn set_brightness(&self, led: Led, level: u8) -> Result<()> {
+        match level {
+            0 => return self.off(led).await,
+            u8::MAX => return self.on(led).await,
+            _ => {}
+        }
+        if self.backend.has_pwm(led.get()) {
+            self.check_available(led)?;
+            self.cancel_blink(led).await?;
+            write_level(&self.backend, led, level, self.gpio_timeout()).await?;
+            self.set_tracked_state(led, LedState::Dimmed { level }).await;
+            return Ok(());
+        }
+        let duty = f64::from(level) / f64::from(u8::MAX);
+        self.start_pwm(led, LedState::Dimmed { level }, false, move |_| Some(duty)).await
+    }
+
+    /// Pulse an LED once: brightness ramps linearly from off to full over `rise_ms`, back to off
+    /// over `fall_ms`, and the LED is left off. Runs in the background using software PWM, replacing
+    /// any running effect on the LED.
+    pub async fn pulse(&self, led: Led, rise_ms: u64, fall_ms: u64) -> Result<()> {
+        if rise_ms == 0 && fall_ms == 0 {
+            return Err(TrainError::InvalidParameter("Pulse rise and fall can't both be 0ms".to_string()));
+        }
+        let (rise, fall) = (rise_ms as f64 / 1000.0, fall_ms as f64 / 1000.0);
+        self.start_pwm(led, LedState::Pulsing { rise_ms, fall_ms }, false, move |elapsed| {
+            let t = elapsed.as_secs_f64();
+            if t < rise {
+                Some(t / rise)
+            } else if t < rise + fall {
+                Some(1.0 - (t - rise) / fall)
+            } else {
+                None
+            }
+        }).await
+    }
+
+    /// Fade an LED from off up to full brightness over `duration_ms`, leaving it on
+    /// Runs in the background using software PWM, replacing any running effect on the LED
+    pub async fn fade_in(&self, led: Led, duration_ms: u64) -> Result<()> {
+        self.fade(led, FadeDirection::In, duration_ms, 0.0).await
+    }
+
+    /// Fade an LED from its current brightness down to off over `duration_ms`, leaving it off
+    /// The starting point is the last commanded brightness: full for an LED that is on, lit
+    /// mid-blink, pulsing or fading, the level of a dimmed LED, the peak of a breathing one.
+    pub async fn fade_out(&self, led: Led, duration_ms: u64) -> Result<()> {
+        let from = match self.state(led).await {
+            LedState::Off => 0,
+            LedState::On | LedState::Pulsing { .. } | LedState::Fading { .. } => u8::MAX,
+            LedState::Dimmed { level } => level,
+            LedState::Breathing { max, .. } => max,
+            LedState::Blinking { .. } | LedState::Flickering { .. } => {
+                if self.is_lit(led).await == Some(true) { u8::MAX } else { 0 }
+            }
+        };
+        self.fade(led, FadeDirection::Out, duration_ms, f64::from(from) / f64::from(u8::MAX)).await
+    }
+
+    /// Fade an LED in or out over `duration_ms`, fading out from duty cycle `from`
+    async fn fade(&self, led: Led, direction: FadeDirection, duration_ms: u64, from: f64) -> Result<()> {
+        let end = direction == FadeDirection::In;
+        if duration_ms == 0 {
+            return if end { self.on(led).await } else { self.off(led).await };
+        }
+        let duration = duration_ms as f64 / 1000.0;
+        self.start_pwm(led, LedState::Fading { direction, duration_ms }, end, move |elapsed| {
+            let progress = elapsed.as_secs_f64() / duration;
+            (progress < 1.0).then_some(if end { progress } else { from * (1.0 - progress) })
+        }).await
+    }
+
+    /// Breathe an LED: brightness rises and falls smoothly between `min` and `max` (0-255) every `period_ms`
+    /// Brightness is simulated with software PWM and gamma corrected. Like a blink, this replaces any
+    /// running effect on the LED and runs until the LED is commanded again.
+    pub async fn breathe(&self, led: Led, period_ms: u64, min: u8, max: u8) -> Result<()> {
+        Self::check_breathe(period_ms, min, max)?;
+        let period = Duration::from_millis(period_ms).as_secs_f64();
+        self.start_pwm(led, LedState::Breathing { period_ms, min, max }, false, move |elapsed| {
+            // Raised cosine: min at the start of each period, max halfway through
+            let swing = (1.0 - (elapsed.as_secs_f64() / period * std::f64::consts::TAU).cos()) / 2.0;
+            Some(duty_cycle(f64::from(min) + f64::from(max - min) * swing))
+        }).await
+    }
+
+    /// Start a software PWM task tracked as `state`, running each period at `duty_at(time since start)`
+    /// When `duty_at` returns `None` the LED is left steadily on (`end`) or off and the task ends.
+    /// Replaces any running effect on the LED; a failed write ends the task
+    async fn start_pwm(
+        &self,
+        led: Led,
+        state: LedState,
+        end: bool,
+        duty_at: impl Fn(Duration) -> Option<f64> + Send + 'static,
+    ) -> Result<()> {
+        self.check_available(led)?;
+        self.check_effect_capacity(&[led], 1).await?;
+        self.cancel_blink(led).await?;
+
+        let mut states = self.states.write().await;
+        let old = states.insert(led, state).unwrap_or(LedState::Off);
+        drop(states);
+        self.events.command(led, old, state, old == LedState::On);
+
+        let backend = Arc::clone(&self.backend);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let states = Arc::clone(&self.states);
+
+        let mut handles = self.blink_handles.write().await;
+
+        let handle_task = tokio::spawn(async move {
+            let start = Instant::now();
+            let mut lit = None;
+            while let Some(duty) = duty_at(start.elapsed()) {
+                let period = tokio::select! {
+                    _ = shutdown.cancelled() => return,
+                    period = pwm_period(&backend, led, duty, gpio_timeout, &mut lit) => period,
+                };
+                if let Err(e) = period {
+                    end_failed_effect(&blink_handles, &states, &events, led, e).await;
+                    return;
+                }
+            }
+
+            // Finished: leave the LED at its end value and remove our own entry,
+            // unless a newer effect has replaced it
+            if lit != Some(end)
+                && let Err(e) = write_led(&backend, led, end, gpio_timeout).await
+            {
+                end_failed_effect(&blink_handles, &states, &events, led, e).await;
+                return;
+            }
+            let mut handles = blink_handles.write().await;
+            if handles.get(&led).is_none_or(|handle| handle.id() != tokio::task::id()) {
+                return;
+            }
+            handles.remove(&led);
+            drop(handles);
+
+            let mut states = states.write().await;
+            if states.get(&led) == Some(&state) {
+                let end_state = if end { LedState::On } else { LedState::Off };
+                states.insert(led, end_state);
+                events.command(led, state, end_state, end);
+            }
+        });
+
+        handles.insert(led, handle_task.abort_handle());
+
+        Ok(())
+    }
+
+    /// Run a traffic light: green, then amber, then red, one lamp lit at a time, repeating
+    /// `timings_ms` is how long each lamp stays lit (green, amber, red).
+    /// Returns once the cycle has started. It runs until one of the three LEDs is commanded
+    /// directly (which stops the whole cycle, leaving the other lamps as they were),
+    /// `all_off()`, or shutdown.
+    pub async fn traffic_cycle(&self, green_led: Led, amber_led: Led, red_led: Led, timings_ms: (u64, u64, u64)) -> Result<()> {
+        let durations = [timings_ms.0, timings_ms.1, timings_ms.2];
+        if durations.contains(&0) {
+            return Err(TrainError::InvalidParameter("Traffic light timings must be greater than 0".to_string()));
+        }
+        self.start_aspects([green_led, amber_led, red_led], vec![
+            ([true, false, false], durations[0]),
+            ([false, true, false], durations[1]),
+            ([false, false, true], durations[2]),
+        ]).await
+    }
+
+    /// Run a railway-style signal: red, red + amber, green, amber, then red again, repeating
+    /// The lamps are the LEDs at `green_pos`, `amber_pos` and `red_pos` within their colour banks
+    /// (1-based). One full cycle takes `cycle_secs`: red and green each get 3/8 of it, the two
+    /// amber aspects 1/8 each. Stops like `traffic_cycle()`.
+    pub async fn traffic_light(&self, green_pos: u8, amber_pos: u8, red_pos: u8, cycle_secs: u64) -> Result<()> {
+        let green = Self::get_led_from_subset(GREEN_LEDS, green_pos)?;
+        let amber = Self::get_led_from_subset(AMBER_LEDS, amber_pos)?;
+        let red = Self::get_led_from_subset(RED_LEDS, red_pos)?;
+        if cycle_secs == 0 {
+            return Err(TrainError::InvalidParameter("Traffic light cycle must be at least 1 second".to_string()));
+        }
+        let eighth_ms = cycle_secs * 1000 / 8;
+        self.start_aspects([green, amber, red], vec![
+            ([false, false, true], 3 * eighth_ms),
+            ([false, true, true], eighth_ms),
+            ([true, false, false], 3 * eighth_ms),
+            ([false, true, false], eighth_ms),
+        ]).await
+    }
+
+    /// Start a task cycling three distinct lamps through `aspects`: which lamps are lit, and for how long
+    async fn start_aspects(&self, lamps: [Led; 3], aspects: Vec<([bool; 3], u64)>) -> Result<()> {
+        let [green_led, amber_led, red_led] = lamps;
+        if green_led == amber_led || amber_led == red_led || green_led == red_led {
+            return Err(TrainError::InvalidParameter(
+                format!("Traffic light LEDs must be distinct, got {}, {} and {}", green_led, amber_led, red_led)
+            ));
+        }
+        for led in lamps {
+            self.check_available(led)?;
+        }
+        self.check_effect_capacity(&lamps, 1).await?;
+        for led in lamps {
+            self.cancel_blink(led).await?;
+        }
+
+        let backend = Arc::clone(&self.backend);
+        let gpio_timeout = self.gpio_timeout();
+        let events = Arc::clone(&self.events);
+        let shutdown = self.shutdown.clone();
+        let blink_handles = Arc::clone(&self.blink_handles);
+        let states = Arc::clone(&self.states);
+
+        let mut handles = self.blink_handles.write().await;
+
+        let handle_task = tokio::spawn(async move {
+            loop {
+                for (lit, duration) in &aspects {
+                    for (led, &on) in lamps.into_iter().zip(lit) {
+                        let state = if on { LedState::On } else { LedState::Off };
+                        if states.read().await.get(&led) == Some(&state) {
+                            continue;
+                        }
+                        // Stops the whole cycle; the other lamps keep their last state
+                        if let Err(e) = write_led(&backend, led, on, gpio_timeout).await {
+                            end_failed_effect(&blink_handles, &states, &events, led, e).await;
+                            return;
+                        }
+                        states.write().await.insert(led, state);
+                        events.effect(led, state, on);
+                    }
+                    tokio::select! {
+                        _ = shutdown.cancelled() => return,
+                        _ = tokio::time::sleep(Duration::from_millis(*duration)) => {}
+                    }
+                }
+            }
+        });
+
+        for led in lamps {
+            handles.insert(led, handle_task.abort_handle());
+        }
+
+        Ok(())
+    }
+
+    /// Like `blink()`, but fails with `Busy` instead of overriding an LED that is already blinking
+    pub async fn blink_if_idle(&self, led: Led, frequency_ms: u64) -> Result<()> {
+        if let LedState::Blinking { frequency_ms: current } = self.state(led).await {
+            return Err(TrainError::Busy(
+                format!("LED {} is already blinking every {}ms", led, current)
+            ));
+        }
+        self.blink(led, frequency_ms).await
+    }
+
+    /// Stop a blinking LED, leaving it off
+    /// Fails with `BlinkNotActive` if the LED isn't blinking
+    pub async fn stop_blink(&self, led: Led) -> Result<()> {
+        if !matches!(self.state(led).await, LedState::Blinking { .. }) {
+            return Err(TrainError::BlinkNotActive(led.get()));
+        }
+        self.cancel_blink(led).await?;
+        self.write(led, false).await?;
+        self.set_tracked_state(led, LedState::Off).await;
+        Ok(())
+    }
+
+    /// Number of background effect tasks running; a blink shared by several LEDs counts once
+    pub async fn active_effects(&self) -> usize {
+        let handles = self.blink_handles.read().await;
+        handles.values()
+            .filter(|handle| !handle.is_finished())
+            .map(AbortHandle::id)
+            .collect::<HashSet<TaskId>>()
+            .len()
+    }
+
+    /// Fail with `TooManyEffects` unless `new_tasks` more effect tasks fit under `max_effects`,
+    /// once the tasks that only drive LEDs in `replacing` have been cancelled
+    /// Entries left behind by tasks that have already finished are dropped here, so they
+    /// never count against the limit.
+    async fn check_effect_capacity(&self, replacing: &[Led], new_tasks: usize) -> Result<()> {
+        let mut handles = self.blink_handles.write().await;
+        handles.retain(|_, handle| !handle.is_finished());
+        self.shared_blinks.write().await.retain(|id| handles.values().any(|handle| handle.id() == *id));
+        // Task -> whether it keeps running after `replacing` is taken over
+        let mut survives: HashMap<TaskId, bool> = HashMap::new();
+        for (led, handle) in handles.iter() {
+            *survives.entry(handle.id()).or_default() |= !replacing.contains(led);
+        }
+        let running = survives.values().filter(|&&survives| survives).count();
+        if running + new_tasks > self.config.max_effects {
+            return Err(TrainError::TooManyEffects { max: self.config.max_effects });
+        }
+        Ok(())
+    }
+
+    /// Cancel blinking (or any other effect) for a specific LED, including the heartbeat if it runs on that LED
+    /// An effect driving several LEDs is stopped on all of them, except a shared blink, which
+    /// just loses this LED and keeps blinking the others.
+    /// The LED's physical state is unknown afterwards, so its tracked state is left for the caller to set
+    async fn cancel_blink(&self, led: Led) -> Result<()> {
+        let mut handles = self.blink_handles.write().await;
+        if let Some(handle) = handles.remove(&led) {
+            let mut shared = self.shared_blinks.write().await;
+            let detach = shared.contains(&handle.id()) && handles.values().any(|other| other.id() == handle.id());
+            if !detach {
+                handle.abort();
+                handles.retain(|_, other| other.id() != handle.id());
+                shared.remove(&handle.id());
+            }
+        }
+        self.expiries.write().await.remove(&led);
+        drop(handles);
+
+        if self.heartbeat_led().await == Some(led) {
+            self.stop_heartbeat().await?;
+        }
+        Ok(())
+    }
+
+    /// Start a liveness heartbeat on an LED: a short double-blip every two seconds
+    /// Refuses to start on an LED that isn't off. Commanding the LED directly (on/off/blink/release)
+    /// or calling `all_off()` stops the heartbeat; it is not resumed automatically.
+    /// Replaces any heartbeat already running on another LED.
+    pub async fn start_heartbeat(&self, led: Led) -> Result<()> {
+        self.check_available(led)?;
+        if self.heartbeat_led().await != Some(led) && self.state(led).await != LedState::Off {
+            return Err(TrainError::InvalidParameter(
+                format!("LED {} is in use and can't run the heartbeat", led)
+            ));
+        }
+        self.stop_heartbeat().await?;
+
+        let backend = Arc::clone(&self.backend);
+        let gpio_timeout = self.gpio_timeout();
+        let shutdown = self.shutdown.clone();
+
+        let handle_task = tokio::spawn(async move {
+            let mut period = interval(HEARTBEAT_PERIOD);
+            period.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = period.tick() => {}
+                }
+                for on in [true, false, true, false] {
+                    let _ = write_led(&backend, led, on, gpio_timeout).await;
+                    tokio::time::sleep(HEARTBEAT_BLIP).await;
+                }
+            }
+        });
+
+        *self.heartbeat.write().await = Some(Heartbeat { led, task: handle_task });
+        Ok(())
+    }
+
+    /// Stop the heartbeat, leaving its LED off
+    /// Does nothing if no heartbeat is running
+    pub async fn stop_heartbeat(&self) -> Result<()> {
+        let Some(Heartbeat { led, task }) = self.heartbeat.write().await.take() else {
+            return Ok(());
+        };
+        task.abort();
+        if self.check_available(led).is_ok() {
+            self.write(led, false).await?;
+        }
+        Ok(())
+    }
+
+    /// LED currently running the heartbeat, if any
+    pub async fn heartbeat_led(&self) -> Option<Led> {
+        self.heartbeat.read().await.as_ref().map(|heartbeat| heartbeat.led)
+    }
+
+    /// Error unless `state` has parameters `set_state()` would accept
+    fn check_state(&self, state: LedState) -> Result<()> {
+        match state {
+            LedState::Blinking { frequency_ms } => self.check_frequency(frequency_ms),
+            LedState::Breathing { period_ms, min, max } => Self::check_breathe(period_ms, min, max),
+            LedState::Pulsing { rise_ms: 0, fall_ms: 0 } => {
+                Err(TrainError::InvalidParameter("Pulse rise and fall can't both be 0ms".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Put the LEDs into their `LedConfig::initial` states, skipping any that can't be driven
+    /// Called by `with_persistence()` when there's no saved state; without persistence, call it
+    /// straight after construction.
+    pub async fn apply_initial(&self) -> Result<()> {
+        let changes: Vec<(Led, LedState)> = Led::all()
+            .zip(self.config.initial.iter().copied())
+            .filter(|&(led, _)| self.check_available(led).is_ok())
+            .collect();
+        self.apply(&changes).await
+    }
+
+    /// Put several LEDs into the given states, leaving every other LED as it is
+    /// All of the changes are validated before any LED is touched, so one bad entry rejects the lot.
+    pub async fn apply(&self, changes: &[(Led, LedState)]) -> Result<()> {
+        for (i, &(led, state)) in changes.iter().enumerate() {
+            if changes[..i].iter().any(|&(other, _)| other == led) {
+                return Err(TrainError::InvalidParameter(format!("LED {} is listed more than once", led)));
+            }
+            self.check_available(led)?;
+            self.check_state(state)?;
+        }
+        for &(led, state) in changes {
+            self.set_state(led, state).await?;
+        }
+        Ok(())
+    }
+
+    /// Put a specific LED into the given state
+    pub async fn set_state(&self, led: Led, state: LedState) -> Result<()> {
+        match state {
+            LedState::On => self.on(led).await,
+            LedState::Off => self.off(led).await,
+            LedState::Blinking { frequency_ms } => self.blink(led, frequency_ms).await,
+            LedState::Flickering { intensity } => self.flicker(led, intensity, None).await,
+            LedState::Breathing { period_ms, min, max } => self.breathe(led, period_ms, min, max).await,
+            LedState::Dimmed { level } => self.set_brightness(led, level).await,
+            LedState::Pulsing { rise_ms, fall_ms } => self.pulse(led, rise_ms, fall_ms).await,
+            LedState::Fading { direction: FadeDirection::In, duration_ms } => self.fade_in(led, duration_ms).await,
+            LedState::Fading { direction: FadeDirection::Out, duration_ms } => self.fade_out(led, duration_ms).await,
+        }
+    }
+
+    /// Get the state of every LED, sorted by LED number
+    /// Taken under a single read lock, so the result is a consistent view of the panel
+    pub async fn get_all_states(&self) -> Vec<(Led, LedState)> {
+        let states = self.states.read().await;
+        let mut all: Vec<(Led, LedState)> = states.iter()
+            .map(|(&led, &state)| (led, state))
+            .collect();
+        all.sort_by_key(|&(led, _)| led);
+        all
+    }
+
+    /// Capture the current state of every LED, ordered by LED number
+    pub async fn snapshot(&self) -> LedSnapshot {
+        snapshot_of(&self.states, &self.aliases, &self.groups, &self.schedules).await
+    }
+
+    /// Re-apply a snapshot taken with `snapshot()`, restarting blinks at their saved frequencies
+    /// Aliases, groups and pending schedules are replaced by the snapshot's.
+    /// The whole snapshot is validated before any LED is changed; unavailable or released LEDs are skipped.
+    /// Blinks running beforehand are cancelled first; LEDs they drove that the snapshot doesn't mention are left off.
+    pub async fn restore(&self, snapshot: &LedSnapshot) -> Result<()> {
+        for entry in &snapshot.leds {
+            self.check_state(entry.state)?;
+        }
+        for name in snapshot.aliases.keys() {
+            Self::check_alias(name)?;
+        }
+        for (name, members) in &snapshot.groups {
+            Self::check_group(name, members)?;
+        }
+        let mut aliases: HashMap<String, Alias> = snapshot.aliases.iter()
+            .map(|(name, &led)| (name.clone(), Alias { led, label: false }))
+            .collect();
+        for entry in &snapshot.leds {
+            let Some(name) = &entry.name else { continue };
+            Self::check_name(name)?;
+            if let Some(other) = aliases.get(name).filter(|other| other.label || other.led != entry.led) {
+                return Err(TrainError::InvalidParameter(
+                    format!("Name '{}' is given to both LED {} and LED {}", name, other.led, entry.led)
+                ));
+            }
+            aliases.insert(name.clone(), Alias { led: entry.led, label: true });
+        }
+
+        *self.aliases.write().await = aliases;
+        *self.groups.write().await = snapshot.groups.iter()
+            .map(|(name, members)| (name.clone(), Self::group_order(members.clone())))
+            .collect();
+        self.schedules.replace(snapshot.schedules.clone()).await;
+        self.labels_changed.notify_one();
+
+        let mut handles = self.blink_handles.write().await;
+        let cancelled: Vec<Led> = handles
+            .drain()
+            .map(|(led, handle)| {
+                handle.abort();
+                led
+            })
+            .collect();
+        self.shared_blinks.write().await.clear();
+        self.expiries.write().await.clear();
+        drop(handles);
+        for led in cancelled {
+            if snapshot.leds.iter().all(|entry| entry.led != led) && self.check_available(led).is_ok() {
+                self.off(led).await?;
+            }
+        }
+
+        for entry in &snapshot.leds {
+            if self.check_available(entry.led).is_err() {
+                continue;
+            }
+            self.set_state(entry.led, entry.state).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Run a single command now
+    pub async fn execute(&self, command: &LedCommand) -> Result<()> {
+        match command {
+            LedCommand::On { led } => self.on(*led).await,
+            LedCommand::Off { led } => self.off(*led).await,
+            LedCommand::Blink { led, frequency_ms } => self.blink(*led, *frequency_ms).await,
+            LedCommand::GroupOn { group } => self.group_on(group).await,
+            LedCommand::GroupOff { group } => self.group_off(group).await,
+            LedCommand::GroupBlink { group, frequency_ms } => self.group_blink(group, *frequency_ms).await,
+            LedCommand::AllOff => self.all_off().await,
+        }
+    }
+
+    /// Run `command` at the wall-clock time `at` (immediately if that has passed)
+    /// Commands only run while `run_scheduler()` is running. Schedules are saved along with the
+    /// LED state when persistence is enabled, and are otherwise lost on restart.
+    pub async fn schedule(&self, at: impl Into<DateTime<Utc>>, command: LedCommand) -> ScheduleId {
+        let id = self.schedules.add(at.into(), command, None).await;
+        self.labels_changed.notify_one();
+        id
+    }
+
+    /// Like `schedule()`, then again every `every` after `at` until cancelled
+    /// Runs missed while the scheduler wasn't running (or the clock jumped) are skipped.
+    pub async fn schedule_every(&self, at: impl Into<DateTime<Utc>>, every: Duration, command: LedCommand) -> Result<ScheduleId> {
+        if every.as_secs() == 0 {
+            return Err(TrainError::InvalidParameter("Schedule interval must be at least 1 second".to_string()));
+        }
+        let id = self.schedules.add(at.into(), command, Some(every)).await;
+        self.labels_changed.notify_one();
+        Ok(id)
+    }
+
+    /// Cancel a command that hasn't run yet, or stop a recurring one
+    pub async fn cancel_schedule(&self, id: ScheduleId) -> Result<()> {
+        if !self.schedules.cancel(id).await {
+            return Err(TrainError::InvalidParameter(format!("No scheduled command {} is pending", id)));
+        }
+        self.labels_changed.notify_one();
+        Ok(())
+    }
+
+    /// Commands waiting to run, soonest first
+    pub async fn list_schedules(&self) -> Vec<ScheduledCommand> {
+        self.schedules.list().await
+    }
+
+    /// Run scheduled commands as they fall due, until shutdown
+    /// Spawn this on a task that shares the controller (e.g. via `Arc`). The wall clock is
+    /// re-read at least once a second, so clock jumps shift commands by no more than that.
+    /// A command that fails is logged and dropped.
+    pub async fn run_scheduler(&self) {
+        loop {
+            let due = self.schedules.take_due(Utc::now()).await;
+            if !due.is_empty() {
+                self.labels_changed.notify_one();
+            }
+            for scheduled in due {
+                if self.is_panicking() {
+                    tracing::warn!(id = %scheduled.id, command = ?scheduled.command, "Scheduled command dropped in panic mode");
+                } else if let Err(e) = self.execute(&scheduled.command).await {
+                    tracing::warn!(id = %scheduled.id, command = ?scheduled.command, error = %e, "Scheduled command failed");
+                }
+            }
+
+            let wait = match self.schedules.next_deadline().await {
+                Some(at) => (at - Utc::now()).to_std().unwrap_or_default().min(schedule::CLOCK_RECHECK),
+                None => schedule::CLOCK_RECHECK,
+            };
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                _ = self.schedules.changed.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+
+    /// Turn all LEDs off and cancel all blinking, including the heartbeat
+    /// LEDs that failed to initialize or have been released are skipped. Every other LED is
+    /// tried even if some writes fail; those are reported together as `PartialFailure`.
+    /// This is also the way out of `panic_mode()`.
+    pub async fn all_off(&self) -> Result<()> {
+        self.panicking.store(false, Ordering::SeqCst);
+        self.clear_all().await
+    }
+
+    /// Safety gesture: stop every operation and effect, turn the greens and ambers off and
+    /// blink every red LED together at `PANIC_BLINK_MS`
+    /// Running operations (like `self_test()`) are cancelled first, so they can't undo it, and
+    /// scheduled commands are dropped until `all_off()` ends panic mode. The red blink is one
+    /// shared task that doesn't count against `max_effects`, so a full effect budget can't stop
+    /// it. Red LEDs that failed to initialize or have been released are skipped.
+    pub async fn panic_mode(&self) -> Result<()> {
+        self.check_frequency(PANIC_BLINK_MS)?;
+        self.panicking.store(true, Ordering::SeqCst);
+        let fresh = self.shutdown.child_token();
+        std::mem::replace(&mut *self.operations.write().await, fresh).cancel();
+        self.clear_all().await?;
+        let reds: Vec<Led> = RED_LEDS.map(Led).filter(|&led| self.check_available(led).is_ok()).collect();
+        if !reds.is_empty() {
+            self.spawn_shared_blink(&reds, PANIC_BLINK_MS).await?;
+        }
+        tracing::warn!("Panic mode: all red LEDs blinking");
+        Ok(())
+    }
+
+    /// Whether `panic_mode()` is in force
+    pub fn is_panicking(&self) -> bool {
+        self.panicking.load(Ordering::SeqCst)
+    }
+
+    /// `all_off()` without leaving panic mode
+    async fn clear_all(&self) -> Result<()> {
+        // Cancel all blinking first
+        let mut handles = self.blink_handles.write().await;
+        for handle in handles.values() {
+            handle.abort();
+        }
+        handles.clear();
+        self.shared_blinks.write().await.clear();
+        self.expiries.write().await.clear();
+        drop(handles);
+        if let Some(heartbeat) = self.heartbeat.write().await.take() {
+            heartbeat.task.abort();
+        }
+
+        // Turn off all LEDs together, so the panel doesn't ripple; the batch is in LED order,
+        // so backends that can't switch everything at once sweep 1-24 rather than scattering
+        let mut states = self.states.write().await;
+        let available: Vec<Led> = Led::all().filter(|&led| self.check_available(led).is_ok()).collect();
+        let failures = self.write_each(available.iter().map(|&led| (led, false)).collect()).await;
+        for led in available {
+            let old = states.insert(led, LedState::Off).unwrap_or(LedState::Off);
+            if let Some((_, error)) = failures.iter().find(|(failed, _)| *failed == led) {
+                self.events.error(led, old, LedState::Off, error.to_string());
+            } else if old != LedState::Off {
+                self.events.command(led, old, LedState::Off, false);
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(TrainError::PartialFailure(failures.into_iter().map(|(led, e)| (led.get(), e.to_string())).collect()))
+        }
+    }
+
+    /// Write a batch, falling back to one LED at a time if the batch fails, so a single bad
+    /// line can't stop the others being switched
+    /// Returns the LEDs that still failed, with their errors
+    async fn write_each(&self, values: Vec<(Led, bool)>) -> Vec<(Led, TrainError)> {
+        if self.write_many(values.clone()).await.is_ok() {
+            return Vec::new();
+        }
+        let mut failures = Vec::new();
+        for (led, on) in values {
+            if let Err(e) = self.write(led, on).await {
+                failures.push((led, e));
+            }
+        }
+        failures
+    }
+
+    /// Walk every LED in order: on for `step_ms`, then off
+    /// Write failures are recorded per LED rather than stopping the walk.
+    /// Cancelling `cancel`, `stop_all()` or `panic_mode()` ends the walk at the next step, marking
+    /// the report `cancelled`. All LEDs are turned off afterwards, even if a write in the middle
+    /// failed - except after `stop_all()` or `panic_mode()`, which set the panel themselves.
+    pub async fn self_test(&self, step_ms: u64, cancel: Option<&CancellationToken>) -> Result<SelfTestReport> {
+        let mut report = SelfTestReport { tested: 0, failures: Vec::new(), cancelled: false };
+        let stop = self.operation_token().await;
+        let cancel = cancel.cloned().unwrap_or_default();
+
+        for led in Led::all() {
+            // Each switch is checked against `stop` under the operations lock, which `stop_all()`
+            // and `panic_mode()` take to cancel it, so nothing is written after they have
+            let operations = self.operations.read().await;
+            if stop.is_cancelled() || cancel.is_cancelled() {
+                report.cancelled = true;
+                break;
+            }
+            report.tested += 1;
+            if let Err(e) = self.on(led).await {
+                report.failures.push(SelfTestFailure { led, error: e.to_string() });
+                continue;
+            }
+            drop(operations);
+
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(step_ms)) => {}
+                _ = stop.cancelled() => {}
+                _ = cancel.cancelled() => {}
+            }
+
+            let _operations = self.operations.read().await;
+            if stop.is_cancelled() {
+                report.cancelled = true;
+                break;
+            }
+            if let Err(e) = self.off(led).await {
+                report.failures.push(SelfTestFailure { led, error: e.to_string() });
+            }
+        }
+
+        let _operations = self.operations.read().await;
+        if !stop.is_cancelled() {
+            self.all_off().await?;
+        }
+        Ok(report)
+    }
+
+    /// Light random LEDs one at a time, each on for `on_ms` then off, `iterations` times
+    /// With a `seed` the sequence of LEDs is reproducible. `stop_all()` or `panic_mode()` ends
+    /// the walk at the next step, leaving the panel as they set it.
+    pub async fn random_walk(&self, iterations: u32, on_ms: u64, seed: Option<u64>) -> Result<()> {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let stop = self.operation_token().await;
+
+        for _ in 0..iterations {
+            // Checked under the operations lock, as in `self_test()`
+            let operations = self.operations.read().await;
+            if stop.is_cancelled() {
+                break;
+            }
+            let led = Led(rng.gen_range(1..=LED_COUNT));
+            self.on(led).await?;
+            drop(operations);
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(on_ms)) => {}
+                _ = stop.cancelled() => {}
+            }
+            let _operations = self.operations.read().await;
+            if stop.is_cancelled() {
+                break;
+            }
+            self.off(led).await?;
+        }
+        Ok(())
+    }
+
+    /// Error unless the name is usable as an alias (and in a URL path)
+    fn check_alias(name: &str) -> Result<()> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(TrainError::InvalidParameter(
+                format!("Alias '{}' must be non-empty and contain only letters, digits, '-' or '_'", name)
+            ));
+        }
+        if name.parse::<u8>().is_ok() {
+            return Err(TrainError::InvalidParameter(
+                format!("Alias '{}' would be mistaken for an LED number", name)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Give an LED a name that can be used in place of its number
+    /// An LED can have several aliases; reusing a name moves it to the new LED, unless it's
+    /// another LED's display name (see `set_name()`)
+    pub async fn set_alias(&self, led: Led, name: String) -> Result<()> {
+        Self::check_alias(&name)?;
+        let mut aliases = self.aliases.write().await;
+        match aliases.get(&name) {
+            Some(existing) if existing.label && existing.led != led => {
+                return Err(TrainError::InvalidParameter(
+                    format!("Name '{}' is already used by LED {}", name, existing.led)
+                ));
+            }
+            // Already this LED's display name
+            Some(existing) if existing.label => return Ok(()),
+            _ => {}
+        }
+        aliases.insert(name, Alias { led, label: false });
+        drop(aliases);
+        self.labels_changed.notify_one();
+        Ok(())
+    }
+
+    /// Error unless the name is usable as an LED label
+    fn check_name(name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            return Err(TrainError::InvalidParameter("LED name must not be empty".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Give an LED a human-readable name, e.g. "Platform 2 home signal red"
+    /// Each LED has at most one name, replacing any previous one. The name is stored as an
+    /// alias, so it also works with `resolve_alias()` (and in API paths, URL-encoded); it must
+    /// not already name another LED, as a display name or an alias.
+    pub async fn set_name(&self, led: Led, name: impl Into<String>) -> Result<()> {
+        let name = name.into();
+        Self::check_name(&name)?;
+        let mut aliases = self.aliases.write().await;
+        if let Some(existing) = aliases.get(&name).filter(|existing| existing.led != led) {
+            return Err(TrainError::InvalidParameter(
+                format!("Name '{}' is already used by LED {}", name, existing.led)
+            ));
+        }
+        aliases.retain(|_, alias| !(alias.label && alias.led == led));
+        aliases.insert(name, Alias { led, label: true });
+        drop(aliases);
+        self.labels_changed.notify_one();
+        Ok(())
+    }
+
+    /// Remove an LED's display name, if it has one
+    pub async fn clear_name(&self, led: Led) {
+        let mut aliases = self.aliases.write().await;
+        let before = aliases.len();
+        aliases.retain(|_, alias| !(alias.label && alias.led == led));
+        if aliases.len() != before {
+            self.labels_changed.notify_one();
+        }
+    }
+
+    /// Display name of an LED, if one has been set
+    pub async fn name(&self, led: Led) -> Option<String> {
+        self.aliases.read().await.iter()
+            .find(|&(_, alias)| alias.label && alias.led == led)
+            .map(|(name, _)| name.clone())
+    }
+
+    /// Find the LED with the given display name or alias (exact match)
+    pub async fn find_by_name(&self, name: &str) -> Option<Led> {
+        self.aliases.read().await.get(name).map(|alias| alias.led)
+    }
+
+    /// Look up the LED number for an alias or display name
+    pub async fn resolve_alias(&self, name: &str) -> Result<Led> {
+        self.find_by_name(name).await
+            .ok_or_else(|| TrainError::InvalidParameter(format!("No LED is named '{}'", name)))
+    }
+
+    /// All aliases, display names included, sorted by name
+    pub async fn aliases(&self) -> BTreeMap<String, Led> {
+        self.aliases.read().await.iter()
+            .map(|(name, alias)| (name.clone(), alias.led))
+            .collect()
+    }
+
+    /// Error unless the name and members make a usable group
+    fn check_group(name: &str, members: &[Led]) -> Result<()> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(TrainError::InvalidParameter(
+                format!("Group name '{}' must be non-empty and contain only letters, digits, '-' or '_'", name)
+            ));
+        }
+        if members.is_empty() {
+            return Err(TrainError::InvalidParameter(format!("Group '{}' has no LEDs", name)));
+        }
+        Ok(())
+    }
+
+    /// Group members in LED order without duplicates
+    fn group_order(mut members: Vec<Led>) -> Vec<Led> {
+        members.sort();
+        members.dedup();
+        members
+    }
+
+    /// Define (or redefine) a named group of LEDs, e.g. "yard" or "crossing_west"
+    /// An LED can belong to any number of groups
+    pub async fn define_group(&self, name: &str, leds: Vec<Led>) -> Result<()> {
+        Self::check_group(name, &leds)?;
+        self.groups.write().await.insert(name.to_string(), Self::group_order(leds));
+        self.labels_changed.notify_one();
+        Ok(())
+    }
+
+    /// All groups and their members, sorted by name
+    pub async fn groups(&self) -> Vec<(String, Vec<Led>)> {
+        let mut groups: Vec<(String, Vec<Led>)> = self.groups.read().await.iter()
+            .map(|(name, members)| (name.clone(), members.clone()))
+            .collect();
+        groups.sort();
+        groups
+    }
+
+    /// Members of a group, after checking that every one of them can be driven
+    async fn group_members(&self, name: &str) -> Result<Vec<Led>> {
+        let members = self.groups.read().await.get(name).cloned()
+            .ok_or_else(|| TrainError::InvalidParameter(format!("No group is named '{}'", name)))?;
+        for &led in &members {
+            self.check_available(led)?;
+        }
+        Ok(members)
+    }
+
+    /// Turn on every LED in a group, together
+    /// Nothing changes unless every member is available
+    pub async fn group_on(&self, name: &str) -> Result<()> {
+        self.set_many(&self.group_members(name).await?, true).await
+    }
+
+    /// Turn off every LED in a group together, stopping any blinks
+    /// Nothing changes unless every member is available
+    pub async fn group_off(&self, name: &str) -> Result<()> {
+        self.set_many(&self.group_members(name).await?, false).await
+    }
+
+    /// Flash every LED in a group together from a single task, like `blink_color()`
+    /// Nothing changes unless the interval is allowed and every member is available
+    pub async fn group_blink(&self, name: &str, frequency_ms: u64) -> Result<()> {
+        self.start_shared_blink(&self.group_members(name).await?, frequency_ms).await
+    }
+
+    /// Members of a zone from the config, in LED order, or `None` if there's no such zone
+    /// Unlike groups, zones are fixed when the controller is built and aren't part of snapshots.
+    pub fn zone(&self, name: &str) -> Option<Vec<Led>> {
+        let members = self.config.zones.get(name)?;
+        Some(Self::group_order(members.iter().filter_map(|&led| Led::new(led).ok()).collect()))
+    }
+
+    /// Names of every configured zone, sorted
+    pub fn zones(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.config.zones.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Members of a zone, after checking that every one of them can be driven
+    fn zone_members(&self, name: &str) -> Result<Vec<Led>> {
+        let members = self.zone(name)
+            .ok_or_else(|| TrainError::InvalidParameter(format!("No zone is named '{}'", name)))?;
+        for &led in &members {
+            self.check_available(led)?;
         }
+        Ok(members)
+    }
 
-        // Cancel any existing blink for this LED
-        self.cancel_blink(led).await?;
-
-        let handles = Arc::clone(&self.handles);
-        let blink_handles = Arc::clone(&self.blink_handles);
-        
-        // Get the handle for this LED
-        let handles_read = handles.read().await;
-        let handle = handles_read.get(&led)
-            .ok_or_else(|| TrainError::InvalidParameter(format!("LED {} not found", led)))?
-            .clone();
-        drop(handles_read);
+    /// Turn on every LED in a zone, together
+    /// Nothing changes unless every member is available
+    pub async fn zone_on(&self, name: &str) -> Result<()> {
+        self.set_many(&self.zone_members(name)?, true).await
+    }
 
-        // Spawn a task to handle blinking
-        let handle_task = tokio::spawn(async move {
-            let mut interval = interval(Duration::from_millis(frequency_ms));
-            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-            let mut state = false;
+    /// Turn off every LED in a zone together, stopping any blinks
+    /// Nothing changes unless every member is available
+    pub async fn zone_off(&self, name: &str) -> Result<()> {
+        self.set_many(&self.zone_members(name)?, false).await
+    }
 
-            loop {
-                interval.tick().await;
-                let handle_guard = handle.lock().await;
-                state = !state;
-                let _ = handle_guard.set_value(if state { 1 } else { 0 });
-            }
-        });
+    /// Flash every LED in a zone together from a single task, like `blink_color()`
+    /// Nothing changes unless the interval is allowed and every member is available
+    pub async fn zone_blink(&self, name: &str, frequency_ms: u64) -> Result<()> {
+        self.start_shared_blink(&self.zone_members(name)?, frequency_ms).await
+    }
 
-        // Store the handle
-        let mut handles_write = blink_handles.write().await;
-        handles_write.insert(led, handle_task);
+    /// Turn on every LED of one colour, together
+    pub async fn color_group_on(&self, color: LedColor) -> Result<()> {
+        self.color(color.range()).on().await
+    }
 
-        Ok(())
+    /// Turn off every LED of one colour together, stopping any blinks
+    pub async fn color_group_off(&self, color: LedColor) -> Result<()> {
+        self.color(color.range()).off().await
     }
 
-    /// Cancel blinking for a specific LED
-    async fn cancel_blink(&self, led: u8) -> Result<()> {
-        let mut handles = self.blink_handles.write().await;
-        if let Some(handle) = handles.remove(&led) {
-            handle.abort();
-        }
-        Ok(())
+    /// Blink every LED of one colour at the same interval, in phase; see `blink_color()`
+    /// Nothing changes if the interval isn't allowed
+    pub async fn color_group_blink(&self, color: LedColor, frequency_ms: u64) -> Result<()> {
+        self.blink_color(color, frequency_ms).await
     }
 
-    /// Turn all LEDs off and cancel all blinking
-    pub async fn all_off(&self) -> Result<()> {
-        // Cancel all blinking first
-        let mut handles = self.blink_handles.write().await;
-        for handle in handles.values() {
-            handle.abort();
-        }
-        handles.clear();
-        drop(handles);
+    /// Flash every LED of one colour together from a single task, so they stay in phase
+    /// Commanding one member afterwards (on, off, another effect) detaches just that LED; the
+    /// rest of the colour keeps blinking. `stop_color_blink()` or `all_off()` stops the group.
+    pub async fn blink_color(&self, color: LedColor, frequency_ms: u64) -> Result<()> {
+        let leds: Vec<Led> = color.range().map(Led).collect();
+        self.start_shared_blink(&leds, frequency_ms).await
+    }
 
-        // Turn off all LEDs
-        let handles_read = self.handles.read().await;
-        for (led, handle) in handles_read.iter() {
-            let handle_guard = handle.lock().await;
-            handle_guard.set_value(0)
-                .map_err(|e| TrainError::GPIO(format!("Failed to turn off LED {}: {}", led, e)))?;
+    /// Stop every blinking LED of one colour, leaving them off
+    /// Members that aren't blinking are left alone.
+    pub async fn stop_color_blink(&self, color: LedColor) -> Result<()> {
+        let mut blinking = Vec::new();
+        for led in color.range().map(Led) {
+            if matches!(self.state(led).await, LedState::Blinking { .. }) {
+                blinking.push(led);
+            }
         }
-
-        Ok(())
+        self.set_many(&blinking, false).await
     }
 
     /// Get the number of LEDs
@@ -178,9 +2434,20 @@ impl LedController {
         LED_COUNT as usize
     }
 
+    /// Physical wiring of every LED, ordered by LED number
+    pub fn mapping(&self) -> Vec<LedMapping> {
+        Led::all()
+            .filter_map(|led| {
+                let (chip, gpio) = self.config.gpio_line(led.get()).ok()?;
+                let (color, position) = LedColor::of(led)?;
+                Some(LedMapping { led, chip: chip.to_string(), gpio, color, position })
+            })
+            .collect()
+    }
+
     /// Check if an LED number is valid (1-24)
     pub fn is_valid_led(&self, led: u8) -> bool {
-        led >= 1 && led <= LED_COUNT
+        Led::new(led).is_ok()
     }
 
     /// Get the actual LED number from a color subset and position (1-based)
@@ -190,26 +2457,24 @@ impl LedController {
     /// * `position` - Position within the subset (1-based, e.g., 1 = first LED in subset)
     /// 
     /// # Returns
-    /// The actual LED number (1-24)
+    /// The actual LED
     /// 
     /// # Example
-    /// ```
+    /// ```ignore
     /// // Get the 2nd red LED (LED 14)
-    /// let led = get_led_from_subset(RED_LEDS, 2); // Returns 14
+    /// let led = get_led_from_subset(RED_LEDS, 2); // Returns LED 14
     /// ```
-    fn get_led_from_subset(subset: std::ops::RangeInclusive<u8>, position: u8) -> Result<u8> {
+    fn get_led_from_subset(subset: std::ops::RangeInclusive<u8>, position: u8) -> Result<Led> {
         let start = *subset.start();
         let end = *subset.end();
         let count = end - start + 1;
         
-        if position < 1 || position > count {
-            return Err(TrainError::InvalidParameter(
-                format!("Position {} is out of range for subset (1-{})", position, count)
-            ));
+        if !(1..=count).contains(&position) {
+            return Err(TrainError::PositionOutOfRange { position, max: count });
         }
         
         // Position is 1-based, so subtract 1 to get 0-based offset
-        Ok(start + position - 1)
+        Led::new(start + position - 1)
     }
 
     /// Set LED state by color subset and position
@@ -217,10 +2482,10 @@ impl LedController {
     /// # Arguments
     /// * `subset` - The LED color range (GREEN_LEDS, AMBER_LEDS, or RED_LEDS)
     /// * `position` - Position within the subset (1-based, e.g., 1 = first LED in subset)
-    /// * `state` - LED state (On or Off)
+    /// * `state` - LED state (On, Off, or any effect such as Blinking)
     /// 
     /// # Example
-    /// ```
+    /// ```ignore
     /// // Turn off the 2nd red LED (LED 14)
     /// controller.set_led_by_color(RED_LEDS, 2, LedState::Off).await?;
     /// 
@@ -234,16 +2499,13 @@ impl LedController {
         state: LedState,
     ) -> Result<()> {
         let led = Self::get_led_from_subset(subset, position)?;
-        match state {
-            LedState::On => self.on(led).await,
-            LedState::Off => self.off(led).await,
-        }
+        self.set_state(led, state).await
     }
 
     /// Turn on a LED by color subset and position
     /// 
     /// # Example
-    /// ```
+    /// ```ignore
     /// // Turn on the 2nd amber LED (LED 8)
     /// controller.green_on(2).await?;
     /// ```
@@ -279,7 +2541,7 @@ impl LedController {
     /// Blink a LED by color subset and position
     /// 
     /// # Example
-    /// ```
+    /// ```ignore
     /// // Blink the 1st red LED (LED 13) at 500ms interval
     /// controller.blink_by_color(RED_LEDS, 1, 500).await?;
     /// ```
@@ -293,3 +2555,924 @@ impl LedController {
         self.blink(led, frequency_ms).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::config::LedPin;
+    use std::time::SystemTime;
+
+    fn mock_controller() -> (Arc<MockBackend>, LedController) {
+        let backend = Arc::new(MockBackend::new());
+        let controller = LedController::with_backend(backend.clone());
+        (backend, controller)
+    }
+
+    fn led(n: u8) -> Led {
+        Led::new(n).unwrap()
+    }
+
+    #[test]
+    fn led_rejects_out_of_range_numbers() {
+        assert!(matches!(Led::new(0), Err(TrainError::LedOutOfRange { led: 0, .. })));
+        assert!(matches!(Led::try_from(LED_COUNT + 1), Err(TrainError::LedOutOfRange { .. })));
+        assert_eq!(Led::try_from(LED_COUNT).unwrap().get(), LED_COUNT);
+        assert!(serde_json::from_str::<Led>("25").is_err());
+    }
+
+    #[tokio::test]
+    async fn repeated_on_writes_once() {
+        let (backend, leds) = mock_controller();
+
+        leds.on(led(5)).await.unwrap();
+        leds.on(led(5)).await.unwrap();
+
+        assert_eq!(backend.writes(), vec![(5, true)]);
+        assert_eq!(leds.state(led(5)).await, LedState::On);
+    }
+
+    #[tokio::test]
+    async fn blink_count_finishes_off_and_cleans_up() {
+        let (backend, leds) = mock_controller();
+
+        leds.blink_count(led(3), 20, 2).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(backend.writes(), vec![(3, true), (3, false), (3, true), (3, false)]);
+        assert_eq!(leds.state(led(3)).await, LedState::Off);
+        assert!(leds.blink_handles.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn self_test_records_failures_and_leaves_leds_off() {
+        let (backend, leds) = mock_controller();
+        leds.release(led(7)).await.unwrap();
+
+        let report = leds.self_test(0, None).await.unwrap();
+
+        assert_eq!(report.tested, LED_COUNT as usize);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].led, led(7));
+        assert!(backend.writes().contains(&(24, true)));
+        assert!(leds.get_all_states().await.iter().all(|&(_, state)| state == LedState::Off));
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trips_through_restore() {
+        let (_, leds) = mock_controller();
+        leds.on(led(2)).await.unwrap();
+        leds.blink(led(14), 500).await.unwrap();
+
+        let snapshot = leds.snapshot().await;
+        let json = serde_json::to_string(&snapshot).unwrap();
+
+        let (_, restored) = mock_controller();
+        restored.restore(&serde_json::from_str(&json).unwrap()).await.unwrap();
+
+        assert_eq!(restored.snapshot().await, snapshot);
+        assert_eq!(restored.state(led(14)).await, LedState::Blinking { frequency_ms: 500 });
+    }
+
+    #[tokio::test]
+    async fn persisted_state_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("train-persist-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (_, leds) = mock_controller();
+        let leds = leds.with_persistence(&path).await;
+        leds.on(led(4)).await.unwrap();
+        leds.blink(led(20), 300).await.unwrap();
+        tokio::time::sleep(persist::PERSIST_INTERVAL + Duration::from_millis(300)).await;
+
+        let (_, restarted) = mock_controller();
+        let restarted = restarted.with_persistence(&path).await;
+        assert_eq!(restarted.state(led(4)).await, LedState::On);
+        assert_eq!(restarted.state(led(20)).await, LedState::Blinking { frequency_ms: 300 });
+
+        // The saved state wins over the configured initial state
+        let config = LedConfig { initial: vec![LedState::On, LedState::Off, LedState::On], ..LedConfig::default() };
+        let configured = LedController::with_backend_and_config(Arc::new(MockBackend::new()), config.clone());
+        let configured = configured.with_persistence(&path).await;
+        assert_eq!(configured.state(led(1)).await, LedState::Off);
+        assert_eq!(configured.state(led(4)).await, LedState::On);
+
+        std::fs::write(&path, "not json").unwrap();
+        let (_, corrupt) = mock_controller();
+        let corrupt = corrupt.with_persistence(&path).await;
+        assert_eq!(corrupt.state(led(4)).await, LedState::Off);
+        let configured = LedController::with_backend_and_config(Arc::new(MockBackend::new()), config);
+        let configured = configured.with_persistence(&path).await;
+        assert_eq!((configured.state(led(1)).await, configured.state(led(3)).await), (LedState::On, LedState::On));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn names_are_unique_and_snapshotted() {
+        let (_, leds) = mock_controller();
+        leds.set_name(led(14), "Platform 2 home signal red").await.unwrap();
+        assert!(leds.set_name(led(15), "Platform 2 home signal red").await.is_err());
+
+        assert_eq!(leds.find_by_name("Platform 2 home signal red").await, Some(led(14)));
+        assert_eq!(leds.name(led(15)).await, None);
+
+        // One store: display names resolve as aliases, aliases are found by name, and neither
+        // can take a name the other already gave to a different LED
+        assert_eq!(leds.resolve_alias("Platform 2 home signal red").await.unwrap(), led(14));
+        leds.set_alias(led(15), "p2_distant".to_string()).await.unwrap();
+        assert_eq!(leds.find_by_name("p2_distant").await, Some(led(15)));
+        assert!(leds.set_name(led(16), "p2_distant").await.is_err());
+        leds.set_name(led(14), "p2_home").await.unwrap();
+        assert!(leds.set_alias(led(16), "p2_home".to_string()).await.is_err());
+        assert_eq!(leds.find_by_name("Platform 2 home signal red").await, None);
+        assert_eq!(leds.aliases().await.len(), 2);
+        leds.set_name(led(14), "Platform 2 home signal red").await.unwrap();
+
+        let (_, restored) = mock_controller();
+        restored.restore(&leds.snapshot().await).await.unwrap();
+        assert_eq!(restored.name(led(14)).await.as_deref(), Some("Platform 2 home signal red"));
+        assert_eq!(restored.find_by_name("p2_distant").await, Some(led(15)));
+        assert_eq!(restored.aliases().await, leds.aliases().await);
+    }
+
+    #[tokio::test]
+    async fn restore_cancels_running_blinks() {
+        let (_, leds) = mock_controller();
+        let snapshot = leds.snapshot().await;
+        leds.blink(led(3), 500).await.unwrap();
+        leds.blink(led(9), 500).await.unwrap();
+
+        let partial = LedSnapshot {
+            leds: snapshot.leds.into_iter().filter(|entry| entry.led != led(9)).collect(),
+            aliases: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            schedules: Vec::new(),
+        };
+        leds.restore(&partial).await.unwrap();
+
+        assert_eq!(leds.state(led(3)).await, LedState::Off);
+        assert_eq!(leds.state(led(9)).await, LedState::Off);
+        assert!(leds.blink_handles.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn groups_validate_before_applying_and_are_snapshotted() {
+        let (backend, leds) = mock_controller();
+        leds.define_group("yard", vec![led(3), led(1), led(2)]).await.unwrap();
+        leds.define_group("crossing_west", vec![led(2), led(9)]).await.unwrap();
+        assert!(leds.define_group("empty", Vec::new()).await.is_err());
+        assert!(leds.define_group("bad name", vec![led(1)]).await.is_err());
+
+        leds.group_on("yard").await.unwrap();
+        assert_eq!(backend.writes(), vec![(1, true), (2, true), (3, true)]);
+
+        // A bad interval or a released member stops the whole group before any write
+        assert!(matches!(leds.group_blink("crossing_west", 1).await, Err(TrainError::InvalidFrequency { .. })));
+        leds.group_blink("yard", 100).await.unwrap();
+        let task = leds.blink_handles.read().await.get(&led(1)).unwrap().id();
+        for n in [2, 3] {
+            assert_eq!(leds.blink_handles.read().await.get(&led(n)).unwrap().id(), task);
+        }
+        assert_eq!(leds.active_effects().await, 1);
+        leds.group_on("yard").await.unwrap();
+        leds.release(led(9)).await.unwrap();
+        assert!(matches!(leds.group_off("crossing_west").await, Err(TrainError::LedReleased(9))));
+        assert_eq!(leds.state(led(2)).await, LedState::On);
+
+        let (_, restored) = mock_controller();
+        restored.restore(&leds.snapshot().await).await.unwrap();
+        assert_eq!(restored.groups().await, vec![
+            ("crossing_west".to_string(), vec![led(2), led(9)]),
+            ("yard".to_string(), vec![led(1), led(2), led(3)]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn on_for_refreshes_one_timer_and_off_cancels_it() {
+        let (backend, leds) = mock_controller();
+
+        leds.on_for(led(6), 200).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        leds.on_for(led(6), 200).await.unwrap();
+        assert!(leds.expires_in(led(6)).await.unwrap() > Duration::from_millis(150));
+
+        // The first timer was replaced, so the LED is still on after it would have fired
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(leds.state(led(6)).await, LedState::On);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(leds.state(led(6)).await, LedState::Off);
+        assert_eq!(leds.expires_in(led(6)).await, None);
+        assert_eq!(backend.writes(), vec![(6, true), (6, false)]);
+
+        leds.on_for(led(7), 100).await.unwrap();
+        leds.off(led(7)).await.unwrap();
+        leds.on(led(7)).await.unwrap();
+        assert_eq!(leds.expires_in(led(7)).await, None);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(leds.state(led(7)).await, LedState::On);
+    }
+
+    #[tokio::test]
+    async fn stop_all_cancels_the_self_test_at_the_next_step() {
+        let (_, leds) = mock_controller();
+        leds.blink(led(20), 100).await.unwrap();
+
+        let (report, stopped) = tokio::join!(leds.self_test(50, None), async {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            leds.stop_all().await
+        });
+        stopped.unwrap();
+        let report = report.unwrap();
+
+        assert!(report.cancelled);
+        assert!(report.tested < 5, "walked {} LEDs", report.tested);
+        assert!(leds.get_all_states().await.iter().all(|&(_, state)| state == LedState::Off));
+        assert!(leds.blink_handles.read().await.is_empty());
+
+        // Later operations aren't affected
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert_eq!(leds.self_test(0, Some(&cancel)).await.unwrap().tested, 0);
+        assert!(!leds.self_test(0, None).await.unwrap().cancelled);
+    }
+
+    #[tokio::test]
+    async fn scheduled_commands_run_when_due() {
+        let (_, leds) = mock_controller();
+        leds.define_group("amber", vec![led(7), led(8)]).await.unwrap();
+        let now = SystemTime::now();
+
+        let late = leds.schedule(now + Duration::from_secs(3600), LedCommand::Off { led: led(1) }).await;
+        leds.schedule(now + Duration::from_millis(100), LedCommand::GroupOn { group: "amber".to_string() }).await;
+        leds.schedule(now - Duration::from_secs(1), LedCommand::On { led: led(1) }).await;
+        let cancelled = leds.schedule(now + Duration::from_millis(50), LedCommand::On { led: led(2) }).await;
+        leds.cancel_schedule(cancelled).await.unwrap();
+        assert!(leds.cancel_schedule(cancelled).await.is_err());
+
+        tokio::select! {
+            _ = leds.run_scheduler() => unreachable!("scheduler only stops on shutdown"),
+            _ = tokio::time::sleep(Duration::from_millis(300)) => {}
+        }
+
+        assert_eq!(leds.state(led(1)).await, LedState::On);
+        assert_eq!(leds.state(led(2)).await, LedState::Off);
+        assert_eq!(leds.state(led(8)).await, LedState::On);
+        let pending: Vec<ScheduleId> = leds.list_schedules().await.iter().map(|scheduled| scheduled.id).collect();
+        assert_eq!(pending, vec![late]);
+    }
+
+    #[tokio::test]
+    async fn phased_blinks_alternate() {
+        let (backend, leds) = mock_controller();
+        // Last value written to an LED, i.e. whether it's lit
+        let lit = |n: u8| backend.writes().iter().rev().find(|&&(led, _)| led == n).is_some_and(|&(_, on)| on);
+
+        leds.blink_with_phase(led(1), 100, 0).await.unwrap();
+        // Three half-periods reduce to one: exactly out of step
+        leds.blink_with_phase(led(2), 100, 300).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(lit(1) && !lit(2));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!lit(1) && lit(2));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(lit(1) && !lit(2));
+        leds.all_off().await.unwrap();
+    }
+
+    #[test]
+    fn jittered_intervals_stay_in_range_and_positive() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let intervals: Vec<u64> = (0..1000).map(|_| jittered_interval(&mut rng, 100, 30).as_millis() as u64).collect();
+        assert!(intervals.iter().all(|interval| (70..=130).contains(interval)));
+        assert!(intervals.iter().any(|&interval| interval < 85) && intervals.iter().any(|&interval| interval > 115));
+        assert!((0..1000).all(|_| jittered_interval(&mut rng, 10, 50) >= Duration::from_millis(1)));
+        assert!((0..10).all(|_| jittered_interval(&mut rng, 100, 0) == Duration::from_millis(100)));
+    }
+
+    #[tokio::test]
+    async fn jittered_blink_toggles_like_a_blink() {
+        let (backend, leds) = mock_controller();
+        leds.blink_jitter(led(4), 50, 20).await.unwrap();
+        assert_eq!(leds.state(led(4)).await, LedState::Blinking { frequency_ms: 50 });
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let toggles: Vec<bool> = backend.writes().iter().filter(|&&(n, _)| n == 4).map(|&(_, on)| on).collect();
+        // 400ms at 30-70ms a toggle, alternating from on
+        assert!((3..=14).contains(&toggles.len()), "{} toggles", toggles.len());
+        assert!(toggles.iter().step_by(2).all(|&on| on) && toggles.iter().skip(1).step_by(2).all(|&on| !on));
+        assert!(matches!(leds.blink_jitter(led(4), 0, 20).await, Err(TrainError::InvalidFrequency { .. })));
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn seeded_random_walk_is_reproducible() {
+        let (first, leds) = mock_controller();
+        leds.random_walk(10, 0, Some(42)).await.unwrap();
+        let (second, leds) = mock_controller();
+        leds.random_walk(10, 0, Some(42)).await.unwrap();
+
+        let writes = first.writes();
+        assert_eq!(writes, second.writes());
+        // Each LED is switched on and then straight off again
+        assert_eq!(writes.len(), 20);
+        assert!(writes.chunks(2).all(|pair| matches!(pair, [(a, true), (b, false)] if a == b)));
+        assert!(leds.get_all_states().await.iter().all(|&(_, state)| state == LedState::Off));
+    }
+
+    #[tokio::test]
+    async fn seeded_flicker_is_reproducible_and_replaces_blinks() {
+        let (first, leds_a) = mock_controller();
+        let (second, leds_b) = mock_controller();
+        leds_a.flicker(led(1), FlickerIntensity::Medium, Some(7)).await.unwrap();
+        leds_b.flicker(led(1), FlickerIntensity::Medium, Some(7)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        leds_a.all_off().await.unwrap();
+        leds_b.all_off().await.unwrap();
+
+        // Same seed, same pattern; the runs may stop a step apart, and all_off adds a final write
+        let flickers = |backend: &MockBackend| -> Vec<bool> {
+            let mut writes: Vec<bool> = backend.writes().iter().filter(|&&(n, _)| n == 1).map(|&(_, on)| on).collect();
+            writes.pop();
+            writes
+        };
+        let (a, b) = (flickers(&first), flickers(&second));
+        let common = a.len().min(b.len()) - 1;
+        assert!(common >= 3);
+        assert_eq!(a[..common], b[..common]);
+        // Only changes are written
+        assert!(a.windows(2).all(|pair| pair[0] != pair[1]));
+
+        // A blink replaces the flicker and vice versa
+        leds_a.blink(led(2), 100).await.unwrap();
+        leds_a.flicker(led(2), FlickerIntensity::Heavy, None).await.unwrap();
+        assert_eq!(leds_a.state(led(2)).await, LedState::Flickering { intensity: FlickerIntensity::Heavy });
+        leds_a.blink(led(2), 100).await.unwrap();
+        assert_eq!(leds_a.state(led(2)).await, LedState::Blinking { frequency_ms: 100 });
+        assert_eq!(leds_a.blink_handles.read().await.len(), 1);
+        leds_a.all_off().await.unwrap();
+        let count = first.writes().len();
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert_eq!(first.writes().len(), count);
+    }
+
+    #[test]
+    fn pin_map_can_span_chips() {
+        let mut config = LedConfig::default();
+        config.pins[23] = LedPin::OnChip { chip: "/dev/gpiochip1".to_string(), line: 4 };
+        config.validate().unwrap();
+        assert_eq!(config.chips(), ["/dev/gpiochip0", "/dev/gpiochip1"]);
+
+        let leds = LedController::with_backend_and_config(Arc::new(MockBackend::new()), config.clone());
+        let last = leds.mapping().pop().unwrap();
+        assert_eq!((last.chip.as_str(), last.gpio), ("/dev/gpiochip1", 4));
+
+        // The same line twice on one chip is still rejected
+        config.pins[23] = LedPin::OnChip { chip: "/dev/gpiochip0".to_string(), line: 4 };
+        assert!(matches!(config.validate(), Err(TrainError::InvalidParameter(_))));
+    }
+
+    #[tokio::test]
+    async fn breathing_stays_within_its_range() {
+        let (backend, leds) = mock_controller();
+        assert!(matches!(leds.breathe(led(1), 1000, 200, 100).await, Err(TrainError::InvalidParameter(_))));
+        assert!(matches!(leds.breathe(led(1), 10, 0, 255).await, Err(TrainError::InvalidParameter(_))));
+
+        // Never fully dark, so the line is never switched off
+        leds.breathe(led(1), 200, 255, 255).await.unwrap();
+        leds.breathe(led(2), 200, 0, 255).await.unwrap();
+        assert_eq!(leds.state(led(2)).await, LedState::Breathing { period_ms: 200, min: 0, max: 255 });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        leds.blink(led(2), 100).await.unwrap();
+        assert_eq!(leds.blink_handles.read().await.len(), 2);
+        leds.all_off().await.unwrap();
+
+        let writes = backend.writes();
+        let first: Vec<bool> = writes.iter().filter(|&&(n, _)| n == 1).map(|&(_, on)| on).collect();
+        assert_eq!(first, [true, false]);
+        // Dimmed part of the way: switched both ways many times
+        let second = writes.iter().filter(|&&(n, on)| n == 2 && !on).count();
+        assert!(second > 5);
+    }
+
+    #[tokio::test]
+    async fn binary_values_are_written_in_one_batch() {
+        let (backend, leds) = mock_controller();
+        let bank = [led(1), led(2), led(3), led(4)];
+        leds.blink(led(4), 100).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let before = backend.writes().len();
+
+        leds.display_binary(0b0101, &bank).await.unwrap();
+        assert_eq!(backend.writes()[before..], [(1, true), (3, true), (4, false)]);
+        assert!(leds.blink_handles.read().await.is_empty());
+
+        assert!(matches!(leds.display_binary(16, &bank).await, Err(TrainError::InvalidParameter(_))));
+        assert!(matches!(leds.display_binary(1, &[led(1), led(1)]).await, Err(TrainError::InvalidParameter(_))));
+        assert_eq!(leds.state(led(1)).await, LedState::On);
+    }
+
+    #[tokio::test]
+    async fn brightness_levels_use_pwm_between_the_extremes() {
+        let (backend, leds) = mock_controller();
+
+        leds.set_brightness(led(1), 128).await.unwrap();
+        assert_eq!(leds.state(led(1)).await, LedState::Dimmed { level: 128 });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let toggles = backend.writes().len();
+        assert!(toggles > 5);
+
+        // The extremes are plain on and off, with no PWM task
+        leds.set_brightness(led(1), 255).await.unwrap();
+        assert_eq!(leds.state(led(1)).await, LedState::On);
+        assert!(leds.blink_handles.read().await.is_empty());
+        leds.set_brightness(led(1), 0).await.unwrap();
+        assert_eq!(leds.state(led(1)).await, LedState::Off);
+        assert_eq!(backend.writes().len(), toggles + 2);
+    }
+
+    #[tokio::test]
+    async fn failed_writes_end_effects_and_clean_up() {
+        let (backend, leds) = mock_controller();
+        let mut events = leds.subscribe();
+        leds.blink(led(1), 20).await.unwrap();
+        leds.set_brightness(led(2), 100).await.unwrap();
+        backend.fail_after(backend.writes().len() + 3);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(leds.blink_handles.read().await.is_empty());
+        assert_eq!(leds.state(led(1)).await, LedState::Off);
+        assert_eq!(leds.state(led(2)).await, LedState::Off);
+
+        let mut failed = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if event.error.is_some() {
+                failed.push((event.led.get(), event.old_state));
+            }
+        }
+        failed.sort_by_key(|&(n, _)| n);
+        assert_eq!(failed, [(1, LedState::Blinking { frequency_ms: 20 }), (2, LedState::Dimmed { level: 100 })]);
+    }
+
+    #[tokio::test]
+    async fn progress_bars_round_and_shrink() {
+        let (_, leds) = mock_controller();
+        let lit = |states: Vec<(Led, LedState)>| -> Vec<u8> {
+            states.into_iter().filter(|&(_, state)| state == LedState::On).map(|(led, _)| led.get()).collect()
+        };
+
+        leds.show_progress(LedColor::Red, 50).await.unwrap();
+        assert_eq!(lit(leds.get_all_states().await), (13..=18).collect::<Vec<u8>>());
+        // 6 x 0.3 = 1.8, rounded to 2
+        leds.show_progress(LedColor::Green, 30).await.unwrap();
+        leds.show_progress(LedColor::Red, 4).await.unwrap();
+        assert_eq!(lit(leds.get_all_states().await), [1, 2]);
+        leds.show_progress(LedColor::Amber, 250).await.unwrap();
+        assert_eq!(lit(leds.get_all_states().await), [1, 2, 7, 8, 9, 10, 11, 12]);
+    }
+
+    #[tokio::test]
+    async fn pulses_ramp_up_and_down_then_finish_off() {
+        let (backend, leds) = mock_controller();
+        assert!(matches!(leds.pulse(led(1), 0, 0).await, Err(TrainError::InvalidParameter(_))));
+
+        leds.pulse(led(1), 100, 100).await.unwrap();
+        assert_eq!(leds.state(led(1)).await, LedState::Pulsing { rise_ms: 100, fall_ms: 100 });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(leds.state(led(1)).await, LedState::Off);
+        assert!(leds.blink_handles.read().await.is_empty());
+        let writes = backend.writes();
+        assert!(writes.len() > 5);
+        assert_eq!(writes.last(), Some(&(1, false)));
+    }
+
+    #[tokio::test]
+    async fn level_meter_fills_green_then_amber_then_red() {
+        let (backend, leds) = mock_controller();
+
+        leds.show_level(50).await.unwrap();
+        assert_eq!(leds.state(led(12)).await, LedState::On);
+        assert_eq!(leds.state(led(13)).await, LedState::Off);
+        leds.show_level(100).await.unwrap();
+        assert_eq!(leds.state(led(24)).await, LedState::On);
+        let writes = backend.writes().len();
+        // Only the LEDs that change are written
+        leds.show_level(96).await.unwrap();
+        assert_eq!(backend.writes().len(), writes + 1);
+        leds.show_level(0).await.unwrap();
+        assert!(leds.get_all_states().await.iter().all(|&(_, state)| state == LedState::Off));
+    }
+
+    #[tokio::test]
+    async fn fades_end_in_a_steady_state() {
+        let (backend, leds) = mock_controller();
+
+        leds.set_brightness(led(1), 128).await.unwrap();
+        leds.fade_in(led(2), 100).await.unwrap();
+        leds.fade_out(led(1), 100).await.unwrap();
+        assert_eq!(leds.state(led(2)).await, LedState::Fading { direction: FadeDirection::In, duration_ms: 100 });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(leds.state(led(1)).await, LedState::Off);
+        assert_eq!(leds.state(led(2)).await, LedState::On);
+        assert!(leds.blink_handles.read().await.is_empty());
+        let writes = backend.writes();
+        assert_eq!(writes.iter().rev().find(|&&(n, _)| n == 2), Some(&(2, true)));
+    }
+
+    #[tokio::test]
+    async fn panic_mode_wins_until_all_off() {
+        let (_backend, leds) = mock_controller();
+        leds.blink(led(1), 200).await.unwrap();
+        leds.on(led(7)).await.unwrap();
+        let operation = leds.operation_token().await;
+
+        leds.panic_mode().await.unwrap();
+        assert!(operation.is_cancelled());
+        assert!(leds.is_panicking());
+        for (l, state) in leds.get_all_states().await {
+            let expected = if RED_LEDS.contains(&l.get()) {
+                LedState::Blinking { frequency_ms: PANIC_BLINK_MS }
+            } else {
+                LedState::Off
+            };
+            assert_eq!(state, expected, "LED {}", l);
+        }
+
+        leds.all_off().await.unwrap();
+        assert!(!leds.is_panicking());
+        assert!(leds.blink_handles.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn panic_mode_isnt_undone_by_a_cancelled_self_test() {
+        let (_backend, leds) = mock_controller();
+        let (report, panicked) = tokio::join!(leds.self_test(200, None), async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            leds.panic_mode().await
+        });
+        panicked.unwrap();
+        assert!(report.unwrap().cancelled);
+
+        assert!(leds.is_panicking());
+        for (l, state) in leds.get_all_states().await {
+            let expected = if RED_LEDS.contains(&l.get()) {
+                LedState::Blinking { frequency_ms: PANIC_BLINK_MS }
+            } else {
+                LedState::Off
+            };
+            assert_eq!(state, expected, "LED {}", l);
+        }
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn blink_all_flashes_in_unison_until_all_off() {
+        let (_backend, leds) = mock_controller();
+        leds.blink_all(100).await.unwrap();
+        let task = leds.blink_handles.read().await.get(&led(1)).unwrap().id();
+        assert!(leds.blink_handles.read().await.values().all(|handle| handle.id() == task), "one shared task");
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        for l in Led::all() {
+            assert_eq!(leds.is_lit(l).await, Some(false), "LED {}", l);
+        }
+
+        // Commanding one LED takes it out of the blink; the rest carry on
+        leds.on(led(5)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(leds.state(led(5)).await, LedState::On);
+        assert_eq!(leds.is_lit(led(6)).await, Some(true));
+        assert_eq!(leds.state(led(6)).await, LedState::Blinking { frequency_ms: 100 });
+
+        leds.all_off().await.unwrap();
+        assert!(leds.blink_handles.read().await.is_empty());
+        assert!(leds.shared_blinks.read().await.is_empty());
+        assert!(leds.get_all_states().await.iter().all(|(_, state)| *state == LedState::Off));
+    }
+
+    #[tokio::test]
+    async fn colour_blink_detaches_members_and_stops_by_colour() {
+        let (_backend, leds) = mock_controller();
+        leds.blink(led(1), 100).await.unwrap();
+        leds.blink_color(LedColor::Amber, 100).await.unwrap();
+        let handles = leds.blink_handles.read().await;
+        let task = handles[&led(7)].id();
+        assert!(AMBER_LEDS.map(Led).all(|l| handles[&l].id() == task), "one task for the colour");
+        drop(handles);
+
+        leds.off(led(8)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(leds.state(led(8)).await, LedState::Off);
+        assert_eq!(leds.state(led(9)).await, LedState::Blinking { frequency_ms: 100 });
+        assert_eq!(leds.is_lit(led(9)).await, leds.is_lit(led(12)).await);
+
+        leds.stop_color_blink(LedColor::Amber).await.unwrap();
+        for l in AMBER_LEDS.map(Led) {
+            assert_eq!(leds.state(l).await, LedState::Off, "LED {}", l);
+        }
+        assert!(!leds.shared_blinks.read().await.contains(&task));
+        assert_eq!(leds.state(led(1)).await, LedState::Blinking { frequency_ms: 100 }, "other colours untouched");
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn invert_flips_steady_leds_and_leaves_blinks_alone() {
+        let (backend, leds) = mock_controller();
+        leds.on(led(1)).await.unwrap();
+        leds.on(led(2)).await.unwrap();
+        // Delayed, so the blink itself writes nothing during the test
+        leds.blink_with_phase(led(3), 1000, 500).await.unwrap();
+        let before = backend.writes().len();
+
+        leds.invert().await.unwrap();
+        let writes = backend.writes().split_off(before);
+        assert_eq!(writes.len(), 23, "every LED but the blinking one written once");
+        for (n, on) in writes {
+            assert_eq!(on, n > 3, "LED {}", n);
+        }
+        assert_eq!(leds.state(led(1)).await, LedState::Off);
+        assert_eq!(leds.state(led(4)).await, LedState::On);
+        assert_eq!(leds.state(led(3)).await, LedState::Blinking { frequency_ms: 1000 });
+        leds.all_off().await.unwrap();
+    }
+
+    #[test]
+    fn subset_positions_map_to_leds() {
+        let at = |subset, position| LedController::get_led_from_subset(subset, position).map(Led::get);
+        assert_eq!(at(GREEN_LEDS, 3).unwrap(), 3);
+        assert_eq!(at(AMBER_LEDS, 1).unwrap(), 7);
+        assert_eq!(at(RED_LEDS, 12).unwrap(), 24);
+
+        for (subset, count, first) in [(GREEN_LEDS, 6, 1), (AMBER_LEDS, 6, 7), (RED_LEDS, 12, 13)] {
+            assert_eq!(at(subset.clone(), 1).unwrap(), first);
+            assert_eq!(at(subset.clone(), count).unwrap(), first + count - 1);
+            for position in [0, count + 1] {
+                assert!(
+                    matches!(at(subset.clone(), position), Err(TrainError::PositionOutOfRange { max, .. }) if max == count),
+                    "position {} of {:?}", position, subset
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip_in_one_write() {
+        let (backend, leds) = mock_controller();
+        leds.blink_with_phase(led(2), 1000, 500).await.unwrap();
+        let before = backend.writes().len();
+
+        leds.set_frame_u32(0b1000_0000_0000_0000_0000_0101).await.unwrap();
+        assert_eq!(backend.writes().len() - before, 4, "only LEDs 1, 2, 3 and 24 change");
+        assert_eq!(leds.state(led(2)).await, LedState::Off, "blink cancelled");
+        assert_eq!(leds.get_frame_u32().await, 0b1000_0000_0000_0000_0000_0101);
+        let frame = leds.get_frame().await;
+        assert!(frame[0] && !frame[1] && frame[2] && frame[23]);
+
+        assert!(matches!(leds.set_frame_u32(1 << 24).await, Err(TrainError::InvalidParameter(_))));
+        leds.set_frame(&[false; 24]).await.unwrap();
+        assert_eq!(leds.get_frame_u32().await, 0);
+    }
+
+    #[tokio::test]
+    async fn frames_play_at_the_frame_rate_and_can_be_stopped() {
+        let (_backend, leds) = mock_controller();
+        let frame = |lit: &[usize]| std::array::from_fn(|i| lit.contains(&(i + 1)));
+        assert!(matches!(leds.play_frames(vec![frame(&[1])], 0, false).await, Err(TrainError::InvalidParameter(_))));
+        assert!(matches!(leds.play_frames(Vec::new(), 10, false).await, Err(TrainError::InvalidParameter(_))));
+
+        // Halfway through the second frame, so a slow scheduler can't land on a frame boundary
+        let animation = leds.play_frames(vec![frame(&[1]), frame(&[2]), frame(&[3, 24])], 10, false).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(leds.get_frame().await, frame(&[2]));
+        tokio::time::timeout(Duration::from_secs(1), animation.finished()).await.unwrap();
+        assert_eq!(leds.get_frame().await, frame(&[3, 24]), "last frame left showing");
+        assert!(leds.blink_handles.read().await.is_empty());
+
+        let animation = leds.play_frames(vec![frame(&[5]), frame(&[6])], 50, true).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        animation.stop().await;
+        tokio::time::timeout(Duration::from_secs(1), animation.finished()).await.unwrap();
+        assert!(leds.blink_handles.read().await.is_empty());
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_for_returns_when_another_task_changes_the_led() {
+        let (_backend, leds) = mock_controller();
+        let leds = Arc::new(leds);
+        leds.wait_for(led(7), LedState::Off, Duration::ZERO).await.unwrap();
+
+        leds.on(led(7)).await.unwrap();
+        let switcher = Arc::clone(&leds);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            switcher.off(led(7)).await.unwrap();
+        });
+        leds.wait_for(led(7), LedState::Off, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(leds.state(led(7)).await, LedState::Off);
+
+        let result = leds.wait_for(led(7), LedState::On, Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(TrainError::StateWaitTimeout { led: 7, waited_ms: 50 })));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn snapshot_and_subscription_line_up_under_concurrent_commands() {
+        let (_backend, leds) = mock_controller();
+        let leds = Arc::new(leds);
+        let switcher = Arc::clone(&leds);
+        let toggling = tokio::spawn(async move {
+            for i in 0..200 {
+                if i % 2 == 0 { switcher.on(led(3)).await.unwrap() } else { switcher.off(led(3)).await.unwrap() }
+                tokio::task::yield_now().await;
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+
+        // Replaying the events onto the snapshot must end at the real state, with no gaps along the way
+        let (snapshot, mut events) = leds.subscribe_with_snapshot().await;
+        assert_eq!(snapshot.len(), 24);
+        let mut state = snapshot.iter().find(|(l, _)| *l == led(3)).unwrap().1;
+        toggling.await.unwrap();
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.old_state, state);
+            state = event.new_state;
+        }
+        assert_eq!(state, leds.state(led(3)).await);
+    }
+
+    #[tokio::test]
+    async fn zones_come_from_config_and_switch_together() {
+        let mut config = LedConfig::default();
+        config.zones.insert("platform1".to_string(), vec![7, 1, 2]);
+        let backend = Arc::new(MockBackend::new());
+        let leds = LedController::with_backend_and_config(backend.clone(), config);
+        assert_eq!(leds.zones(), vec!["platform1".to_string()]);
+        assert_eq!(leds.zone("platform1"), Some(vec![led(1), led(2), led(7)]));
+        assert!(matches!(leds.zone_on("platform2").await, Err(TrainError::InvalidParameter(_))));
+
+        let before = backend.writes().len();
+        leds.zone_on("platform1").await.unwrap();
+        assert_eq!(backend.writes().split_off(before), vec![(1, true), (2, true), (7, true)]);
+
+        leds.zone_blink("platform1", 500).await.unwrap();
+        assert_eq!(leds.state(led(2)).await, LedState::Blinking { frequency_ms: 500 });
+        let handles = leds.blink_handles.read().await;
+        assert_eq!(handles.len(), 3);
+        assert!(handles.values().all(|handle| handle.id() == handles[&led(1)].id()), "one shared task");
+        drop(handles);
+        leds.zone_off("platform1").await.unwrap();
+        assert!(leds.blink_handles.read().await.is_empty());
+        assert!(leds.get_all_states().await.iter().all(|(_, state)| *state == LedState::Off));
+    }
+
+    #[tokio::test]
+    async fn hz_counts_full_cycles() {
+        let (_backend, leds) = mock_controller();
+        assert_eq!(LedController::interval_for_hz(1.0).unwrap(), 500);
+        assert_eq!(LedController::interval_for_hz(3.0).unwrap(), 167);
+        for hz in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(LedController::interval_for_hz(hz), Err(TrainError::InvalidParameter(_))), "{} Hz", hz);
+        }
+
+        leds.blink_hz(led(4), 2.0).await.unwrap();
+        assert_eq!(leds.state(led(4)).await, LedState::Blinking { frequency_ms: 250 });
+        assert!(matches!(leds.blink_hz(led(4), 10_000.0).await, Err(TrainError::InvalidFrequency { .. })));
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn traffic_light_steps_through_the_railway_aspects() {
+        let (_backend, leds) = mock_controller();
+        let lamps = [led(2), led(8), led(14)];
+        async fn lit(leds: &LedController, lamps: [Led; 3]) -> [bool; 3] {
+            let states = leds.states.read().await;
+            lamps.map(|l| states.get(&l) == Some(&LedState::On))
+        }
+
+        assert!(matches!(leds.traffic_light(7, 2, 2, 1).await, Err(TrainError::PositionOutOfRange { .. })));
+        leds.traffic_light(2, 2, 2, 1).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(lit(&leds, lamps).await, [false, false, true]);
+        tokio::time::sleep(Duration::from_millis(240)).await;
+        assert_eq!(lit(&leds, lamps).await, [false, true, true]);
+        tokio::time::sleep(Duration::from_millis(260)).await;
+        assert_eq!(lit(&leds, lamps).await, [true, false, false]);
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn effect_events_can_be_suppressed() {
+        let config = LedConfig { effect_events: false, event_coalesce_ms: 0, ..LedConfig::default() };
+        let leds = LedController::with_backend_and_config(Arc::new(MockBackend::new()), config);
+        let mut events = leds.subscribe();
+        leds.blink(led(2), 20).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        leds.off(led(2)).await.unwrap();
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            seen.push((event.led, event.old_state, event.new_state, event.effect));
+        }
+        let blinking = LedState::Blinking { frequency_ms: 20 };
+        assert_eq!(seen, vec![(led(2), LedState::Off, blinking, false), (led(2), blinking, LedState::Off, false)]);
+    }
+
+    #[tokio::test]
+    async fn effects_are_capped_and_finished_ones_free_their_slot() {
+        let config = LedConfig { max_effects: 2, ..LedConfig::default() };
+        let leds = LedController::with_backend_and_config(Arc::new(MockBackend::new()), config);
+        leds.blink(led(1), 500).await.unwrap();
+        leds.blink_count(led(2), 20, 1).await.unwrap();
+        assert!(matches!(leds.blink(led(3), 500).await, Err(TrainError::TooManyEffects { max: 2 })));
+        assert_eq!(leds.state(led(3)).await, LedState::Off, "nothing changed");
+        leds.blink(led(1), 250).await.expect("replacing an effect doesn't need a new slot");
+
+        // The counted blink ends by itself
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(leds.active_effects().await, 1);
+        leds.flicker(led(3), FlickerIntensity::Medium, Some(1)).await.unwrap();
+        assert!(matches!(leds.on_for(led(4), 1000).await, Err(TrainError::TooManyEffects { .. })));
+
+        // A shared blink is one task however many LEDs it drives
+        leds.all_off().await.unwrap();
+        leds.blink_all(500).await.unwrap();
+        assert_eq!(leds.active_effects().await, 1);
+        leds.blink(led(6), 500).await.unwrap();
+        assert_eq!(leds.active_effects().await, 2);
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn panic_mode_blinks_the_reds_as_one_task_whatever_the_cap() {
+        let config = LedConfig { max_effects: 2, ..LedConfig::default() };
+        let leds = LedController::with_backend_and_config(Arc::new(MockBackend::new()), config);
+        leds.blink(led(1), 500).await.unwrap();
+        leds.blink(led(2), 500).await.unwrap();
+
+        leds.panic_mode().await.unwrap();
+        let task = leds.blink_handles.read().await.get(&led(13)).unwrap().id();
+        for n in RED_LEDS {
+            assert_eq!(leds.blink_handles.read().await.get(&led(n)).unwrap().id(), task, "LED {}", n);
+            assert_eq!(leds.state(led(n)).await, LedState::Blinking { frequency_ms: PANIC_BLINK_MS });
+        }
+        assert_eq!(leds.active_effects().await, 1);
+        leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn all_off_sweeps_the_leds_in_order() {
+        let (backend, leds) = mock_controller();
+        for n in [17, 2, 9] {
+            leds.on(led(n)).await.unwrap();
+        }
+        leds.blink_with_phase(led(5), 100, 500).await.unwrap();
+
+        let before = backend.writes().len();
+        leds.all_off().await.unwrap();
+        let expected: Vec<(u8, bool)> = (1..=LED_COUNT).map(|n| (n, false)).collect();
+        assert_eq!(backend.writes().split_off(before), expected);
+    }
+
+    #[tokio::test]
+    async fn all_off_tries_every_led_and_reports_the_failures() {
+        let (backend, leds) = mock_controller();
+        leds.on(led(3)).await.unwrap();
+        leds.on(led(4)).await.unwrap();
+        leds.on(led(24)).await.unwrap();
+        backend.fail_led(4);
+
+        match leds.all_off().await {
+            Err(TrainError::PartialFailure(failures)) => assert_eq!(failures.iter().map(|(n, _)| *n).collect::<Vec<_>>(), [4]),
+            other => panic!("expected a partial failure, got {:?}", other),
+        }
+        let writes = backend.writes();
+        assert!(writes.ends_with(&[(22, false), (23, false), (24, false)]), "LEDs after the bad one are still turned off");
+        assert!(writes.contains(&(3, false)));
+    }
+
+    #[tokio::test]
+    async fn recurring_schedules_skip_missed_runs_and_are_saved() {
+        let (_, leds) = mock_controller();
+        let start = Utc::now() - chrono::Duration::milliseconds(2500);
+        let id = leds.schedule_every(start, Duration::from_secs(1), LedCommand::On { led: led(3) }).await.unwrap();
+        assert!(leds.schedule_every(start, Duration::ZERO, LedCommand::AllOff).await.is_err());
+
+        tokio::select! {
+            _ = leds.run_scheduler() => unreachable!("scheduler only stops on shutdown"),
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+        }
+
+        assert_eq!(leds.state(led(3)).await, LedState::On);
+        let pending = leds.list_schedules().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].at, start + chrono::Duration::seconds(3), "runs at 1s and 2s were missed");
+
+        let snapshot = leds.snapshot().await;
+        assert_eq!(snapshot.schedules, pending);
+        let (_, restored) = mock_controller();
+        restored.restore(&snapshot).await.unwrap();
+        assert_eq!(restored.list_schedules().await, pending);
+        leds.cancel_schedule(id).await.unwrap();
+    }
+}
@@ -1,7 +1,36 @@
+pub mod audit;
+pub mod backend;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod config;
 pub mod error;
+pub mod events;
+#[cfg(feature = "hardware")]
+pub mod inputs;
 pub mod leds;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod ops;
+mod persist;
+pub mod schedule;
 pub mod server;
 
+pub use audit::{AuditEntry, AuditLog, LedOperation};
+pub use backend::{LedBackend, LineDiagnostics, MockBackend, DryRunBackend, Mcp23017Backend, Pca9685Backend, CompositeBackend, ShiftRegisterBackend};
+#[cfg(feature = "hardware")]
+pub use backend::GpioBackend;
+pub use config::{LedConfig, LedPin, ServerConfig};
 pub use error::{TrainError, Result};
-pub use leds::{LedController, LedState, GREEN_LEDS, AMBER_LEDS, RED_LEDS, LED_COUNT};
-pub use server::{AppState, create_router};
+pub use events::LedEvent;
+#[cfg(feature = "hardware")]
+pub use inputs::{Edge, InputController, InputEvent, InputLine, Pull};
+#[cfg(feature = "mqtt")]
+pub use mqtt::{run_mqtt, DEFAULT_MQTT_PORT};
+pub use ops::{LedOps, ColorOps};
+pub use schedule::{LedCommand, ScheduleId, ScheduledCommand};
+pub use leds::{Led, LedController, Frame, AnimationHandle, LedState, FlickerIntensity, FadeDirection, LedColor, LedMapping, LedSnapshot, LedSnapshotEntry, SelfTestReport, SelfTestFailure, GREEN_LEDS, AMBER_LEDS, RED_LEDS, LED_COUNT};
+pub use server::{AppState, BlinkRequest, ErrorResponse, OnRequest, StatusResponse, DEFAULT_BODY_LIMIT, DEFAULT_MAX_CONNECTIONS, bind_address, bind_listener, create_router, serve_tcp};
+#[cfg(unix)]
+pub use server::{bind_unix_listener, serve_unix};
+#[cfg(feature = "blocking")]
+pub use blocking::BlockingLedController;
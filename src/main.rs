@@ -1,6 +1,20 @@
-use train::{LedController, AppState, create_router, GREEN_LEDS, AMBER_LEDS, RED_LEDS};
-use clap::{Parser, Subcommand};
-use tokio::net::TcpListener;
+use train::{AuditLog, BlinkRequest, ErrorResponse, OnRequest, StatusResponse, Led, LedConfig, LedController, ServerConfig, LedState, LedSnapshot, SelfTestReport, AppState, bind_address, bind_listener, create_router, serve_tcp};
+#[cfg(unix)]
+use train::{bind_unix_listener, serve_unix};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::time::Instant;
+use tokio::io::AsyncBufReadExt;
+use tokio_util::sync::CancellationToken;
+
+/// Where LED state is persisted unless --state-file or the server config says otherwise
+const DEFAULT_STATE_FILE: &str = "train-state.json";
+/// How long each LED stays on during the sequential self-test
+const SELF_TEST_STEP_MS: u64 = 250;
+/// Number of LEDs lit by the random test, and how long each stays on
+const RANDOM_TEST_ITERATIONS: u32 = 200;
+const RANDOM_TEST_ON_MS: u64 = 250;
 
 #[derive(Parser)]
 #[command(name = "train")]
@@ -8,6 +22,15 @@ use tokio::net::TcpListener;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// File the LED state is saved to and restored from across restarts (default: train-state.json)
+    #[arg(long, global = true)]
+    state_file: Option<std::path::PathBuf>,
+    /// Don't restore or save LED state
+    #[arg(long, global = true)]
+    no_persist: bool,
+    /// Print GPIO writes to stderr instead of driving the LEDs (implies --no-persist)
+    #[arg(long, global = true)]
+    dry_run: bool,
 }
 
 #[derive(Subcommand)]
@@ -18,13 +41,152 @@ enum Commands {
         component: TestComponent,
     },
     /// Start the web server
-    Server {
-        /// Port to listen on (default: 8080)
-        #[arg(short, long, default_value_t = 8080)]
-        port: u16,
-        /// Host to bind to (default: 0.0.0.0)
-        #[arg(short = 'H', long, default_value = "0.0.0.0")]
-        host: String,
+    Server(ServerArgs),
+    /// Control LEDs interactively: `on 5`, `blink 3 500`, `off all`, `status`
+    Repl,
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+    /// Save or restore the LED state of a running server
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Control the LEDs of a running server: `remote led 5 on`, `remote led 3 blink 500`, `remote all-off`
+    Remote {
+        /// Base URL of the running server
+        #[arg(short, long, default_value = "http://127.0.0.1:8080")]
+        url: String,
+        /// API token, if the server was started with --api-token
+        #[arg(long)]
+        api_token: Option<String>,
+        #[command(subcommand)]
+        action: RemoteAction,
+    },
+}
+
+#[derive(Args)]
+struct ServerArgs {
+    /// Load settings from a TOML (`.toml`) or JSON file; flags given here override it
+    #[arg(long, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+    /// Port to listen on (default: 8080)
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// Host to bind to (default: 0.0.0.0); use `::` for IPv4 and IPv6 on all interfaces
+    #[arg(short = 'H', long)]
+    host: Option<String>,
+    /// Listen on a Unix domain socket at this path instead of TCP
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["port", "host"])]
+    unix_socket: Option<std::path::PathBuf>,
+    /// Group (name or gid) given access to the Unix socket, which is created with mode 0660
+    #[cfg(unix)]
+    #[arg(long, value_name = "GROUP", requires = "unix_socket")]
+    socket_group: Option<String>,
+    /// Fail to start if any LED's GPIO line can't be requested
+    #[arg(long)]
+    strict: bool,
+    /// Require `Authorization: Bearer <token>` on POST/PUT/DELETE requests
+    #[arg(long)]
+    api_token: Option<String>,
+    /// Requests per second allowed from each client IP, 0 disables rate limiting (default: 20)
+    #[arg(long)]
+    rate_limit_rps: Option<u32>,
+    /// Compress responses (gzip or brotli) for clients that accept it
+    #[arg(long)]
+    compression: bool,
+    /// Also accept cleartext HTTP/2 (h2c, prior knowledge) alongside HTTP/1.1
+    #[arg(long)]
+    http2: bool,
+    /// Largest request body accepted, in bytes (default: 65536)
+    #[arg(long, value_name = "BYTES")]
+    body_limit: Option<usize>,
+    /// Connections served at once; more are answered with 503 (default: 64)
+    #[arg(long, value_name = "N")]
+    max_connections: Option<usize>,
+    /// Walk every LED on/off at startup and report failures at /api/self-test
+    #[arg(long)]
+    self_test: bool,
+    /// Double-blip an LED every two seconds while the server is running (default: LED 1)
+    #[arg(long, value_name = "LED", num_args = 0..=1, default_missing_value = "1", value_parser = led_number(), hide_possible_values = true)]
+    heartbeat: Option<u8>,
+    /// Take LED commands from this MQTT broker (topics `train/leds/{led}/set`)
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "HOST")]
+    mqtt_broker: Option<String>,
+    /// MQTT broker port
+    #[cfg(feature = "mqtt")]
+    #[arg(long, default_value_t = train::DEFAULT_MQTT_PORT, requires = "mqtt_broker")]
+    mqtt_port: u16,
+    /// Client ID to connect to the MQTT broker with
+    #[cfg(feature = "mqtt")]
+    #[arg(long, default_value = "train", requires = "mqtt_broker")]
+    mqtt_client_id: String,
+}
+
+/// Parser for LED number arguments, listing 1-24 as completion candidates
+fn led_number() -> impl TypedValueParser<Value = u8> {
+    PossibleValuesParser::new((1..=train::LED_COUNT).map(|led| led.to_string()))
+        .map(|led| led.parse::<u8>().expect("possible values are LED numbers"))
+}
+
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Save the server's current LED state to a JSON file
+    Save {
+        /// File to write the snapshot to
+        file: std::path::PathBuf,
+        /// Base URL of the running server
+        #[arg(short, long, default_value = "http://127.0.0.1:8080")]
+        url: String,
+        /// API token, if the server was started with --api-token
+        #[arg(long)]
+        api_token: Option<String>,
+    },
+    /// Restore LED state on the server from a JSON file
+    Load {
+        /// File to read the snapshot from
+        file: std::path::PathBuf,
+        /// Base URL of the running server
+        #[arg(short, long, default_value = "http://127.0.0.1:8080")]
+        url: String,
+        /// API token, if the server was started with --api-token
+        #[arg(long)]
+        api_token: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteAction {
+    /// Command a single LED
+    Led {
+        /// LED number
+        #[arg(value_parser = led_number(), hide_possible_values = true)]
+        led: u8,
+        #[command(subcommand)]
+        command: RemoteLedCommand,
+    },
+    /// Turn every LED off
+    AllOff,
+}
+
+#[derive(Subcommand)]
+enum RemoteLedCommand {
+    /// Turn the LED on
+    On {
+        /// Turn it off again after this many milliseconds
+        #[arg(long)]
+        ttl_ms: Option<u64>,
+    },
+    /// Turn the LED off
+    Off,
+    /// Blink the LED
+    Blink {
+        /// Toggle interval in milliseconds
+        frequency_ms: u64,
     },
 }
 
@@ -46,56 +208,122 @@ enum LedTest {
     /// Sequential test: turn each LED on for 250ms, then off
     Seq,
     /// Random test: turn random LEDs on/off for 200 iterations
-    Random,
+    Random {
+        /// Seed for a reproducible sequence of LEDs
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Blink every LED in unison until Enter is pressed
+    BlinkAll {
+        /// Toggle interval in milliseconds
+        #[arg(long, default_value_t = 500)]
+        freq: u64,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
+    // Log level from RUST_LOG, e.g. RUST_LOG=train=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    // Where to persist LED state, if anywhere; a dry run leaves the saved state alone
+    let persist = !cli.no_persist && !cli.dry_run;
+    let state_file = |configured: Option<std::path::PathBuf>| {
+        persist.then(|| cli.state_file.clone().or(configured).unwrap_or_else(|| DEFAULT_STATE_FILE.into()))
+    };
+    let config = LedConfig { dry_run: cli.dry_run, ..LedConfig::default() };
+
     match cli.command {
         Commands::Test { component } => {
-            run_test(component).await?;
+            run_test(component, config, state_file(None)).await?;
         }
-        Commands::Server { port, host } => {
-            run_server(port, host).await?;
+        Commands::Server(args) => {
+            let file = args.config.as_ref().map(ServerConfig::load).transpose()?.unwrap_or_default();
+            let state_file = state_file(file.state_file.clone());
+            run_server(args, file, cli.dry_run, state_file).await?;
+        }
+        Commands::Snapshot { action } => {
+            run_snapshot(action).await?;
+        }
+        Commands::Remote { url, api_token, action } => {
+            run_remote(&url, api_token, action).await?;
+        }
+        Commands::Repl => {
+            run_repl(config, state_file(None)).await?;
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "train", &mut std::io::stdout());
         }
     }
 
     Ok(())
 }
 
-async fn run_test(component: TestComponent) -> Result<(), Box<dyn std::error::Error>> {
+async fn run_test(component: TestComponent, config: LedConfig, state_file: Option<std::path::PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
     println!("Train Set Control System - Test Mode");
     println!("Initializing LED controller...");
 
     // Initialize LED controller (24 LEDs on GPIO pins 4-27)
-    let leds = LedController::new()?;
+    let leds = LedController::with_config(config)?;
+    let leds = match state_file {
+        Some(path) => leds.with_persistence(path).await,
+        None => leds,
+    };
     println!("LED controller initialized with {} LEDs (GPIO pins 4-27)", leds.count());
     println!("  Green LEDs: 1-6");
     println!("  Amber LEDs: 7-12");
     println!("  Red LEDs: 13-24\n");
 
+    // Cancelled on SIGINT/SIGTERM; each test stops at its next step
+    let cancel = leds.operation_token().await;
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            shutdown_signal().await;
+            println!("\nInterrupted, turning all LEDs off...");
+            cancel.cancel();
+        }
+    });
+
+    let result = run_component_test(&leds, component, &cancel).await;
+
+    leds.shutdown_token().cancel();
+    leds.all_off().await?;
+    result
+}
+
+async fn run_component_test(leds: &LedController, component: TestComponent, cancel: &CancellationToken) -> Result<(), Box<dyn std::error::Error>> {
     match component {
-        TestComponent::Led { test } => test_leds(leds, test).await?,
+        TestComponent::Led { test } => test_leds(leds, test, cancel).await?,
     }
 
     Ok(())
 }
 
-async fn test_leds(leds: LedController, test: LedTest) -> Result<(), Box<dyn std::error::Error>> {
+async fn test_leds(leds: &LedController, test: LedTest, cancel: &CancellationToken) -> Result<(), Box<dyn std::error::Error>> {
     println!("=== LED Test ===");
 
     match test {
         LedTest::All => {
             println!("Turning all LEDs on...");
-            for led in 1..=24 {
+            for led in Led::all() {
                 leds.on(led).await?;
             }
             println!("All {} LEDs are now ON", leds.count());
             println!("\nPress Enter to turn all LEDs off...");
             let mut buffer = String::new();
-            std::io::stdin().read_line(&mut buffer)?;
+            let mut stdin = tokio::io::BufReader::new(tokio::io::stdin());
+            tokio::select! {
+                read = stdin.read_line(&mut buffer) => { read?; }
+                _ = cancel.cancelled() => {}
+            }
             leds.all_off().await?;
             println!("All LEDs turned off");
         }
@@ -105,64 +333,492 @@ async fn test_leds(leds: LedController, test: LedTest) -> Result<(), Box<dyn std
             println!("All {} LEDs are now OFF", leds.count());
         }
         LedTest::Seq => {
-            println!("Sequential LED test - turning each LED on for 250ms...");
-            for led in 1..=24 {
+            println!("Sequential LED test - turning each LED on for {}ms...", SELF_TEST_STEP_MS);
+            let started = Instant::now();
+            let (progress, tracker) = track_progress(leds, u64::from(train::LED_COUNT), "LEDs tested");
+            let report = leds.self_test(SELF_TEST_STEP_MS, Some(cancel)).await;
+            tracker.abort();
+            let report = report?;
+            progress.set_position(report.tested as u64);
+            progress.finish();
+            print_self_test(&report);
+            if !report.cancelled {
+                println!("\nSequential test complete! ({:.1?})", started.elapsed());
+            }
+        }
+        LedTest::Random { seed } => {
+            println!("Random LED test - {} iterations...", RANDOM_TEST_ITERATIONS);
+            let started = Instant::now();
+            let (progress, tracker) = track_progress(leds, u64::from(RANDOM_TEST_ITERATIONS), "iterations");
+            let result = tokio::select! {
+                result = leds.random_walk(RANDOM_TEST_ITERATIONS, RANDOM_TEST_ON_MS, seed) => Some(result),
+                _ = cancel.cancelled() => None,
+            };
+            tracker.abort();
+            progress.abandon();
+            if result.transpose()?.is_none() {
+                return Ok(());
+            }
+            println!("\nRandom test complete! ({} iterations, {:.1?})", RANDOM_TEST_ITERATIONS, started.elapsed());
+        }
+        LedTest::BlinkAll { freq } => {
+            println!("Blinking all LEDs every {}ms...", freq);
+            leds.blink_all(freq).await?;
+            println!("\nPress Enter to turn all LEDs off...");
+            let mut buffer = String::new();
+            let mut stdin = tokio::io::BufReader::new(tokio::io::stdin());
+            tokio::select! {
+                read = stdin.read_line(&mut buffer) => { read?; }
+                _ = cancel.cancelled() => {}
+            }
+            leds.all_off().await?;
+            println!("All LEDs turned off");
+        }
+    }
+
+    Ok(())
+}
+
+/// Progress bar for an LED test, advanced each time the test finishes with an LED (switches it from on to off)
+/// Progress is read from the controller's events, so the tests themselves don't need to report it
+fn track_progress(leds: &LedController, total: u64, unit: &str) -> (ProgressBar, tokio::task::JoinHandle<()>) {
+    let progress = ProgressBar::new(total);
+    let template = format!("[{{bar:40}}] {{pos}}/{{len}} {} ({{percent}}%)", unit);
+    if let Ok(style) = ProgressStyle::with_template(&template) {
+        progress.set_style(style.progress_chars("=> "));
+    }
+
+    let mut events = leds.subscribe();
+    let tracker = tokio::spawn({
+        let progress = progress.clone();
+        async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) if !event.effect && event.old_state == LedState::On && event.new_state == LedState::Off => {
+                        progress.inc(1);
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        }
+    });
+    (progress, tracker)
+}
+
+/// LEDs a REPL command applies to
+enum ReplTarget {
+    One(Led),
+    All,
+}
+
+impl ReplTarget {
+    fn leds(&self) -> Vec<Led> {
+        match self {
+            ReplTarget::One(led) => vec![*led],
+            ReplTarget::All => Led::all().collect(),
+        }
+    }
+}
+
+/// A command typed at the `train repl` prompt
+enum ReplCommand {
+    On(ReplTarget),
+    Off(ReplTarget),
+    Blink(ReplTarget, u64),
+    Status,
+    Help,
+    Quit,
+}
+
+const REPL_HELP: &str = "\
+Commands:
+  on <led|all>              Turn LEDs on
+  off <led|all>             Turn LEDs off (stops blinking)
+  blink <led|all> <ms>      Blink LEDs, toggling every <ms> milliseconds
+  status                    Show every LED that isn't off
+  help                      Show this help
+  quit                      Turn all LEDs off and exit";
+
+/// Parse one REPL line; `Ok(None)` for a blank line
+fn parse_repl_command(line: &str) -> Result<Option<ReplCommand>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&command, args)) = words.split_first() else {
+        return Ok(None);
+    };
+
+    let target = |arg: Option<&&str>| -> Result<ReplTarget, String> {
+        match arg {
+            Some(&"all") => Ok(ReplTarget::All),
+            Some(arg) => arg.parse::<u8>()
+                .map_err(|_| format!("'{}' is not an LED number", arg))
+                .and_then(|led| Led::new(led).map_err(|e| e.to_string()))
+                .map(ReplTarget::One),
+            None => Err(format!("Usage: {} <led|all>", command)),
+        }
+    };
+
+    let parsed = match (command, args.len()) {
+        ("on", 1) => ReplCommand::On(target(args.first())?),
+        ("off", 1) => ReplCommand::Off(target(args.first())?),
+        ("blink", 2) => {
+            let frequency_ms = args[1].parse()
+                .map_err(|_| format!("'{}' is not a number of milliseconds", args[1]))?;
+            ReplCommand::Blink(target(args.first())?, frequency_ms)
+        }
+        ("blink", _) => return Err("Usage: blink <led|all> <ms>".to_string()),
+        ("on" | "off", _) => return Err(format!("Usage: {} <led|all>", command)),
+        ("status", 0) => ReplCommand::Status,
+        ("help" | "?", _) => ReplCommand::Help,
+        ("quit" | "exit", _) => ReplCommand::Quit,
+        _ => return Err(format!("Unknown command '{}', type 'help' for a list", line.trim())),
+    };
+    Ok(Some(parsed))
+}
+
+/// Run one REPL command; `Ok(false)` means quit
+async fn run_repl_command(leds: &LedController, command: ReplCommand) -> train::Result<bool> {
+    match command {
+        ReplCommand::On(target) => {
+            for led in target.leds() {
                 leds.on(led).await?;
-                println!("  LED {}: ON", led);
-                tokio::time::sleep(tokio::time::Duration::from_millis(250)).await;
+            }
+        }
+        ReplCommand::Off(ReplTarget::All) => leds.all_off().await?,
+        ReplCommand::Off(target) => {
+            for led in target.leds() {
                 leds.off(led).await?;
-                println!("  LED {}: OFF", led);
             }
-            println!("\nSequential test complete!");
-        }
-        LedTest::Random => {
-            use rand::Rng;
-            println!("Random LED test - 200 iterations...");
-            let mut rng = rand::thread_rng();
-            
-            for iteration in 1..=200 {
-                let random_led = rng.gen_range(1..=24);
-                leds.on(random_led).await?;
-                tokio::time::sleep(tokio::time::Duration::from_millis(250)).await;
-                leds.off(random_led).await?;
-                
-                if iteration % 20 == 0 {
-                    println!("  Completed {} iterations...", iteration);
+        }
+        ReplCommand::Blink(target, frequency_ms) => {
+            for led in target.leds() {
+                leds.blink(led, frequency_ms).await?;
+            }
+        }
+        ReplCommand::Status => {
+            let active: Vec<_> = leds.get_all_states().await
+                .into_iter()
+                .filter(|&(_, state)| state != LedState::Off)
+                .collect();
+            if active.is_empty() {
+                println!("All LEDs are off");
+            }
+            for (led, state) in active {
+                match state {
+                    LedState::Blinking { frequency_ms } => println!("  LED {}: blinking every {}ms", led, frequency_ms),
+                    LedState::Flickering { intensity } => println!("  LED {}: flickering ({:?})", led, intensity),
+                    LedState::Breathing { period_ms, min, max } => {
+                        println!("  LED {}: breathing every {}ms between {} and {}", led, period_ms, min, max)
+                    }
+                    LedState::Dimmed { level } => println!("  LED {}: dimmed to {}", led, level),
+                    LedState::Pulsing { .. } => println!("  LED {}: pulsing", led),
+                    LedState::Fading { direction, .. } => println!("  LED {}: fading {:?}", led, direction),
+                    _ => println!("  LED {}: on", led),
                 }
             }
-            println!("\nRandom test complete! (200 iterations)");
         }
+        ReplCommand::Help => println!("{}", REPL_HELP),
+        ReplCommand::Quit => return Ok(false),
     }
+    Ok(true)
+}
+
+async fn run_repl(config: LedConfig, state_file: Option<std::path::PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Train Set Control System - Interactive Mode");
+    let leds = LedController::with_config(config)?;
+    let leds = match state_file {
+        Some(path) => leds.with_persistence(path).await,
+        None => leds,
+    };
+    println!("LED controller initialized with {} LEDs. Type 'help' for commands.", leds.count());
 
+    let mut editor = rustyline::DefaultEditor::new()?;
+    let history = std::env::var_os("HOME").map(|home| std::path::PathBuf::from(home).join(".train_history"));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        // Blinks keep running on the other runtime threads while we wait for input
+        let line = match tokio::task::block_in_place(|| editor.readline("train> ")) {
+            Ok(line) => line,
+            Err(rustyline::error::ReadlineError::Interrupted) => continue,
+            Err(rustyline::error::ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let _ = editor.add_history_entry(line.as_str());
+
+        match parse_repl_command(&line) {
+            Ok(None) => {}
+            Ok(Some(command)) => match run_repl_command(&leds, command).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => println!("Error: {}", e),
+            },
+            Err(message) => println!("{}", message),
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    println!("Turning all LEDs off...");
+    leds.shutdown_token().cancel();
+    leds.all_off().await?;
     Ok(())
 }
 
-async fn run_server(port: u16, host: String) -> Result<(), Box<dyn std::error::Error>> {
+/// Print a self-test report
+fn print_self_test(report: &SelfTestReport) {
+    if report.cancelled {
+        println!("  Stopped after {} of {} LEDs", report.tested, train::LED_COUNT);
+    }
+    if report.passed() {
+        println!("  All {} LEDs passed", report.tested);
+        return;
+    }
+    println!("  {} of {} LEDs failed:", report.failures.len(), report.tested);
+    for failure in &report.failures {
+        println!("    LED {}: {}", failure.led, failure.error);
+    }
+}
+
+async fn run_server(args: ServerArgs, file: ServerConfig, dry_run: bool, state_file: Option<std::path::PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let ServerArgs { config: _, port, host, #[cfg(unix)] unix_socket, #[cfg(unix)] socket_group, strict, api_token, rate_limit_rps, compression, http2, body_limit, max_connections, self_test, heartbeat,
+        #[cfg(feature = "mqtt")] mqtt_broker, #[cfg(feature = "mqtt")] mqtt_port, #[cfg(feature = "mqtt")] mqtt_client_id } = args;
+    // Flags override the config file
+    let port = port.unwrap_or(file.port);
+    let host = host.unwrap_or(file.host);
+    let api_token = api_token.or(file.api_token);
+    let rate_limit_rps = rate_limit_rps.unwrap_or(file.rate_limit_rps);
+    let strict = strict || file.strict;
+    let compression = compression || file.compression;
+    let http2 = http2 || file.http2;
+    let body_limit = body_limit.unwrap_or(file.body_limit);
+    let max_connections = max_connections.unwrap_or(file.max_connections);
+    if max_connections == 0 {
+        return Err("max_connections must be at least 1".into());
+    }
     println!("Train Set Control System - Web Server Mode");
     println!("Initializing LED controller...");
 
     // Initialize LED controller (24 LEDs on GPIO pins 4-27)
-    let leds = std::sync::Arc::new(LedController::new()?);
-    println!("LED controller initialized with {} LEDs", leds.count());
+    let leds = LedController::with_config(LedConfig { strict, dry_run, ..file.leds })?;
+    println!("LED controller initialized with {} LEDs{}", leds.count(), if dry_run { " (dry run)" } else { "" });
     println!("  Green LEDs: 1-6");
     println!("  Amber LEDs: 7-12");
     println!("  Red LEDs: 13-24");
 
+    let failed = leds.failed_leds();
+    if !failed.is_empty() {
+        println!("\nWARNING: running in degraded mode, {} LED(s) unavailable:", failed.len());
+        for (led, error) in &failed {
+            println!("  LED {}: {}", led, error);
+        }
+    }
+
+    // Catch miswired pins before they look like software bugs
+    for line in leds.diagnostics() {
+        tracing::info!(led = line.led, chip = %line.chip, line = line.line, output = line.output, active_low = line.active_low, drive = %line.drive, consumer = ?line.consumer, "GPIO line");
+        for warning in &line.warnings {
+            println!("WARNING: LED {} (line {} of {}): {}", line.led, line.line, line.chip, warning);
+        }
+    }
+
+    let self_test = if self_test {
+        println!("\nRunning LED self-test...");
+        let report = leds.self_test(SELF_TEST_STEP_MS, None).await?;
+        print_self_test(&report);
+        Some(report)
+    } else {
+        None
+    };
+
+    // Restore after the self-test, which leaves every LED off
+    let leds = match state_file {
+        Some(path) => {
+            println!("Restoring LED state from {}", path.display());
+            leds.with_persistence(path).await
+        }
+        None => {
+            leds.apply_initial().await?;
+            leds
+        }
+    };
+    let leds = std::sync::Arc::new(leds);
+
+    if let Some(led) = heartbeat {
+        let led = Led::new(led)?;
+        match leds.start_heartbeat(led).await {
+            Ok(()) => println!("Heartbeat running on LED {}", led),
+            Err(e) => println!("WARNING: heartbeat not started: {}", e),
+        }
+    }
+
+    tokio::spawn({
+        let leds = std::sync::Arc::clone(&leds);
+        async move { leds.run_scheduler().await }
+    });
+
+    #[cfg(feature = "mqtt")]
+    if let Some(broker) = mqtt_broker {
+        println!("Taking LED commands from MQTT broker {}:{}", broker, mqtt_port);
+        tokio::spawn({
+            let leds = std::sync::Arc::clone(&leds);
+            async move { train::run_mqtt(leds, &broker, mqtt_port, &mqtt_client_id).await }
+        });
+    }
+
+    // Cancelled on SIGINT/SIGTERM; shared with the blink tasks
+    let shutdown = leds.shutdown_token();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
     // Create application state
+    if api_token.is_some() {
+        println!("API token required for POST/PUT/DELETE requests");
+    }
     let app_state = AppState {
-        leds,
+        audit: AuditLog::record(&leds),
+        leds: std::sync::Arc::clone(&leds),
+        api_token,
+        self_test,
+        rate_limit_rps: (rate_limit_rps > 0).then_some(rate_limit_rps),
+        compression,
+        leases: Default::default(),
+        stats: Default::default(),
+        body_limit,
     };
 
     // Create router
     let app = create_router(app_state);
 
     // Start server
-    let addr = format!("{}:{}", host, port);
+    #[cfg(unix)]
+    if let Some(path) = unix_socket {
+        println!("\nStarting web server on unix:{}", path.display());
+        let listener = bind_unix_listener(&path, socket_group.as_deref())?;
+        serve_unix(listener, app, shutdown, http2, max_connections).await?;
+        let _ = std::fs::remove_file(&path);
+    } else {
+        serve_http(&host, port, app, shutdown, http2, max_connections).await?;
+    }
+    #[cfg(not(unix))]
+    serve_http(&host, port, app, shutdown, http2, max_connections).await?;
+
+    println!("\nShutting down, turning all LEDs off...");
+    leds.all_off().await?;
+
+    Ok(())
+}
+
+/// Serve the API over TCP until `shutdown` is cancelled
+async fn serve_http(host: &str, port: u16, app: axum::Router, shutdown: CancellationToken, http2: bool, max_connections: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let addr = bind_address(host, port);
     println!("\nStarting web server on http://{}", addr);
     println!("API endpoints available at http://{}/api", addr);
-    
-    let listener = TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+
+    let listener = bind_listener(host, port).await?;
+    if http2 {
+        println!("HTTP/2 (h2c) enabled");
+    }
+    serve_tcp(listener, app, shutdown, http2, max_connections).await?;
+    Ok(())
+}
+
+async fn run_snapshot(action: SnapshotAction) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+
+    match action {
+        SnapshotAction::Save { file, url, api_token } => {
+            let snapshot: LedSnapshot = with_token(client.post(format!("{}/api/leds/snapshot", url.trim_end_matches('/'))), api_token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            std::fs::write(&file, serde_json::to_string_pretty(&snapshot)?)?;
+            println!("Saved state of {} LEDs to {}", snapshot.leds.len(), file.display());
+        }
+        SnapshotAction::Load { file, url, api_token } => {
+            let snapshot: LedSnapshot = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+            with_token(client.post(format!("{}/api/leds/restore", url.trim_end_matches('/'))), api_token)
+                .json(&snapshot)
+                .send()
+                .await?
+                .error_for_status()?;
+            println!("Restored state of {} LEDs from {}", snapshot.leds.len(), file.display());
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_remote(url: &str, api_token: Option<String>, action: RemoteAction) -> Result<(), Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    let url = url.trim_end_matches('/');
+
+    // Bodies use the server's own request types, so the two can't drift apart
+    let request = match action {
+        RemoteAction::Led { led, command: RemoteLedCommand::On { ttl_ms } } => {
+            client.post(format!("{}/api/leds/{}/on", url, led)).json(&OnRequest { ttl_ms })
+        }
+        RemoteAction::Led { led, command: RemoteLedCommand::Off } => client.post(format!("{}/api/leds/{}/off", url, led)),
+        RemoteAction::Led { led, command: RemoteLedCommand::Blink { frequency_ms } } => {
+            client.post(format!("{}/api/leds/{}/blink", url, led))
+                .json(&BlinkRequest { frequency_ms: Some(frequency_ms), ..Default::default() })
+        }
+        RemoteAction::AllOff => client.post(format!("{}/api/leds/all/off", url)),
+    };
+
+    let response = with_token(request, api_token).send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        return Err(match response.json::<ErrorResponse>().await {
+            Ok(error) => format!("{} ({}): {}", status, error.code, error.message).into(),
+            Err(_) => format!("Server returned {}", status).into(),
+        });
+    }
+    let response: StatusResponse = response.json().await?;
+    println!("{}", response.message);
 
     Ok(())
 }
+
+/// Attach a bearer token to a request if one was given
+fn with_token(request: reqwest::RequestBuilder, api_token: Option<String>) -> reqwest::RequestBuilder {
+    match api_token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
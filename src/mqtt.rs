@@ -0,0 +1,141 @@
+use crate::error::{Result, TrainError};
+use crate::leds::{Led, LedController};
+use crate::schedule::LedCommand;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Standard MQTT broker port
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Topics LED commands arrive on, one per LED: `train/leds/{led}/set`
+const SET_TOPICS: &str = "train/leds/+/set";
+
+/// Pause before retrying after the broker connection fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The command for a message on `train/leds/{led}/set`
+/// Payloads are `on`, `off` or `blink:{ms}`, ignoring case and surrounding whitespace.
+pub fn parse_command(topic: &str, payload: &[u8]) -> Result<LedCommand> {
+    let led = topic.strip_prefix("train/leds/")
+        .and_then(|rest| rest.strip_suffix("/set"))
+        .ok_or_else(|| TrainError::InvalidParameter(format!("Not an LED command topic: {}", topic)))?;
+    let led = led.parse::<u8>()
+        .map_err(|_| TrainError::InvalidParameter(format!("Not an LED number: {}", led)))
+        .and_then(Led::new)?;
+
+    let payload = String::from_utf8_lossy(payload).trim().to_ascii_lowercase();
+    match payload.as_str() {
+        "on" => Ok(LedCommand::On { led }),
+        "off" => Ok(LedCommand::Off { led }),
+        _ => {
+            let frequency_ms = payload.strip_prefix("blink:")
+                .and_then(|ms| ms.trim().parse::<u64>().ok())
+                .ok_or_else(|| TrainError::InvalidParameter(
+                    format!("Unknown LED command '{}' (expected on, off or blink:<ms>)", payload)
+                ))?;
+            Ok(LedCommand::Blink { led, frequency_ms })
+        }
+    }
+}
+
+/// Take LED commands from an MQTT broker until the controller's shutdown token is cancelled
+///
+/// Subscribes to `train/leds/+/set` and runs each message through `LedController::execute()`;
+/// bad topics, payloads and failed commands are logged and skipped. Every commanded state
+/// change, whatever made it, is published to `train/leds/{led}/state` as the LED's state in
+/// JSON (e.g. `{"state":"blinking","frequency_ms":500}`), retained so new subscribers see it.
+/// Background effect toggles aren't published. A lost connection is retried every few seconds,
+/// resubscribing once it's back.
+pub async fn run_mqtt(leds: Arc<LedController>, broker: &str, port: u16, client_id: &str) {
+    let mut options = MqttOptions::new(client_id, broker, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    let shutdown = leds.shutdown_token();
+
+    let publisher = tokio::spawn({
+        let client = client.clone();
+        let mut events = leds.subscribe();
+        async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!(missed, "MQTT publisher fell behind; some LED states weren't published");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if event.old_state == event.new_state {
+                    continue;
+                }
+                let topic = format!("train/leds/{}/state", event.led);
+                let payload = serde_json::to_vec(&event.new_state).expect("LED states serialize");
+                if let Err(e) = client.publish(topic, QoS::AtLeastOnce, true, payload).await {
+                    tracing::warn!(error = %e, "MQTT publish failed");
+                }
+            }
+        }
+    });
+
+    loop {
+        let event = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            event = eventloop.poll() => event,
+        };
+        match event {
+            // Subscriptions don't survive a reconnect with a clean session, so make them on every connect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                tracing::info!(broker, port, "Connected to MQTT broker");
+                if let Err(e) = client.try_subscribe(SET_TOPICS, QoS::AtLeastOnce) {
+                    tracing::warn!(error = %e, "MQTT subscribe failed");
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => {
+                let result = match parse_command(&message.topic, &message.payload) {
+                    Ok(command) => leds.execute(&command).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    tracing::warn!(topic = %message.topic, error = %e, "MQTT command failed");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(broker, port, error = %e, "MQTT connection failed; retrying");
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                }
+            }
+        }
+    }
+
+    publisher.abort();
+    let _ = client.try_disconnect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_come_from_topic_and_payload() {
+        let led = |n| Led::new(n).unwrap();
+        assert_eq!(parse_command("train/leds/5/set", b"on").unwrap(), LedCommand::On { led: led(5) });
+        assert_eq!(parse_command("train/leds/24/set", b" OFF\n").unwrap(), LedCommand::Off { led: led(24) });
+        assert_eq!(parse_command("train/leds/1/set", b"blink:250").unwrap(), LedCommand::Blink { led: led(1), frequency_ms: 250 });
+
+        assert!(matches!(parse_command("train/leds/25/set", b"on"), Err(TrainError::LedOutOfRange { led: 25, .. })));
+        for (topic, payload) in [
+            ("train/leds/five/set", &b"on"[..]),
+            ("train/leds/5/state", b"on"),
+            ("train/leds/5/set", b"dim"),
+            ("train/leds/5/set", b"blink:"),
+            ("train/leds/5/set", b"blink:-3"),
+        ] {
+            assert!(matches!(parse_command(topic, payload), Err(TrainError::InvalidParameter(_))), "{} {:?}", topic, payload);
+        }
+    }
+}
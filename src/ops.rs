@@ -0,0 +1,161 @@
+use crate::error::{Result, TrainError};
+use crate::leds::{FlickerIntensity, Led, LedController, LedState};
+use std::ops::RangeInclusive;
+
+/// Fluent operations on a single LED, from `LedController::led()`
+///
+/// ```ignore
+/// controller.led(5).blink(500).await?;
+/// ```
+#[derive(Clone, Copy)]
+pub struct LedOps<'a> {
+    controller: &'a LedController,
+    led: u8,
+}
+
+impl LedOps<'_> {
+    /// The LED these operations apply to; fails if the number given to `led()` was out of range
+    pub fn get(&self) -> Result<Led> {
+        Led::new(self.led)
+    }
+
+    pub async fn on(self) -> Result<()> {
+        self.controller.on(self.get()?).await
+    }
+
+    pub async fn off(self) -> Result<()> {
+        self.controller.off(self.get()?).await
+    }
+
+    /// See `LedController::on_for()`
+    pub async fn on_for(self, ttl_ms: u64) -> Result<()> {
+        self.controller.on_for(self.get()?, ttl_ms).await
+    }
+
+    pub async fn blink(self, frequency_ms: u64) -> Result<()> {
+        self.controller.blink(self.get()?, frequency_ms).await
+    }
+
+    /// See `LedController::blink_count()`
+    pub async fn blink_count(self, frequency_ms: u64, cycles: u32) -> Result<()> {
+        self.controller.blink_count(self.get()?, frequency_ms, cycles).await
+    }
+
+    /// See `LedController::blink_with_phase()`
+    pub async fn blink_with_phase(self, frequency_ms: u64, phase_ms: u64) -> Result<()> {
+        self.controller.blink_with_phase(self.get()?, frequency_ms, phase_ms).await
+    }
+
+    pub async fn stop_blink(self) -> Result<()> {
+        self.controller.stop_blink(self.get()?).await
+    }
+
+    pub async fn flicker(self, intensity: FlickerIntensity, seed: Option<u64>) -> Result<()> {
+        self.controller.flicker(self.get()?, intensity, seed).await
+    }
+
+    pub async fn breathe(self, period_ms: u64, min: u8, max: u8) -> Result<()> {
+        self.controller.breathe(self.get()?, period_ms, min, max).await
+    }
+
+    pub async fn set_state(self, state: LedState) -> Result<()> {
+        self.controller.set_state(self.get()?, state).await
+    }
+
+    pub async fn state(self) -> Result<LedState> {
+        Ok(self.controller.state(self.get()?).await)
+    }
+}
+
+/// Fluent operations on a colour bank (or any contiguous range of LEDs), from `LedController::color()`
+///
+/// ```ignore
+/// controller.color(GREEN_LEDS).on().await?;
+/// controller.color(RED_LEDS).position(2).off().await?;
+/// ```
+#[derive(Clone)]
+pub struct ColorOps<'a> {
+    controller: &'a LedController,
+    subset: RangeInclusive<u8>,
+}
+
+impl<'a> ColorOps<'a> {
+    /// LEDs in the range; fails if it is empty or reaches outside 1-24
+    pub fn leds(&self) -> Result<Vec<Led>> {
+        if self.subset.is_empty() {
+            return Err(TrainError::InvalidParameter(
+                format!("LED range {}-{} is empty", self.subset.start(), self.subset.end())
+            ));
+        }
+        self.subset.clone().map(Led::new).collect()
+    }
+
+    /// Operations on the LED at `position` within the range (1-based)
+    pub fn position(&self, position: u8) -> Result<LedOps<'a>> {
+        let count = self.leds()?.len() as u8;
+        if !(1..=count).contains(&position) {
+            return Err(TrainError::PositionOutOfRange { position, max: count });
+        }
+        Ok(self.controller.led(self.subset.start() + position - 1))
+    }
+
+    /// Turn every LED in the range on, in a single backend write
+    pub async fn on(self) -> Result<()> {
+        self.controller.set_many(&self.leds()?, true).await
+    }
+
+    /// Turn every LED in the range off, in a single backend write
+    pub async fn off(self) -> Result<()> {
+        self.controller.set_many(&self.leds()?, false).await
+    }
+
+    /// Blink every LED in the range; the frequency is checked before any LED changes
+    pub async fn blink(self, frequency_ms: u64) -> Result<()> {
+        let leds = self.leds()?;
+        let (min_ms, max_ms) = self.controller.blink_limits();
+        if !(min_ms..=max_ms).contains(&frequency_ms) {
+            return Err(TrainError::InvalidFrequency { frequency_ms, min_ms, max_ms });
+        }
+        for led in leds {
+            self.controller.blink(led, frequency_ms).await?;
+        }
+        Ok(())
+    }
+}
+
+impl LedController {
+    /// Fluent operations on one LED, e.g. `controller.led(5).blink(500)`
+    /// An out-of-range number is reported when an operation runs
+    pub fn led(&self, n: u8) -> LedOps<'_> {
+        LedOps { controller: self, led: n }
+    }
+
+    /// Fluent operations on a range of LEDs, e.g. `controller.color(GREEN_LEDS).on()`
+    pub fn color(&self, subset: RangeInclusive<u8>) -> ColorOps<'_> {
+        ColorOps { controller: self, subset }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use crate::leds::{GREEN_LEDS, RED_LEDS};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn fluent_calls_match_the_direct_methods() {
+        let leds = LedController::with_backend(Arc::new(MockBackend::new()));
+
+        leds.color(GREEN_LEDS).on().await.unwrap();
+        leds.color(RED_LEDS).position(2).unwrap().blink(500).await.unwrap();
+        assert_eq!(leds.led(14).state().await.unwrap(), LedState::Blinking { frequency_ms: 500 });
+        assert_eq!(leds.led(3).state().await.unwrap(), LedState::On);
+        assert!(matches!(leds.led(25).on().await, Err(TrainError::LedOutOfRange { .. })));
+        assert!(matches!(leds.color(RED_LEDS).position(13), Err(TrainError::PositionOutOfRange { .. })));
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = leds.color(6..=1).on().await;
+        assert!(matches!(empty, Err(TrainError::InvalidParameter(_))));
+        leds.all_off().await.unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use crate::leds::LedSnapshot;
+use std::path::Path;
+use tokio::time::Duration;
+
+/// Minimum time between writes of the state file
+pub(crate) const PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Read a saved snapshot, or `None` (with a warning) if the file is missing or unreadable
+pub(crate) async fn load(path: &Path) -> Option<LedSnapshot> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "No saved LED state, starting dark");
+            return None;
+        }
+    };
+    match serde_json::from_str(&contents) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            tracing::warn!(path = %path.display(), error = %e, "Saved LED state is corrupt, starting dark");
+            None
+        }
+    }
+}
+
+/// Write a snapshot via a temporary file, so a crash mid-write never leaves a truncated state file
+pub(crate) async fn save(path: &Path, snapshot: &LedSnapshot) {
+    let tmp = path.with_extension("tmp");
+    let result = async {
+        let json = serde_json::to_vec_pretty(snapshot).map_err(std::io::Error::other)?;
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, path).await
+    }.await;
+    if let Err(e) = result {
+        tracing::warn!(path = %path.display(), error = %e, "Failed to save LED state");
+    }
+}
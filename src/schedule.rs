@@ -0,0 +1,130 @@
+use crate::leds::Led;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{Notify, RwLock};
+use utoipa::ToSchema;
+
+/// Longest the scheduler sleeps before re-reading the wall clock
+/// Deadlines are re-evaluated rather than slept through, so a clock jump (e.g. NTP sync after boot)
+/// delays or advances a command by at most this much
+pub(crate) const CLOCK_RECHECK: Duration = Duration::from_secs(1);
+
+/// A command the controller can run later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum LedCommand {
+    On { led: Led },
+    Off { led: Led },
+    Blink { led: Led, frequency_ms: u64 },
+    GroupOn { group: String },
+    GroupOff { group: String },
+    GroupBlink { group: String, frequency_ms: u64 },
+    AllOff,
+}
+
+/// Identifies a scheduled command, for cancelling it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+#[schema(value_type = u64)]
+pub struct ScheduleId(u64);
+
+impl fmt::Display for ScheduleId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A command waiting for its time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ScheduledCommand {
+    pub id: ScheduleId,
+    /// Wall-clock time the command next runs at (UTC, RFC 3339 in JSON)
+    pub at: DateTime<Utc>,
+    pub command: LedCommand,
+    /// Repeat this often after `at`; runs once if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every_secs: Option<u64>,
+}
+
+impl ScheduledCommand {
+    /// The next run after `now` of a recurring command; missed runs are skipped, not caught up
+    fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let every = chrono::Duration::seconds(i64::try_from(self.every_secs?).ok()?);
+        let mut next = self.at + every;
+        if next <= now {
+            let missed = (now - self.at).num_seconds() / every.num_seconds();
+            next = self.at + every * i32::try_from(missed + 1).ok()?;
+        }
+        Some(next)
+    }
+}
+
+/// Pending scheduled commands
+/// Saved with the LED state when the controller has persistence enabled.
+#[derive(Default)]
+pub(crate) struct Schedules {
+    next_id: AtomicU64,
+    pending: RwLock<BTreeMap<ScheduleId, ScheduledCommand>>,
+    /// Woken when a command is added, so the scheduler can pick up an earlier deadline
+    pub(crate) changed: Notify,
+}
+
+impl Schedules {
+    pub(crate) async fn add(&self, at: DateTime<Utc>, command: LedCommand, every: Option<Duration>) -> ScheduleId {
+        let id = ScheduleId(self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let every_secs = every.map(|every| every.as_secs());
+        self.pending.write().await.insert(id, ScheduledCommand { id, at, command, every_secs });
+        self.changed.notify_one();
+        id
+    }
+
+    /// Replace every pending command, e.g. with ones loaded from disk, keeping their IDs
+    pub(crate) async fn replace(&self, commands: Vec<ScheduledCommand>) {
+        let mut pending = self.pending.write().await;
+        *pending = commands.into_iter().map(|scheduled| (scheduled.id, scheduled)).collect();
+        let last = pending.keys().last().map_or(0, |id| id.0);
+        self.next_id.fetch_max(last, Ordering::Relaxed);
+        self.changed.notify_one();
+    }
+
+    /// Remove a pending command; false if it has already run or never existed
+    pub(crate) async fn cancel(&self, id: ScheduleId) -> bool {
+        self.pending.write().await.remove(&id).is_some()
+    }
+
+    /// Pending commands, soonest first
+    pub(crate) async fn list(&self) -> Vec<ScheduledCommand> {
+        let mut list: Vec<ScheduledCommand> = self.pending.read().await.values().cloned().collect();
+        list.sort_by_key(|scheduled| (scheduled.at, scheduled.id));
+        list
+    }
+
+    /// Remove and return the commands due at `now`, soonest first
+    /// Recurring commands stay pending, moved on to their next run.
+    pub(crate) async fn take_due(&self, now: DateTime<Utc>) -> Vec<ScheduledCommand> {
+        let mut pending = self.pending.write().await;
+        let due: Vec<ScheduleId> = pending.values()
+            .filter(|scheduled| scheduled.at <= now)
+            .map(|scheduled| scheduled.id)
+            .collect();
+        let mut due: Vec<ScheduledCommand> = due.into_iter()
+            .filter_map(|id| pending.remove(&id))
+            .collect();
+        for scheduled in &due {
+            if let Some(at) = scheduled.next_after(now) {
+                pending.insert(scheduled.id, ScheduledCommand { at, ..scheduled.clone() });
+            }
+        }
+        due.sort_by_key(|scheduled| (scheduled.at, scheduled.id));
+        due
+    }
+
+    /// Deadline of the soonest pending command
+    pub(crate) async fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.pending.read().await.values().map(|scheduled| scheduled.at).min()
+    }
+}
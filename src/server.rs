@@ -1,50 +1,837 @@
-use crate::LedController;
+use crate::{AuditEntry, AuditLog, LedOperation, LineDiagnostics, LedCommand, ScheduleId, ScheduledCommand, AMBER_LEDS, GREEN_LEDS, RED_LEDS, FadeDirection, FlickerIntensity, Led, LedColor, LedController, LedMapping, LedSnapshot, LedSnapshotEntry, LedState, SelfTestFailure, SelfTestReport, TrainError};
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    routing::{get, post},
-    Router,
+    async_trait,
+    extract::{rejection::{JsonRejection, PathRejection}, ConnectInfo, DefaultBodyLimit, FromRequest, FromRequestParts, Path, Query, Request, State},
+    http::request::Parts,
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
+    Extension, Router,
 };
-use serde::{Deserialize, Serialize};
+use futures::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{broadcast, RwLock, Semaphore};
+use tower::ServiceBuilder;
+use tower_governor::{governor::GovernorConfigBuilder, GovernorError, GovernorLayer};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::CorsLayer;
+use tower_http::limit::RequestBodyLimitLayer;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use utoipa::{OpenApi, ToSchema};
 
 #[derive(Clone)]
 pub struct AppState {
     pub leds: Arc<LedController>,
+    /// Bearer token required on mutating requests; `None` disables auth
+    pub api_token: Option<String>,
+    /// Result of the startup self-test, if the server ran one
+    pub self_test: Option<SelfTestReport>,
+    /// Requests per second allowed from each client IP; `None` disables rate limiting
+    /// Limiting keys on the peer address, so serve with `into_make_service_with_connect_info::<SocketAddr>()`
+    pub rate_limit_rps: Option<u32>,
+    /// Gzip/Brotli-compress responses for clients that send `Accept-Encoding`
+    pub compression: bool,
+    /// Exclusive-control leases held on individual LEDs
+    pub leases: Arc<RwLock<HashMap<Led, Lease>>>,
+    /// Counters behind `GET /api/stats`
+    pub stats: Arc<ServerStats>,
+    /// Largest request body accepted, in bytes; bigger ones get `413 Payload Too Large`
+    pub body_limit: usize,
+    /// Recent LED commands for `GET /api/leds/log`; see `AuditLog::record()`
+    pub audit: Arc<AuditLog>,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Default for `AppState::body_limit`: far more than any API request needs
+pub const DEFAULT_BODY_LIMIT: usize = 64 * 1024;
+
+/// Default cap on simultaneous connections, so clients can't exhaust a Pi's file descriptors
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+/// Running totals for `GET /api/stats`, updated by the `count_operations` middleware
+#[derive(Debug)]
+pub struct ServerStats {
+    started: Instant,
+    /// Mutating requests that succeeded
+    operations: AtomicU64,
+    /// Requests that failed talking to the hardware (GPIO, I2C, timeouts)
+    gpio_errors: AtomicU64,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self { started: Instant::now(), operations: AtomicU64::new(0), gpio_errors: AtomicU64::new(0) }
+    }
+}
+
+/// Exclusive control of an LED, granted by `POST /api/leds/:led/lock`
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub token: String,
+    pub expires: Instant,
+}
+
+/// How long a lease lasts unless renewed by locking again with the same token
+pub const LEASE_TTL: Duration = Duration::from_secs(60);
+
+/// Header carrying a lease token on mutating requests
+const LEASE_HEADER: &str = "x-lease-token";
+
+/// Error with 423 unless every LED in `leds` is unleased, its lease has expired, or the request carries its token
+async fn check_leases(state: &AppState, leds: impl IntoIterator<Item = Led>, headers: &HeaderMap) -> Result<(), ApiError> {
+    let provided = headers.get(LEASE_HEADER).and_then(|value| value.to_str().ok());
+    let leases = state.leases.read().await;
+    let now = Instant::now();
+    for led in leds {
+        if let Some(lease) = leases.get(&led)
+            && lease.expires > now
+            && provided != Some(lease.token.as_str())
+        {
+            return Err(locked(led));
+        }
+    }
+    Ok(())
+}
+
+fn locked(led: Led) -> ApiError {
+    ApiError::new(StatusCode::LOCKED, "LED_LOCKED", format!("LED {} is locked by another client", led))
+}
+
+/// LED addressed in a route path for a mutating request
+/// Like `LedIdent`, but rejects with 423 if another client holds a lease on the LED
+pub struct LeasedLed(pub Led);
+
+#[async_trait]
+impl FromRequestParts<AppState> for LeasedLed {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let LedIdent(led) = LedIdent::from_request_parts(parts, state).await?;
+        check_leases(state, [led], &parts.headers).await?;
+        Ok(LeasedLed(led))
+    }
+}
+
+/// LED addressed in a route path, by number (`/api/leds/5`) or alias (`/api/leds/platform1`)
+/// Rejects unknown aliases and out-of-range numbers with 404
+pub struct LedIdent(pub Led);
+
+#[async_trait]
+impl FromRequestParts<AppState> for LedIdent {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Path(ident) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, "LED_NOT_FOUND", e.body_text()))?;
+        let led = match ident.parse::<u8>() {
+            Ok(led) => Led::new(led)?,
+            Err(_) => state.leds.resolve_alias(&ident).await
+                .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, "LED_NOT_FOUND", e.to_string()))?,
+        };
+        Ok(LedIdent(led))
+    }
+}
+
+/// JSON request body, rejected with an `ApiError` body (rather than axum's plain text) when
+/// it's malformed, doesn't match the expected shape or isn't sent as `application/json`
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for ApiJson<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(ApiJson(value))
+    }
+}
+
+/// Path parameters, rejected with an `ApiError` body when they don't parse
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T: DeserializeOwned + Send, S: Send + Sync> FromRequestParts<S> for ApiPath<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state).await?;
+        Ok(ApiPath(value))
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct LedResponse {
-    pub led: u8,
-    pub state: String, // "on", "off", or "blinking"
+    pub led: Led,
+    pub state: String, // "on", "off", "blinking", "flickering", "breathing", "dimmed", "pulsing" or "fading"
+    /// Milliseconds until an LED turned on with a TTL goes off again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in_ms: Option<u64>,
+    /// Toggle interval of a blinking LED
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_ms: Option<u64>,
+    /// Whether the LED is lit at this instant (for a blinking LED, which half of the cycle it's in)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lit: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize)]
+/// LEDs that are not off, from `GET /api/leds/active`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ActiveLedsResponse {
+    pub count: usize,
+    pub leds: Vec<LedResponse>,
+}
+
+impl LedResponse {
+    /// State of an LED, including any pending auto-off and where a blink is in its cycle
+    async fn of(leds: &LedController, led: Led, state: LedState) -> Self {
+        let expires_in_ms = leds.expires_in(led).await
+            .map(|ttl| u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX));
+        let lit = leds.is_lit(led).await;
+        Self { expires_in_ms, lit, ..Self::from((led, state)) }
+    }
+}
+
+impl From<(Led, LedState)> for LedResponse {
+    fn from((led, state): (Led, LedState)) -> Self {
+        let frequency_ms = match state {
+            LedState::Blinking { frequency_ms } => Some(frequency_ms),
+            _ => None,
+        };
+        let state = match state {
+            LedState::On => "on",
+            LedState::Off => "off",
+            LedState::Blinking { .. } => "blinking",
+            LedState::Flickering { .. } => "flickering",
+            LedState::Breathing { .. } => "breathing",
+            LedState::Dimmed { .. } => "dimmed",
+            LedState::Pulsing { .. } => "pulsing",
+            LedState::Fading { .. } => "fading",
+        };
+        Self { led, state: state.to_string(), expires_in_ms: None, frequency_ms, lit: None }
+    }
+}
+
+/// Optional body for `POST /api/leds/{led}/on`
+#[derive(Default, Serialize, Deserialize, ToSchema)]
+pub struct OnRequest {
+    /// Turn the LED off again after this many milliseconds; repeating the request restarts the timer
+    #[serde(default)]
+    pub ttl_ms: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize, ToSchema)]
 pub struct BlinkRequest {
-    pub frequency_ms: u64,
+    /// Toggle interval; give this or `hz`, not both
+    #[serde(default)]
+    pub frequency_ms: Option<u64>,
+    /// Full on-off cycles per second, so the LED toggles every `1000 / (2 * hz)` ms
+    #[serde(default)]
+    pub hz: Option<f64>,
+    /// Stop after this many on/off cycles; blinks until told otherwise if omitted
+    #[serde(default)]
+    pub cycles: Option<u32>,
+    /// Delay the first toggle by this many milliseconds (modulo the on/off period);
+    /// `frequency_ms` blinks out of step with an LED started without one. Not combinable with `cycles`
+    #[serde(default)]
+    pub phase_ms: Option<u64>,
+}
+
+impl BlinkRequest {
+    /// Toggle interval in milliseconds, from whichever of `frequency_ms` and `hz` was given
+    fn interval_ms(&self) -> Result<u64, ApiError> {
+        let interval = match (self.frequency_ms, self.hz) {
+            (Some(frequency_ms), None) => frequency_ms,
+            (None, Some(hz)) => LedController::interval_for_hz(hz)
+                .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "INVALID_FREQUENCY", e.to_string()))?,
+            _ => {
+                return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", "Give exactly one of frequency_ms and hz"));
+            }
+        };
+        if interval == 0 {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "INVALID_FREQUENCY", "Blink frequency must be greater than 0"));
+        }
+        Ok(interval)
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BrightnessRequest {
+    /// 0 (off) to 255 (fully on); levels in between use software PWM
+    pub level: u8,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct PulseRequest {
+    /// Time to ramp from off to full brightness
+    pub rise_ms: u64,
+    /// Time to ramp back down to off
+    pub fall_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct FadeRequest {
+    pub direction: FadeDirection,
+    /// Time the fade takes; 0 switches the LED straight on or off
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct NameRequest {
+    pub name: String,
 }
 
-#[derive(Serialize)]
+/// Traffic light cycle: which LEDs act as the lamps and how long each stays lit
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TrafficRequest {
+    pub green: Led,
+    pub amber: Led,
+    pub red: Led,
+    pub green_ms: u64,
+    pub amber_ms: u64,
+    pub red_ms: u64,
+}
+
+/// A command to run at a wall-clock time, optionally repeating
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ScheduleRequest {
+    /// When to run, as RFC 3339 (e.g. "2024-01-01T09:00:00Z"); a past time runs at once
+    pub at: DateTime<Utc>,
+    pub action: LedCommand,
+    /// Repeat this often after `at`
+    #[serde(default)]
+    pub every_secs: Option<u64>,
+}
+
+/// Railway signal simulation: lamp positions within each colour bank (1-based) and cycle length
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TrafficLightRequest {
+    pub green_pos: u8,
+    pub amber_pos: u8,
+    pub red_pos: u8,
+    pub cycle_secs: u64,
+}
+
+/// Lease granted by `POST /api/leds/:led/lock`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct LeaseResponse {
+    pub status: String,
+    pub led: Led,
+    /// Send as `X-Lease-Token` on mutating requests for this LED
+    pub token: String,
+    pub expires_in_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct StatusResponse {
     pub status: String,
     pub message: String,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct FailedLed {
+    pub led: u8,
+    pub error: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StatsResponse {
+    pub uptime_secs: u64,
+    /// Successful POST/PUT/PATCH/DELETE requests since startup
+    pub operations: u64,
+    /// Background effect tasks running right now; a blink shared by several LEDs counts once
+    pub active_effects: usize,
+    /// Requests that failed with a hardware error since startup
+    pub gpio_errors: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InfoResponse {
+    pub status: String, // "ok" or "degraded"
+    pub led_count: usize,
+    pub failed_leds: Vec<FailedLed>,
+}
+
+/// OpenAPI document for every route, served at `/api/openapi.json`
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Train Set Control API", description = "Control the train set's indicator LEDs"),
+    paths(
+        root, health, get_info, get_stats, get_diagnostics, get_mapping, get_self_test,
+        get_all_leds, patch_leds, get_led_log, get_events, get_active_leds, get_led, set_led_on, set_led_off, set_led_blink, set_led_brightness, pulse_led, fade_led,
+        get_led_names, set_led_name, release_led, acquire_led,
+        lock_led, unlock_led, set_all_leds_off, set_all_leds_blink, set_color_on, set_color_off, set_color_blink, set_zone_on, set_zone_off, set_zone_blink, take_snapshot, restore_snapshot, start_traffic, simulate_traffic_light, stop_all, panic,
+        list_schedules, add_schedule, cancel_schedule,
+    ),
+    components(schemas(
+        Led, LedColor, LedMapping, LedState, FlickerIntensity, FadeDirection, LedSnapshot, LedSnapshotEntry,
+        SelfTestReport, SelfTestFailure, LineDiagnostics, AuditEntry, LedOperation,
+        LedResponse, ActiveLedsResponse, OnRequest, BlinkRequest, BrightnessRequest, PulseRequest, FadeRequest, NameRequest, TrafficRequest, TrafficLightRequest,
+        ScheduleRequest, ScheduledCommand, LedCommand,
+        LeaseResponse, StatusResponse, InfoResponse, StatsResponse, FailedLed, ErrorResponse,
+    )),
+    tags(
+        (name = "system", description = "Server status and wiring"),
+        (name = "leds", description = "Individual LEDs, aliases and snapshots"),
+        (name = "effects", description = "Multi-LED patterns"),
+        (name = "schedule", description = "Commands run at wall-clock times"),
+    ),
+)]
+pub struct ApiDoc;
+
 pub fn create_router(state: AppState) -> Router {
-    Router::new()
+    let mut api = Router::new()
         .route("/", get(root))
-        .route("/api/leds", get(get_all_leds))
+        .route("/api/info", get(get_info))
+        .route("/api/stats", get(get_stats))
+        .route("/api/diagnostics", get(get_diagnostics))
+        .route("/api/mapping", get(get_mapping))
+        .route("/api/self-test", get(get_self_test))
+        .route("/api/leds", get(get_all_leds).patch(patch_leds))
+        .route("/api/leds/log", get(get_led_log))
+        .route("/api/events", get(get_events))
+        .route("/api/leds/names", get(get_led_names))
+        .route("/api/leds/active", get(get_active_leds))
         .route("/api/leds/:led", get(get_led))
+        .route("/api/leds/:led/name", put(set_led_name))
         .route("/api/leds/:led/on", post(set_led_on))
         .route("/api/leds/:led/off", post(set_led_off))
         .route("/api/leds/:led/blink", post(set_led_blink))
+        .route("/api/leds/:led/brightness", put(set_led_brightness))
+        .route("/api/leds/:led/pulse", post(pulse_led))
+        .route("/api/leds/:led/fade", post(fade_led))
+        .route("/api/leds/:led/release", post(release_led))
+        .route("/api/leds/:led/acquire", post(acquire_led))
+        .route("/api/leds/:led/lock", post(lock_led))
+        .route("/api/leds/:led/unlock", post(unlock_led))
         .route("/api/leds/all/off", post(set_all_leds_off))
-        .layer(CorsLayer::permissive())
+        .route("/api/leds/all/blink", post(set_all_leds_blink))
+        .route("/api/zone/:name/on", post(set_zone_on))
+        .route("/api/zone/:name/off", post(set_zone_off))
+        .route("/api/zone/:name/blink", post(set_zone_blink))
+        .route("/api/leds/snapshot", post(take_snapshot))
+        .route("/api/leds/restore", post(restore_snapshot))
+        .route("/api/traffic", post(start_traffic))
+        .route("/api/simulate/traffic-light", post(simulate_traffic_light))
+        .route("/api/stop-all", post(stop_all))
+        .route("/api/panic", post(panic))
+        .route("/api/schedule", get(list_schedules).post(add_schedule))
+        .route("/api/schedule/:id", delete(cancel_schedule));
+    // Static colour paths take priority over `/api/leds/:led/...`
+    for color in LedColor::ALL {
+        api = api
+            .route(&format!("/api/leds/{}/on", color), post(move |state, headers| set_color_on(state, headers, color)))
+            .route(&format!("/api/leds/{}/off", color), post(move |state, headers| set_color_off(state, headers, color)))
+            .route(&format!("/api/leds/{}/blink", color), post(move |state, headers, body| set_color_blink(state, headers, body, color)));
+    }
+
+    if let Some(rps) = state.rate_limit_rps.filter(|&rps| rps > 0) {
+        let config = GovernorConfigBuilder::default()
+            .per_nanosecond(1_000_000_000 / u64::from(rps))
+            .burst_size(rps)
+            .error_handler(rate_limit_error)
+            .finish()
+            .expect("rate limit period and burst are non-zero");
+        api = api.layer(GovernorLayer { config: Arc::new(config) });
+    }
+
+    // Health checks stay outside the rate limit so monitoring never gets throttled
+    let mut router = Router::new()
+        .route("/api/health", get(health))
+        .route("/api/openapi.json", get(get_openapi))
+        .merge(api);
+
+    if state.compression {
+        router = router.layer(CompressionLayer::new().gzip(true).br(true));
+    }
+
+    router
+        // axum's own 2 MB extractor limit would otherwise cap larger configured limits
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(state.body_limit))
+        .layer(middleware::map_response(payload_too_large))
+        .layer(middleware::from_fn_with_state(state.clone(), count_operations))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .layer(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(request_id))
+                .layer(CorsLayer::permissive()),
+        )
         .with_state(state)
 }
 
+/// Address the server listens on, with IPv6 literals bracketed (`::1` -> `[::1]:8080`)
+pub fn bind_address(host: &str, port: u16) -> String {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", host, port),
+    }
+}
+
+/// Bind the API listener. An unspecified IPv6 host (`::`) is bound dual-stack,
+/// so it accepts IPv4 clients too regardless of the system's `bindv6only` default.
+pub async fn bind_listener(host: &str, port: u16) -> std::io::Result<TcpListener> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let Ok(ip) = host.parse::<IpAddr>() else {
+        return TcpListener::bind((host, port)).await;
+    };
+
+    let addr = SocketAddr::new(ip, port);
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if ip.is_ipv6() && ip.is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Peer address given to requests arriving on the Unix socket, which have none
+/// All local socket clients share one rate-limit bucket
+#[cfg(unix)]
+const UNIX_PEER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Bind a Unix domain socket for the API, readable and writable by its owner and group (0660)
+/// A socket left behind by a previous run is replaced; `group` (a name or gid) is given ownership
+#[cfg(unix)]
+pub fn bind_unix_listener(path: &std::path::Path, group: Option<&str>) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+
+    if let Some(group) = group {
+        let gid = match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => nix::unistd::Group::from_name(group)
+                .map_err(std::io::Error::from)?
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("No group named '{}'", group)))?
+                .gid
+                .as_raw(),
+        };
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+    }
+    Ok(listener)
+}
+
+/// Serve the API on a Unix domain socket until `shutdown` is cancelled
+/// In-flight requests are allowed to finish; the socket file is left for the caller to remove.
+/// With `http2`, clients may also speak HTTP/2 with prior knowledge. At most `max_connections`
+/// are served at once; see `serve_tcp()`.
+#[cfg(unix)]
+pub async fn serve_unix(listener: UnixListener, app: Router, shutdown: CancellationToken, http2: bool, max_connections: usize) -> std::io::Result<()> {
+    let accept = || async {
+        listener.accept().await.map(|(stream, _)| (stream, UNIX_PEER))
+    };
+    serve_connections(accept, app, shutdown, http2, max_connections).await
+}
+
+/// Serve the API over TCP until `shutdown` is cancelled, letting in-flight requests finish
+/// With `http2`, cleartext HTTP/2 (h2c) is accepted alongside HTTP/1.1; clients must connect with
+/// prior knowledge. An HTTP/1.1 request asking to `Upgrade: h2c` is answered over HTTP/1.1, since
+/// hyper can't hand an upgraded request to its HTTP/2 server (and RFC 9113 deprecates it). Connections beyond
+/// `max_connections` are sent `503 Service Unavailable` and closed.
+pub async fn serve_tcp(listener: TcpListener, app: Router, shutdown: CancellationToken, http2: bool, max_connections: usize) -> std::io::Result<()> {
+    serve_connections(|| listener.accept(), app, shutdown, http2, max_connections).await
+}
+
+/// Accept loop shared by the TCP and Unix socket listeners
+/// Each connection's peer address is exposed as `ConnectInfo<SocketAddr>`, as the rate limiter expects.
+async fn serve_connections<A, F, IO>(mut accept: A, app: Router, shutdown: CancellationToken, http2: bool, max_connections: usize) -> std::io::Result<()>
+where
+    A: FnMut() -> F,
+    F: std::future::Future<Output = std::io::Result<(IO, SocketAddr)>>,
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use hyper_util::server::graceful::GracefulShutdown;
+    use hyper_util::service::TowerToHyperService;
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    if !http2 {
+        builder = builder.http1_only();
+    }
+    let graceful = GracefulShutdown::new();
+    let connections = Arc::new(Semaphore::new(max_connections));
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to accept connection");
+                    continue;
+                }
+            },
+        };
+        // Held until the connection closes
+        let Ok(permit) = Arc::clone(&connections).try_acquire_owned() else {
+            tracing::warn!(%peer, max_connections, "Too many connections, turning one away");
+            tokio::spawn(reject_connection(stream));
+            continue;
+        };
+        let service = TowerToHyperService::new(app.clone().layer(Extension(ConnectInfo(peer))));
+        let connection = graceful.watch(builder.serve_connection(TokioIo::new(stream), service).into_owned());
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!(error = %e, "Connection closed with an error");
+            }
+            drop(permit);
+        });
+    }
+
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Answer a connection over the limit with a bare HTTP/1.1 503 and close it
+/// The request is never read, so this works whatever the client sent; a client that won't take
+/// the response is dropped after a second.
+async fn reject_connection<IO: tokio::io::AsyncWrite + Unpin>(mut stream: IO) {
+    use tokio::io::AsyncWriteExt;
+
+    let body = serde_json::to_string(&ErrorResponse {
+        status: "error".to_string(),
+        code: "TOO_MANY_CONNECTIONS".to_string(),
+        message: "Too many connections, try again shortly".to_string(),
+        min_ms: None,
+        max_ms: None,
+    }).expect("error bodies serialize");
+    let response = format!(
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Type: application/json\r\nContent-Length: {}\r\nRetry-After: 1\r\nConnection: close\r\n\r\n{}",
+        body.len(), body,
+    );
+    let _ = tokio::time::timeout(Duration::from_secs(1), async {
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }).await;
+}
+
+/// JSON error body: `{ "status": "error", "code": "LED_NOT_FOUND", "message": "..." }`
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub code: String,
+    pub message: String,
+    /// Allowed blink interval range, for `INVALID_FREQUENCY` errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ms: Option<u64>,
+}
+
+/// Error returned by API handlers, rendered as an HTTP status plus `ErrorResponse` body
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    /// Allowed blink interval range, for `INVALID_FREQUENCY` errors
+    pub limits: Option<(u64, u64)>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into(), limits: None }
+    }
+}
+
+impl From<TrainError> for ApiError {
+    fn from(err: TrainError) -> Self {
+        let (status, code) = match &err {
+            TrainError::LedOutOfRange { .. } => (StatusCode::NOT_FOUND, "LED_NOT_FOUND"),
+            TrainError::PositionOutOfRange { .. } => (StatusCode::UNPROCESSABLE_ENTITY, "POSITION_OUT_OF_RANGE"),
+            TrainError::InvalidFrequency { .. } => (StatusCode::UNPROCESSABLE_ENTITY, "INVALID_FREQUENCY"),
+            TrainError::InvalidParameter(_) => (StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER"),
+            TrainError::BlinkNotActive(_) => (StatusCode::CONFLICT, "BLINK_NOT_ACTIVE"),
+            TrainError::LedReleased(_) => (StatusCode::CONFLICT, "LED_RELEASED"),
+            TrainError::DeviceBusy { .. } => (StatusCode::CONFLICT, "DEVICE_BUSY"),
+            TrainError::Busy(_) => (StatusCode::CONFLICT, "BUSY"),
+            TrainError::LedUnavailable(..) => (StatusCode::SERVICE_UNAVAILABLE, "LED_UNAVAILABLE"),
+            TrainError::TooManyEffects { .. } => (StatusCode::SERVICE_UNAVAILABLE, "TOO_MANY_EFFECTS"),
+            TrainError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, "TIMEOUT"),
+            TrainError::StateWaitTimeout { .. } => (StatusCode::GATEWAY_TIMEOUT, "WAIT_TIMEOUT"),
+            TrainError::DeviceNotFound => (StatusCode::SERVICE_UNAVAILABLE, "DEVICE_NOT_FOUND"),
+            TrainError::NotSupported => (StatusCode::NOT_IMPLEMENTED, "NOT_SUPPORTED"),
+            TrainError::GPIO(_) => (StatusCode::INTERNAL_SERVER_ERROR, "GPIO_ERROR"),
+            TrainError::I2C(_) => (StatusCode::INTERNAL_SERVER_ERROR, "I2C_ERROR"),
+            TrainError::Hardware(_) => (StatusCode::INTERNAL_SERVER_ERROR, "HARDWARE_ERROR"),
+            TrainError::PartialFailure(_) => (StatusCode::INTERNAL_SERVER_ERROR, "PARTIAL_FAILURE"),
+            TrainError::Config { .. } => (StatusCode::INTERNAL_SERVER_ERROR, "CONFIG_ERROR"),
+        };
+        let limits = match err {
+            TrainError::InvalidFrequency { min_ms, max_ms, .. } => Some((min_ms, max_ms)),
+            _ => None,
+        };
+        Self { status, code, message: err.to_string(), limits }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => payload_too_large_error(),
+            status => Self::new(status, "INVALID_PARAMETER", rejection.body_text()),
+        }
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(rejection.status(), "INVALID_PARAMETER", rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            status: "error".to_string(),
+            code: self.code.to_string(),
+            message: self.message,
+            min_ms: self.limits.map(|(min, _)| min),
+            max_ms: self.limits.map(|(_, max)| max),
+        };
+        let mut response = (self.status, Json(body)).into_response();
+        response.extensions_mut().insert(ErrorCode(self.code));
+        response
+    }
+}
+
+/// `code` of an `ApiError` response, for middleware that looks at failures
+#[derive(Debug, Clone, Copy)]
+struct ErrorCode(&'static str);
+
+/// Error codes that mean the hardware itself failed, rather than the request
+const HARDWARE_ERROR_CODES: [&str; 5] = ["GPIO_ERROR", "I2C_ERROR", "HARDWARE_ERROR", "TIMEOUT", "PARTIAL_FAILURE"];
+
+/// Render rate limiter rejections in the API's error format
+fn rate_limit_error(error: GovernorError) -> Response {
+    match error {
+        GovernorError::TooManyRequests { wait_time, .. } => {
+            let retry_after = wait_time.max(1);
+            let mut response = ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+                format!("Too many requests, retry in {}s", retry_after),
+            ).into_response();
+            response.headers_mut().insert(header::RETRY_AFTER, retry_after.into());
+            response
+        }
+        GovernorError::UnableToExtractKey => ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL_ERROR",
+            "Couldn't determine the client address for rate limiting",
+        ).into_response(),
+        GovernorError::Other { code, msg, .. } => ApiError::new(
+            code,
+            "RATE_LIMITED",
+            msg.unwrap_or_else(|| "Request rejected by the rate limiter".to_string()),
+        ).into_response(),
+    }
+}
+
+/// The error for a request body over the configured limit
+fn payload_too_large_error() -> ApiError {
+    ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "PAYLOAD_TOO_LARGE", "Request body too large")
+}
+
+/// Render body limit rejections in the API's error format
+/// An oversized `Content-Length` is refused by `RequestBodyLimitLayer` itself and arrives here as a
+/// bare 413; a body that only turns out too big while being read is reported by `ApiJson`.
+async fn payload_too_large(response: Response) -> Response {
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || response.extensions().get::<ErrorCode>().is_some() {
+        return response;
+    }
+    payload_too_large_error().into_response()
+}
+
+/// Correlation ID header, echoed on every response
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Tag each request with a fresh UUID: every log event inside the handler carries it as
+/// `request_id`, and it is returned in `X-Request-Id` so clients can quote it
+async fn request_id(request: Request, next: Next) -> Response {
+    let id = uuid::Uuid::new_v4().to_string();
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        uri = %request.uri(),
+    );
+
+    async move {
+        tracing::debug!("request started");
+        let mut response = next.run(request).await;
+        tracing::info!(status = response.status().as_u16(), "request finished");
+        if let Ok(value) = header::HeaderValue::from_str(&id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+/// Require `Authorization: Bearer <token>` on anything but GET/HEAD/OPTIONS when a token is configured
+async fn require_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(token) = state.api_token.as_deref() else {
+        return Ok(next.run(request).await);
+    };
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(next.run(request).await);
+    }
+
+    let provided = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(token) {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "UNAUTHORIZED", "Missing or invalid API token"));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Count successful mutating requests and hardware failures for `GET /api/stats`
+async fn count_operations(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let mutating = !matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let response = next.run(request).await;
+    if mutating && response.status().is_success() {
+        state.stats.operations.fetch_add(1, Ordering::Relaxed);
+    }
+    if response.extensions().get::<ErrorCode>().is_some_and(|code| HARDWARE_ERROR_CODES.contains(&code.0)) {
+        state.stats.gpio_errors.fetch_add(1, Ordering::Relaxed);
+    }
+    response
+}
+
+#[utoipa::path(
+    get, path = "/", tag = "system",
+    responses((status = 200, description = "API banner", body = StatusResponse))
+)]
 async fn root() -> Json<StatusResponse> {
     Json(StatusResponse {
         status: "ok".to_string(),
@@ -52,85 +839,1461 @@ async fn root() -> Json<StatusResponse> {
     })
 }
 
+#[utoipa::path(
+    get, path = "/api/health", tag = "system",
+    responses((status = 200, description = "Server is up", body = StatusResponse))
+)]
+async fn health() -> Json<StatusResponse> {
+    Json(StatusResponse {
+        status: "ok".to_string(),
+        message: "healthy".to_string(),
+    })
+}
+
+async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[utoipa::path(
+    get, path = "/api/info", tag = "system",
+    responses((status = 200, description = "Controller status and any failed LEDs", body = InfoResponse))
+)]
+async fn get_info(State(state): State<AppState>) -> Json<InfoResponse> {
+    let failed_leds: Vec<FailedLed> = state.leds.failed_leds()
+        .into_iter()
+        .map(|(led, error)| FailedLed { led, error })
+        .collect();
+    Json(InfoResponse {
+        status: if failed_leds.is_empty() { "ok" } else { "degraded" }.to_string(),
+        led_count: state.leds.count(),
+        failed_leds,
+    })
+}
+
+#[utoipa::path(
+    get, path = "/api/diagnostics", tag = "system",
+    responses((status = 200, description = "Kernel view of each LED's GPIO line; empty when the LEDs aren't on GPIO", body = [LineDiagnostics]))
+)]
+async fn get_diagnostics(State(state): State<AppState>) -> Json<Vec<LineDiagnostics>> {
+    Json(state.leds.diagnostics())
+}
+
+#[utoipa::path(
+    get, path = "/api/stats", tag = "system",
+    responses((status = 200, description = "Uptime and operation counters", body = StatsResponse))
+)]
+async fn get_stats(State(state): State<AppState>) -> Json<StatsResponse> {
+    Json(StatsResponse {
+        uptime_secs: state.stats.started.elapsed().as_secs(),
+        operations: state.stats.operations.load(Ordering::Relaxed),
+        active_effects: state.leds.active_effects().await,
+        gpio_errors: state.stats.gpio_errors.load(Ordering::Relaxed),
+    })
+}
+
+#[utoipa::path(
+    get, path = "/api/mapping", tag = "system",
+    responses((status = 200, description = "Physical wiring of every LED", body = [LedMapping]))
+)]
+async fn get_mapping(State(state): State<AppState>) -> Json<Vec<LedMapping>> {
+    Json(state.leds.mapping())
+}
+
+#[utoipa::path(
+    get, path = "/api/self-test", tag = "system",
+    responses(
+        (status = 200, description = "Startup self-test report", body = SelfTestReport),
+        (status = 404, description = "Server started without --self-test", body = ErrorResponse),
+    )
+)]
+async fn get_self_test(State(state): State<AppState>) -> Result<Json<SelfTestReport>, ApiError> {
+    state.self_test.map(Json).ok_or_else(|| ApiError::new(
+        StatusCode::NOT_FOUND,
+        "SELF_TEST_NOT_RUN",
+        "The server was started without --self-test",
+    ))
+}
+
 // LED endpoints
-async fn get_all_leds(State(state): State<AppState>) -> Result<Json<Vec<LedResponse>>, StatusCode> {
+#[utoipa::path(
+    get, path = "/api/leds", tag = "leds",
+    params(("color" = Option<LedColor>, Query, description = "Only return LEDs of this colour")),
+    responses(
+        (status = 200, description = "State of every LED, or of one colour's LEDs", body = [LedResponse]),
+        (status = 400, description = "Unknown colour", body = ErrorResponse),
+    )
+)]
+async fn get_all_leds(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<LedResponse>>, ApiError> {
+    let color = query.get("color")
+        .map(|name| name.parse::<LedColor>())
+        .transpose()
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, "INVALID_COLOR", e.to_string()))?;
     let mut leds = Vec::new();
-    for led_num in 1..=24 {
-        leds.push(LedResponse {
-            led: led_num,
-            state: "unknown".to_string(), // We don't track state
-        });
+    for (led, led_state) in state.leds.get_all_states().await {
+        if color.is_none_or(|color| color.range().contains(&led.get())) {
+            leds.push(LedResponse::of(&state.leds, led, led_state).await);
+        }
     }
     Ok(Json(leds))
 }
 
-async fn get_led(
-    State(_state): State<AppState>,
-    Path(led): Path<u8>,
-) -> Result<Json<LedResponse>, StatusCode> {
-    if led < 1 || led > 24 {
-        return Err(StatusCode::NOT_FOUND);
+/// Entries returned by `GET /api/leds/log` when `last` isn't given
+const DEFAULT_LOG_ENTRIES: usize = 100;
+
+#[utoipa::path(
+    get, path = "/api/leds/log", tag = "leds",
+    params(("last" = Option<usize>, Query, description = "How many of the most recent commands to return (default 100)")),
+    responses(
+        (status = 200, description = "Recent LED commands, oldest first", body = [AuditEntry]),
+        (status = 422, description = "`last` isn't a number", body = ErrorResponse),
+    )
+)]
+async fn get_led_log(
+    State(state): State<AppState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<AuditEntry>>, ApiError> {
+    let last = query.get("last")
+        .map(|last| last.parse::<usize>())
+        .transpose()
+        .map_err(|_| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", "last must be a non-negative number"))?
+        .unwrap_or(DEFAULT_LOG_ENTRIES);
+    Ok(Json(state.audit.last(last)))
+}
+
+#[utoipa::path(
+    get, path = "/api/events", tag = "leds",
+    responses((status = 200, description = "Server-sent events: a `snapshot` of every LED, then a `change` for each LED event after it", content_type = "text/event-stream"))
+)]
+async fn get_events(State(state): State<AppState>) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    // The snapshot and the subscription are taken together, so the first change follows on from the
+    // snapshot with nothing missed or repeated; see `LedController::subscribe_with_snapshot()`
+    let (snapshot, receiver) = state.leds.subscribe_with_snapshot().await;
+    let mut leds = Vec::with_capacity(snapshot.len());
+    for (led, led_state) in snapshot {
+        leds.push(LedResponse::of(&state.leds, led, led_state).await);
     }
-    Ok(Json(LedResponse {
-        led,
-        state: "unknown".to_string(), // We don't track state
+    let first = Event::default().event("snapshot").json_data(leds);
+
+    let changes = futures::stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => Event::default().event("change").json_data(event),
+            // The client has missed changes, so its view is stale; it should reconnect for a new snapshot
+            Err(broadcast::error::RecvError::Lagged(missed)) => Ok(Event::default().event("lagged").data(missed.to_string())),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((event, receiver))
+    });
+    // End the stream on shutdown, or graceful shutdown would wait on it forever
+    let stream = futures::stream::once(async { first })
+        .chain(changes)
+        .take_until(state.leds.shutdown_token().cancelled_owned());
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[utoipa::path(
+    patch, path = "/api/leds", tag = "leds",
+    request_body(content = HashMap<String, LedState>, description = "Desired state of each LED to change, keyed by LED number"),
+    responses(
+        (status = 200, description = "Listed LEDs changed, the rest left as they were", body = StatusResponse),
+        (status = 422, description = "Invalid LED or state; nothing was changed", body = ErrorResponse),
+    )
+)]
+async fn patch_leds(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(patch): ApiJson<BTreeMap<u8, LedState>>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let changes = patch.into_iter()
+        .map(|(led, led_state)| Ok((Led::new(led)?, led_state)))
+        .collect::<Result<Vec<_>, TrainError>>()
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", e.to_string()))?;
+    check_leases(&state, changes.iter().map(|&(led, _)| led), &headers).await?;
+    state.leds.apply(&changes).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("{} LEDs updated", changes.len()),
     }))
 }
 
+#[utoipa::path(
+    get, path = "/api/leds/active", tag = "leds",
+    responses((status = 200, description = "LEDs that are on or running an effect", body = ActiveLedsResponse))
+)]
+async fn get_active_leds(State(state): State<AppState>) -> Json<ActiveLedsResponse> {
+    let mut leds = Vec::new();
+    for (led, led_state) in state.leds.get_all_states().await {
+        if led_state != LedState::Off {
+            leds.push(LedResponse::of(&state.leds, led, led_state).await);
+        }
+    }
+    Json(ActiveLedsResponse { count: leds.len(), leds })
+}
+
+#[utoipa::path(
+    get, path = "/api/leds/{led}", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    responses(
+        (status = 200, description = "State of the LED", body = LedResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+    )
+)]
+async fn get_led(
+    State(state): State<AppState>,
+    LedIdent(led): LedIdent,
+) -> Result<Json<LedResponse>, ApiError> {
+    let led_state = state.leds.state(led).await;
+    Ok(Json(LedResponse::of(&state.leds, led, led_state).await))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{led}/on", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    request_body(content = OnRequest, description = "Optional; omit to leave the LED on indefinitely"),
+    responses(
+        (status = 200, description = "LED turned on", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 422, description = "TTL is zero", body = ErrorResponse),
+        (status = 409, description = "LED released", body = ErrorResponse),
+        (status = 503, description = "LED unavailable", body = ErrorResponse),
+    )
+)]
 async fn set_led_on(
     State(state): State<AppState>,
-    Path(led): Path<u8>,
-) -> Result<Json<StatusResponse>, StatusCode> {
-    if led < 1 || led > 24 {
-        return Err(StatusCode::NOT_FOUND);
-    }
-    state.leds.on(led).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    LeasedLed(led): LeasedLed,
+    request: Result<Json<OnRequest>, JsonRejection>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let ttl_ms = match request {
+        Ok(Json(request)) => request.ttl_ms,
+        // No JSON body: a plain on
+        Err(JsonRejection::MissingJsonContentType(_)) => None,
+        Err(rejection) => return Err(rejection.into()),
+    };
+    let message = match ttl_ms {
+        Some(ttl_ms) => {
+            state.leds.on_for(led, ttl_ms).await?;
+            format!("LED {} turned on for {}ms", led, ttl_ms)
+        }
+        None => {
+            state.leds.on(led).await?;
+            format!("LED {} turned on", led)
+        }
+    };
     Ok(Json(StatusResponse {
         status: "ok".to_string(),
-        message: format!("LED {} turned on", led),
+        message,
     }))
 }
 
+#[utoipa::path(
+    post, path = "/api/leds/{led}/off", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    responses(
+        (status = 200, description = "LED turned off", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 409, description = "LED released", body = ErrorResponse),
+        (status = 503, description = "LED unavailable", body = ErrorResponse),
+    )
+)]
 async fn set_led_off(
     State(state): State<AppState>,
-    Path(led): Path<u8>,
-) -> Result<Json<StatusResponse>, StatusCode> {
-    if led < 1 || led > 24 {
-        return Err(StatusCode::NOT_FOUND);
-    }
-    state.leds.off(led).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    LeasedLed(led): LeasedLed,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.off(led).await?;
     Ok(Json(StatusResponse {
         status: "ok".to_string(),
         message: format!("LED {} turned off", led),
     }))
 }
 
+#[utoipa::path(
+    post, path = "/api/leds/{led}/blink", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    request_body = BlinkRequest,
+    responses(
+        (status = 200, description = "LED blinking", body = StatusResponse),
+        (status = 400, description = "Zero frequency or bad hz", body = ErrorResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 422, description = "Frequency outside the allowed range, or not exactly one of frequency_ms and hz", body = ErrorResponse),
+    )
+)]
 async fn set_led_blink(
     State(state): State<AppState>,
-    Path(led): Path<u8>,
-    Json(request): Json<BlinkRequest>,
-) -> Result<Json<StatusResponse>, StatusCode> {
-    if led < 1 || led > 24 {
-        return Err(StatusCode::NOT_FOUND);
-    }
-    if request.frequency_ms == 0 {
-        return Err(StatusCode::BAD_REQUEST);
+    LeasedLed(led): LeasedLed,
+    ApiJson(request): ApiJson<BlinkRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let frequency_ms = request.interval_ms()?;
+    let message = match (request.cycles, request.phase_ms) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", "phase_ms can't be combined with cycles"));
+        }
+        (Some(cycles), None) => {
+            state.leds.blink_count(led, frequency_ms, cycles).await?;
+            format!("LED {} blinking at {}ms interval for {} cycles", led, frequency_ms, cycles)
+        }
+        (None, Some(phase_ms)) => {
+            state.leds.blink_with_phase(led, frequency_ms, phase_ms).await?;
+            format!("LED {} blinking at {}ms interval, {}ms phase", led, frequency_ms, phase_ms)
+        }
+        (None, None) => {
+            state.leds.blink(led, frequency_ms).await?;
+            format!("LED {} blinking at {}ms interval", led, frequency_ms)
+        }
+    };
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message,
+    }))
+}
+
+#[utoipa::path(
+    put, path = "/api/leds/{led}/brightness", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    request_body = BrightnessRequest,
+    responses(
+        (status = 200, description = "Brightness set", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 422, description = "Level outside 0-255", body = ErrorResponse),
+    )
+)]
+async fn set_led_brightness(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+    ApiJson(request): ApiJson<BrightnessRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.set_brightness(led, request.level).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("LED {} brightness set to {}", led, request.level),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{led}/pulse", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    request_body = PulseRequest,
+    responses(
+        (status = 200, description = "Pulse started", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 422, description = "Rise and fall both 0", body = ErrorResponse),
+    )
+)]
+async fn pulse_led(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+    ApiJson(request): ApiJson<PulseRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.pulse(led, request.rise_ms, request.fall_ms).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("LED {} pulsing ({}ms up, {}ms down)", led, request.rise_ms, request.fall_ms),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{led}/fade", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    request_body = FadeRequest,
+    responses(
+        (status = 200, description = "Fade started", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+    )
+)]
+async fn fade_led(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+    ApiJson(request): ApiJson<FadeRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let direction = match request.direction {
+        FadeDirection::In => {
+            state.leds.fade_in(led, request.duration_ms).await?;
+            "in"
+        }
+        FadeDirection::Out => {
+            state.leds.fade_out(led, request.duration_ms).await?;
+            "out"
+        }
+    };
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("LED {} fading {} over {}ms", led, direction, request.duration_ms),
+    }))
+}
+
+#[utoipa::path(
+    get, path = "/api/leds/names", tag = "leds",
+    responses((status = 200, description = "LED aliases (name -> LED number)", body = BTreeMap<String, u8>))
+)]
+async fn get_led_names(State(state): State<AppState>) -> Json<BTreeMap<String, Led>> {
+    Json(state.leds.aliases().await)
+}
+
+#[utoipa::path(
+    put, path = "/api/leds/{led}/name", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    request_body = NameRequest,
+    responses(
+        (status = 200, description = "Alias set", body = StatusResponse),
+        (status = 400, description = "Reserved name", body = ErrorResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 422, description = "Invalid name", body = ErrorResponse),
+    )
+)]
+async fn set_led_name(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+    ApiJson(request): ApiJson<NameRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    // Names that collide with fixed routes under /api/leds could never be addressed; colour
+    // names would be taken by the colour routes, switching the whole bank instead
+    let fixed = ["all", "names", "snapshot", "restore", "log", "active"].contains(&request.name.as_str());
+    if fixed || LedColor::ALL.iter().any(|color| color.to_string() == request.name) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_NAME",
+            format!("'{}' is reserved and can't be used as an LED name", request.name),
+        ));
     }
-    state.leds.blink(led, request.frequency_ms).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.leds.set_alias(led, request.name.clone()).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("LED {} named '{}'", led, request.name),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{led}/release", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    responses(
+        (status = 200, description = "GPIO line released", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+    )
+)]
+async fn release_led(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.release(led).await?;
     Ok(Json(StatusResponse {
         status: "ok".to_string(),
-        message: format!("LED {} blinking at {}ms interval", led, request.frequency_ms),
+        message: format!("LED {} GPIO line released", led),
     }))
 }
 
-async fn set_all_leds_off(State(state): State<AppState>) -> Result<Json<StatusResponse>, StatusCode> {
-    state.leds.all_off().await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+#[utoipa::path(
+    post, path = "/api/leds/{led}/acquire", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    responses(
+        (status = 200, description = "GPIO line acquired", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 409, description = "GPIO line held by another process", body = ErrorResponse),
+    )
+)]
+async fn acquire_led(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.acquire(led).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("LED {} GPIO line acquired", led),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{led}/lock", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    responses(
+        (status = 200, description = "Lease granted or renewed", body = LeaseResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 423, description = "Another client holds the lease", body = ErrorResponse),
+    )
+)]
+async fn lock_led(
+    State(state): State<AppState>,
+    LedIdent(led): LedIdent,
+    headers: HeaderMap,
+) -> Result<Json<LeaseResponse>, ApiError> {
+    let provided = headers.get(LEASE_HEADER).and_then(|value| value.to_str().ok());
+    let mut leases = state.leases.write().await;
+    let now = Instant::now();
+    // Locking again with the current token renews the lease rather than issuing a new one
+    let token = match leases.get(&led) {
+        Some(lease) if lease.expires > now => {
+            if provided != Some(lease.token.as_str()) {
+                return Err(locked(led));
+            }
+            lease.token.clone()
+        }
+        _ => uuid::Uuid::new_v4().to_string(),
+    };
+    leases.insert(led, Lease { token: token.clone(), expires: now + LEASE_TTL });
+    Ok(Json(LeaseResponse {
+        status: "ok".to_string(),
+        led,
+        token,
+        expires_in_secs: LEASE_TTL.as_secs(),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{led}/unlock", tag = "leds",
+    params(("led" = String, Path, description = "LED number (1-24) or alias")),
+    responses(
+        (status = 200, description = "Lease released", body = StatusResponse),
+        (status = 404, description = "No such LED", body = ErrorResponse),
+        (status = 423, description = "Another client holds the lease", body = ErrorResponse),
+    )
+)]
+async fn unlock_led(
+    State(state): State<AppState>,
+    LeasedLed(led): LeasedLed,
+) -> Result<Json<StatusResponse>, ApiError> {
+    state.leases.write().await.remove(&led);
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("LED {} unlocked", led),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/all/off", tag = "leds",
+    responses((status = 200, description = "All LEDs off", body = StatusResponse))
+)]
+async fn set_all_leds_off(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, Led::all(), &headers).await?;
+    state.leds.all_off().await?;
     Ok(Json(StatusResponse {
         status: "ok".to_string(),
         message: "All LEDs turned off and blinking cancelled".to_string(),
     }))
 }
+
+/// One shared blink for the whole panel; stopped by `POST /api/leds/all/off`
+#[utoipa::path(
+    post, path = "/api/leds/all/blink", tag = "leds",
+    request_body = BlinkRequest,
+    responses(
+        (status = 200, description = "Every LED blinking in unison", body = StatusResponse),
+        (status = 400, description = "Invalid frequency", body = ErrorResponse),
+        (status = 422, description = "cycles or phase_ms given", body = ErrorResponse),
+    )
+)]
+async fn set_all_leds_blink(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<BlinkRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let frequency_ms = request.interval_ms()?;
+    if request.cycles.is_some() || request.phase_ms.is_some() {
+        return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", "cycles and phase_ms aren't supported for the whole panel"));
+    }
+    check_leases(&state, Led::all(), &headers).await?;
+    state.leds.all_blink(frequency_ms).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("All LEDs blinking at {}ms interval", frequency_ms),
+    }))
+}
+
+/// Emergency stop: ignores leases so the UI's stop button always works
+#[utoipa::path(
+    post, path = "/api/stop-all", tag = "effects",
+    responses((status = 200, description = "Every operation and effect stopped, all LEDs off", body = StatusResponse))
+)]
+async fn stop_all(State(state): State<AppState>) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.stop_all().await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: "All operations and effects stopped, all LEDs off".to_string(),
+    }))
+}
+
+/// All-red safety gesture; like stop-all it ignores leases. `POST /api/leds/all/off` ends it
+#[utoipa::path(
+    post, path = "/api/panic", tag = "effects",
+    responses((status = 200, description = "Greens and ambers off, every red LED blinking", body = StatusResponse))
+)]
+async fn panic(State(state): State<AppState>) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.panic_mode().await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: "Panic mode: all red LEDs blinking until all LEDs are turned off".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{color}/on", tag = "leds",
+    params(("color" = LedColor, Path, description = "green, amber or red")),
+    responses((status = 200, description = "Every LED of the colour on", body = StatusResponse))
+)]
+async fn set_color_on(State(state): State<AppState>, headers: HeaderMap, color: LedColor) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, state.leds.color(color.range()).leds()?, &headers).await?;
+    state.leds.color_group_on(color).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("All {} LEDs turned on", color),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{color}/off", tag = "leds",
+    params(("color" = LedColor, Path, description = "green, amber or red")),
+    responses((status = 200, description = "Every LED of the colour off", body = StatusResponse))
+)]
+async fn set_color_off(State(state): State<AppState>, headers: HeaderMap, color: LedColor) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, state.leds.color(color.range()).leds()?, &headers).await?;
+    state.leds.color_group_off(color).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("All {} LEDs turned off", color),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/{color}/blink", tag = "leds",
+    params(("color" = LedColor, Path, description = "green, amber or red")),
+    request_body = BlinkRequest,
+    responses(
+        (status = 200, description = "Every LED of the colour blinking", body = StatusResponse),
+        (status = 400, description = "Zero frequency or bad hz", body = ErrorResponse),
+        (status = 422, description = "Frequency outside the allowed range, or cycles/phase given", body = ErrorResponse),
+    )
+)]
+async fn set_color_blink(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<BlinkRequest>,
+    color: LedColor,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let frequency_ms = request.interval_ms()?;
+    if request.cycles.is_some() || request.phase_ms.is_some() {
+        return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", "cycles and phase_ms aren't supported for colour groups"));
+    }
+    check_leases(&state, state.leds.color(color.range()).leds()?, &headers).await?;
+    state.leds.color_group_blink(color, frequency_ms).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("All {} LEDs blinking at {}ms interval", color, frequency_ms),
+    }))
+}
+
+/// Members of a configured zone, or 404
+fn zone_members(state: &AppState, name: &str) -> Result<Vec<Led>, ApiError> {
+    state.leds.zone(name)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "ZONE_NOT_FOUND", format!("No zone is named '{}'", name)))
+}
+
+#[utoipa::path(
+    post, path = "/api/zone/{name}/on", tag = "leds",
+    params(("name" = String, Path, description = "Zone name from the config file")),
+    responses(
+        (status = 200, description = "Every LED in the zone on", body = StatusResponse),
+        (status = 404, description = "No such zone", body = ErrorResponse),
+    )
+)]
+async fn set_zone_on(State(state): State<AppState>, headers: HeaderMap, ApiPath(name): ApiPath<String>) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, zone_members(&state, &name)?, &headers).await?;
+    state.leds.zone_on(&name).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("Zone {} turned on", name),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/zone/{name}/off", tag = "leds",
+    params(("name" = String, Path, description = "Zone name from the config file")),
+    responses(
+        (status = 200, description = "Every LED in the zone off", body = StatusResponse),
+        (status = 404, description = "No such zone", body = ErrorResponse),
+    )
+)]
+async fn set_zone_off(State(state): State<AppState>, headers: HeaderMap, ApiPath(name): ApiPath<String>) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, zone_members(&state, &name)?, &headers).await?;
+    state.leds.zone_off(&name).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("Zone {} turned off", name),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/zone/{name}/blink", tag = "leds",
+    params(("name" = String, Path, description = "Zone name from the config file")),
+    request_body = BlinkRequest,
+    responses(
+        (status = 200, description = "Every LED in the zone blinking in phase", body = StatusResponse),
+        (status = 400, description = "Zero frequency or bad hz", body = ErrorResponse),
+        (status = 404, description = "No such zone", body = ErrorResponse),
+        (status = 422, description = "Frequency outside the allowed range, or cycles/phase given", body = ErrorResponse),
+    )
+)]
+async fn set_zone_blink(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiPath(name): ApiPath<String>,
+    ApiJson(request): ApiJson<BlinkRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let frequency_ms = request.interval_ms()?;
+    if request.cycles.is_some() || request.phase_ms.is_some() {
+        return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", "cycles and phase_ms aren't supported for zones"));
+    }
+    check_leases(&state, zone_members(&state, &name)?, &headers).await?;
+    state.leds.zone_blink(&name, frequency_ms).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("Zone {} blinking at {}ms interval", name, frequency_ms),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/snapshot", tag = "leds",
+    responses((status = 200, description = "Current state of every LED", body = LedSnapshot))
+)]
+async fn take_snapshot(State(state): State<AppState>) -> Json<LedSnapshot> {
+    Json(state.leds.snapshot().await)
+}
+
+#[utoipa::path(
+    post, path = "/api/leds/restore", tag = "leds",
+    request_body = LedSnapshot,
+    responses(
+        (status = 200, description = "Snapshot re-applied", body = StatusResponse),
+        (status = 422, description = "Invalid snapshot", body = ErrorResponse),
+    )
+)]
+async fn restore_snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(snapshot): ApiJson<LedSnapshot>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, snapshot.leds.iter().map(|entry| entry.led), &headers).await?;
+    state.leds.restore(&snapshot).await
+        .map_err(|e| match e {
+            // An out-of-range LED in the body is a bad request, not a missing resource
+            TrainError::LedOutOfRange { .. } => ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "INVALID_PARAMETER", e.to_string()),
+            e => ApiError::from(e),
+        })?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: "LED state restored from snapshot".to_string(),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/traffic", tag = "effects",
+    request_body = TrafficRequest,
+    responses(
+        (status = 200, description = "Traffic light running", body = StatusResponse),
+        (status = 422, description = "LEDs not distinct or zero timing", body = ErrorResponse),
+    )
+)]
+async fn start_traffic(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<TrafficRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    check_leases(&state, [request.green, request.amber, request.red], &headers).await?;
+    state.leds.traffic_cycle(
+        request.green,
+        request.amber,
+        request.red,
+        (request.green_ms, request.amber_ms, request.red_ms),
+    ).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!(
+            "Traffic light running on LEDs {} (green), {} (amber), {} (red)",
+            request.green, request.amber, request.red
+        ),
+    }))
+}
+
+#[utoipa::path(
+    post, path = "/api/simulate/traffic-light", tag = "effects",
+    request_body = TrafficLightRequest,
+    responses(
+        (status = 200, description = "Signal cycling red, red + amber, green, amber", body = StatusResponse),
+        (status = 422, description = "Position out of range or zero cycle length", body = ErrorResponse),
+    )
+)]
+async fn simulate_traffic_light(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiJson(request): ApiJson<TrafficLightRequest>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let lamp = |subset, position| state.leds.color(subset).position(position)?.get();
+    let lamps = [
+        lamp(GREEN_LEDS, request.green_pos)?,
+        lamp(AMBER_LEDS, request.amber_pos)?,
+        lamp(RED_LEDS, request.red_pos)?,
+    ];
+    check_leases(&state, lamps, &headers).await?;
+    state.leds.traffic_light(request.green_pos, request.amber_pos, request.red_pos, request.cycle_secs).await?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!(
+            "Signal running on green {}, amber {}, red {} with a {}s cycle",
+            request.green_pos, request.amber_pos, request.red_pos, request.cycle_secs
+        ),
+    }))
+}
+
+#[utoipa::path(
+    get, path = "/api/schedule", tag = "schedule",
+    responses((status = 200, description = "Pending commands, soonest first", body = [ScheduledCommand]))
+)]
+async fn list_schedules(State(state): State<AppState>) -> Json<Vec<ScheduledCommand>> {
+    Json(state.leds.list_schedules().await)
+}
+
+/// Run a command at a wall-clock time, optionally repeating
+///
+/// Scheduled commands don't check leases when they run: the request that schedules one needs no
+/// lease token, and the command later changes its LEDs even if another client has locked them.
+#[utoipa::path(
+    post, path = "/api/schedule", tag = "schedule",
+    request_body = ScheduleRequest,
+    responses(
+        (status = 200, description = "Command scheduled", body = ScheduledCommand),
+        (status = 422, description = "Zero interval", body = ErrorResponse),
+    )
+)]
+async fn add_schedule(
+    State(state): State<AppState>,
+    ApiJson(request): ApiJson<ScheduleRequest>,
+) -> Result<Json<ScheduledCommand>, ApiError> {
+    let id = match request.every_secs {
+        Some(secs) => state.leds.schedule_every(request.at, Duration::from_secs(secs), request.action.clone()).await?,
+        None => state.leds.schedule(request.at, request.action.clone()).await,
+    };
+    // A past time may already have run, and no longer be pending
+    let scheduled = state.leds.list_schedules().await.into_iter().find(|scheduled| scheduled.id == id);
+    Ok(Json(scheduled.unwrap_or(ScheduledCommand {
+        id,
+        at: request.at,
+        command: request.action,
+        every_secs: request.every_secs,
+    })))
+}
+
+#[utoipa::path(
+    delete, path = "/api/schedule/{id}", tag = "schedule",
+    params(("id" = u64, Path, description = "ID returned when the command was scheduled")),
+    responses(
+        (status = 200, description = "Command cancelled", body = StatusResponse),
+        (status = 404, description = "No such pending command", body = ErrorResponse),
+    )
+)]
+async fn cancel_schedule(State(state): State<AppState>, ApiPath(id): ApiPath<ScheduleId>) -> Result<Json<StatusResponse>, ApiError> {
+    state.leds.cancel_schedule(id).await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, "SCHEDULE_NOT_FOUND", e.to_string()))?;
+    Ok(Json(StatusResponse {
+        status: "ok".to_string(),
+        message: format!("Scheduled command {} cancelled", id),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockBackend;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn test_state(rate_limit_rps: Option<u32>) -> AppState {
+        AppState {
+            leds: Arc::new(LedController::with_backend(Arc::new(MockBackend::new()))),
+            api_token: None,
+            self_test: None,
+            rate_limit_rps,
+            compression: false,
+            leases: Default::default(),
+            stats: Default::default(),
+            body_limit: DEFAULT_BODY_LIMIT,
+            audit: Default::default(),
+        }
+    }
+
+    /// Request from a fixed client address, as `into_make_service_with_connect_info` would provide
+    fn request(method: Method, uri: &str) -> Request {
+        let mut request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 4000))));
+        request
+    }
+
+    /// `request()` with a JSON body
+    fn json_request(method: Method, uri: &str, body: &'static str) -> Request {
+        let mut request = request(method, uri);
+        *request.body_mut() = Body::from(body);
+        request.headers_mut().insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn led_routes_map_controller_results_to_status_codes() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/3/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(3).unwrap()).await, LedState::On);
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/3/off")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(3).unwrap()).await, LedState::Off);
+
+        for uri in ["/api/leds/0/on", "/api/leds/25/on", "/api/leds/25/off"] {
+            let response = app.clone().oneshot(request(Method::POST, uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+
+        let blink = json_request(Method::POST, "/api/leds/3/blink", r#"{"frequency_ms": 0}"#);
+        assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.leds.state(Led::new(3).unwrap()).await, LedState::Off);
+
+        let blink = json_request(Method::POST, "/api/leds/3/blink", r#"{"hz": 2}"#);
+        assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(3).unwrap()).await, LedState::Blinking { frequency_ms: 250 });
+        for body in [r#"{"hz": 2, "frequency_ms": 250}"#, "{}"] {
+            let blink = json_request(Method::POST, "/api/leds/3/blink", body);
+            assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+        }
+
+        state.leds.blink(Led::new(4).unwrap(), 200).await.unwrap();
+        let response = app.oneshot(request(Method::POST, "/api/leds/all/off")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(state.leds.get_all_states().await.iter().all(|(_, led_state)| *led_state == LedState::Off));
+    }
+
+    #[tokio::test]
+    async fn zone_routes_switch_the_configured_leds() {
+        let mut config = crate::LedConfig::default();
+        config.zones.insert("platform1".to_string(), vec![1, 2, 7]);
+        let state = AppState {
+            leds: Arc::new(LedController::with_backend_and_config(Arc::new(MockBackend::new()), config)),
+            ..test_state(None)
+        };
+        let app = create_router(state.clone());
+        let lit = |leds: Vec<(Led, LedState)>| -> Vec<u8> {
+            leds.into_iter().filter(|(_, led_state)| *led_state != LedState::Off).map(|(led, _)| led.get()).collect()
+        };
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/zone/platform1/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(lit(state.leds.get_all_states().await), vec![1, 2, 7]);
+
+        let blink = json_request(Method::POST, "/api/zone/platform1/blink", r#"{"frequency_ms": 300}"#);
+        assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(7).unwrap()).await, LedState::Blinking { frequency_ms: 300 });
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/zone/platform1/off")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(lit(state.leds.get_all_states().await), Vec::<u8>::new());
+
+        let response = app.oneshot(request(Method::POST, "/api/zone/platform9/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_get_a_json_413() {
+        let app = create_router(AppState { body_limit: 1024, ..test_state(None) });
+        let padding = "x".repeat(2048);
+        let body = format!(r#"{{"frequency_ms": 500, "padding": "{}"}}"#, padding);
+
+        // Refused up front from Content-Length, and while reading a body sent without one
+        for content_length in [Some(body.len()), None] {
+            let mut request = Request::builder().method(Method::POST).uri("/api/leds/3/blink")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(length) = content_length {
+                request = request.header(header::CONTENT_LENGTH, length);
+            }
+            let response = app.clone().oneshot(request.body(Body::from(body.clone())).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "Content-Length {:?}", content_length);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!((error["status"].as_str(), error["message"].as_str()), (Some("error"), Some("Request body too large")));
+        }
+
+        let response = app.oneshot(json_request(Method::POST, "/api/leds/3/blink", r#"{"frequency_ms": 500}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn names_of_fixed_routes_are_reserved() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+        for body in [r#"{"name":"all"}"#, r#"{"name":"active"}"#, r#"{"name":"log"}"#, r#"{"name":"red"}"#, r#"{"name":"green"}"#] {
+            let response = app.clone().oneshot(json_request(Method::PUT, "/api/leds/3/name", body)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+        }
+        let response = app.clone().oneshot(json_request(Method::PUT, "/api/leds/3/name", r#"{"name":"signal"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // /api/leds/active still lists the active LEDs rather than resolving an alias
+        state.leds.on(Led::new(9).unwrap()).await.unwrap();
+        let response = app.oneshot(request(Method::GET, "/api/leds/active")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let active: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(active["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn log_returns_the_last_commands_oldest_first() {
+        let base = test_state(None);
+        let state = AppState { audit: AuditLog::record(&base.leds), ..base };
+        let app = create_router(state.clone());
+        let log = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request(Method::GET, uri)).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let led = |n| Led::new(n).unwrap();
+        state.leds.on(led(1)).await.unwrap();
+        state.leds.blink(led(2), 400).await.unwrap();
+        state.leds.off(led(2)).await.unwrap();
+        state.leds.off(led(1)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let entries = log("/api/leds/log").await;
+        let operations: Vec<(u64, &str)> = entries.as_array().unwrap().iter()
+            .map(|entry| (entry["led"].as_u64().unwrap(), entry["operation"].as_str().unwrap()))
+            .collect();
+        assert_eq!(operations, [(1, "on"), (2, "blink"), (2, "cancel"), (1, "off")]);
+        assert_eq!(entries[1]["frequency_ms"], 400);
+        assert!(entries[0]["timestamp"].is_string());
+
+        let entries = log("/api/leds/log?last=1").await;
+        assert_eq!((entries.as_array().unwrap().len(), entries[0]["operation"].as_str()), (1, Some("off")));
+        let response = app.oneshot(request(Method::GET, "/api/leds/log?last=many")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn events_start_with_a_snapshot_then_stream_changes() {
+        let state = test_state(None);
+        state.leds.on(Led::new(2).unwrap()).await.unwrap();
+        let app = create_router(state.clone());
+        let response = app.oneshot(request(Method::GET, "/api/events")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+        let mut next_event = async || {
+            let chunk = tokio::time::timeout(Duration::from_secs(1), body.next()).await.unwrap().unwrap().unwrap();
+            String::from_utf8(chunk.to_vec()).unwrap()
+        };
+
+        let snapshot = next_event().await;
+        let (name, data) = snapshot.trim_end().split_once('\n').unwrap();
+        assert_eq!(name, "event: snapshot");
+        let leds: serde_json::Value = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(leds.as_array().unwrap().len(), 24);
+        assert_eq!(leds[1]["state"], "on");
+
+        state.leds.off(Led::new(2).unwrap()).await.unwrap();
+        let change = next_event().await;
+        let (name, data) = change.trim_end().split_once('\n').unwrap();
+        assert_eq!(name, "event: change");
+        let event: serde_json::Value = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!((event["led"].as_u64(), event["new_state"]["state"].as_str()), (Some(2), Some("off")));
+
+        state.leds.shutdown_token().cancel();
+        assert!(tokio::time::timeout(Duration::from_secs(1), body.next()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn patch_changes_only_the_listed_leds() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+        state.leds.on(Led::new(1).unwrap()).await.unwrap();
+
+        let patch = json_request(Method::PATCH, "/api/leds", r#"{"2": {"state": "on"}, "3": {"state": "blinking", "frequency_ms": 400}}"#);
+        assert_eq!(app.clone().oneshot(patch).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(1).unwrap()).await, LedState::On);
+        assert_eq!(state.leds.state(Led::new(2).unwrap()).await, LedState::On);
+        assert_eq!(state.leds.state(Led::new(3).unwrap()).await, LedState::Blinking { frequency_ms: 400 });
+
+        for body in [r#"{"4": {"state": "on"}, "25": {"state": "on"}}"#, r#"{"4": {"state": "on"}, "5": {"state": "blinking", "frequency_ms": 0}}"#] {
+            let response = app.clone().oneshot(json_request(Method::PATCH, "/api/leds", body)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+            assert_eq!(state.leds.state(Led::new(4).unwrap()).await, LedState::Off, "nothing applied");
+        }
+        state.leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stats_count_operations_effects_and_hardware_errors() {
+        let backend = Arc::new(MockBackend::new());
+        let state = AppState { leds: Arc::new(LedController::with_backend(backend.clone())), ..test_state(None) };
+        let app = create_router(state.clone());
+
+        app.clone().oneshot(request(Method::POST, "/api/leds/1/on")).await.unwrap();
+        app.clone().oneshot(json_request(Method::POST, "/api/leds/2/blink", r#"{"frequency_ms": 500}"#)).await.unwrap();
+        // One task blinks the whole colour
+        app.clone().oneshot(json_request(Method::POST, "/api/leds/red/blink", r#"{"frequency_ms": 500}"#)).await.unwrap();
+        app.clone().oneshot(request(Method::POST, "/api/leds/99/on")).await.unwrap();
+        backend.fail_led(3);
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/3/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let response = app.oneshot(request(Method::GET, "/api/stats")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["operations"], 3);
+        assert_eq!(stats["active_effects"], 2);
+        assert_eq!(stats["gpio_errors"], 1);
+        assert!(stats["uptime_secs"].is_u64());
+        // LED 3 still fails
+        state.leds.all_off().await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn rate_limit_rejects_requests_over_the_limit() {
+        let app = create_router(test_state(Some(3)));
+
+        for _ in 0..3 {
+            let response = app.clone().oneshot(request(Method::POST, "/api/leds/1/on")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/1/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        // Health checks are exempt
+        let response = app.oneshot(request(Method::GET, "/api/health")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_get_json_error_bodies() {
+        let app = create_router(test_state(None));
+        let mut plain_body = request(Method::POST, "/api/leds/1/blink");
+        *plain_body.body_mut() = Body::from(r#"{"frequency_ms": 500}"#);
+        for (request, status) in [
+            (json_request(Method::POST, "/api/leds/1/blink", "{not json"), StatusCode::BAD_REQUEST),
+            (json_request(Method::PUT, "/api/leds/1/brightness", r#"{"brightness": "high"}"#), StatusCode::UNPROCESSABLE_ENTITY),
+            (plain_body, StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            (request(Method::DELETE, "/api/schedule/soon"), StatusCode::BAD_REQUEST),
+        ] {
+            let uri = request.uri().to_string();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", uri);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(error["status"], "error", "{}", uri);
+            assert_eq!(error["code"], "INVALID_PARAMETER", "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn openapi_document_lists_routes_and_error_schema() {
+        let app = create_router(test_state(None));
+
+        let response = app.oneshot(request(Method::GET, "/api/openapi.json")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let doc: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(doc["paths"]["/api/leds/{led}/blink"]["post"].is_object());
+        assert!(doc["paths"]["/api/schedule"]["post"]["description"].as_str().unwrap().contains("don't check leases"));
+        assert!(doc["components"]["schemas"]["ErrorResponse"].is_object());
+        assert!(doc["components"]["schemas"]["LineDiagnostics"].is_object());
+    }
+
+    #[tokio::test]
+    async fn diagnostics_are_empty_without_gpio_lines() {
+        let app = create_router(test_state(None));
+        let response = app.oneshot(request(Method::GET, "/api/diagnostics")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn responses_are_gzipped_when_accepted() {
+        let app = create_router(AppState { compression: true, ..test_state(None) });
+
+        let mut gzip = request(Method::GET, "/api/leds");
+        gzip.headers_mut().insert(header::ACCEPT_ENCODING, header::HeaderValue::from_static("gzip"));
+        let response = app.clone().oneshot(gzip).await.unwrap();
+        assert_eq!(response.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+
+        let response = app.oneshot(request(Method::GET, "/api/leds")).await.unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn leased_leds_reject_other_clients() {
+        let app = create_router(test_state(None));
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/5/lock")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let lease: LeaseResponse = serde_json::from_slice(&body).unwrap();
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/5/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::LOCKED);
+
+        let mut with_token = request(Method::POST, "/api/leds/5/on");
+        with_token.headers_mut().insert(LEASE_HEADER, lease.token.parse().unwrap());
+        let response = app.clone().oneshot(with_token).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Other LEDs are unaffected
+        let response = app.oneshot(request(Method::POST, "/api/leds/6/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn responses_carry_a_request_id() {
+        let app = create_router(test_state(None));
+
+        let first = app.clone().oneshot(request(Method::GET, "/api/health")).await.unwrap();
+        let second = app.oneshot(request(Method::GET, "/api/leds/99")).await.unwrap();
+
+        let first = first.headers().get(REQUEST_ID_HEADER).expect("request id on success");
+        let second = second.headers().get(REQUEST_ID_HEADER).expect("request id on error");
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn ipv6_hosts_are_bracketed() {
+        assert_eq!(bind_address("0.0.0.0", 8080), "0.0.0.0:8080");
+        assert_eq!(bind_address("::1", 8080), "[::1]:8080");
+        assert_eq!(bind_address("[::]", 8080), "[::]:8080");
+        assert_eq!(bind_address("localhost", 8080), "localhost:8080");
+    }
+
+    #[tokio::test]
+    async fn unspecified_ipv6_host_accepts_ipv4_and_ipv6() {
+        let listener = bind_listener("::", 0).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while listener.accept().await.is_ok() {}
+        });
+
+        tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        tokio::net::TcpStream::connect(("::1", port)).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_serves_the_api() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("train-api-{}.sock", std::process::id()));
+        let listener = bind_unix_listener(&path, None).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve_unix(listener, create_router(test_state(Some(5))), shutdown.clone(), false, DEFAULT_MAX_CONNECTIONS));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream.write_all(b"POST /api/leds/1/on HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn http2_listener_serves_h2c_and_http1() {
+        use hyper_util::rt::{TokioExecutor, TokioIo};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve_tcp(listener, create_router(test_state(Some(5))), shutdown.clone(), true, DEFAULT_MAX_CONNECTIONS));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder().uri(format!("http://{}/api/leds/1", addr)).body(Body::empty()).unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), axum::http::Version::HTTP_2);
+        drop(sender);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /api/leds/1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // An upgrade request is served over HTTP/1.1 rather than switching protocols
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /api/leds/1 HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings, close\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn connections_over_the_limit_get_503() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(serve_tcp(listener, create_router(test_state(None)), shutdown.clone(), false, 1));
+
+        let get = |addr| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"GET /api/leds/1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // An idle connection takes the only slot
+        let idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let response = get(addr).await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(response.contains("TOO_MANY_CONNECTIONS"), "{}", response);
+
+        // Closing it frees the slot once the server notices
+        drop(idle);
+        let mut response = String::new();
+        for _ in 0..20 {
+            response = get(addr).await;
+            if response.starts_with("HTTP/1.1 200") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn blinking_leds_report_their_frequency_and_phase() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+        state.leds.blink(Led::new(3).unwrap(), 100).await.unwrap();
+
+        // The first toggle switches the LED on at once; the next turns it off after 100ms
+        let mut seen = Vec::new();
+        for wait in [50, 100] {
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+            let response = app.clone().oneshot(request(Method::GET, "/api/leds/3")).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let led: LedResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!((led.state.as_str(), led.frequency_ms), ("blinking", Some(100)));
+            seen.push(led.lit);
+        }
+        assert_eq!(seen, [Some(true), Some(false)]);
+        state.leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn leds_can_be_filtered_by_colour() {
+        let app = create_router(test_state(None));
+
+        let response = app.clone().oneshot(request(Method::GET, "/api/leds?color=Amber")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let leds: Vec<LedResponse> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(leds.iter().map(|led| led.led.get()).collect::<Vec<_>>(), (7..=12).collect::<Vec<_>>());
+
+        let response = app.oneshot(request(Method::GET, "/api/leds?color=blue")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn active_leds_leave_out_the_ones_that_are_off() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+        state.leds.on(Led::new(5).unwrap()).await.unwrap();
+        state.leds.blink(Led::new(14).unwrap(), 500).await.unwrap();
+
+        let response = app.oneshot(request(Method::GET, "/api/leds/active")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let active: ActiveLedsResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(active.count, 2);
+        let leds: Vec<(u8, &str)> = active.leds.iter().map(|led| (led.led.get(), led.state.as_str())).collect();
+        assert_eq!(leds, [(5, "on"), (14, "blinking")]);
+        state.leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn colour_routes_switch_the_whole_bank() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+
+        let response = app.clone().oneshot(request(Method::POST, "/api/leds/amber/on")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        for (led, led_state) in state.leds.get_all_states().await {
+            assert_eq!(led_state == LedState::On, AMBER_LEDS.contains(&led.get()), "LED {}", led);
+        }
+
+        let blink = json_request(Method::POST, "/api/leds/red/blink", r#"{"frequency_ms": 400}"#);
+        assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(24).unwrap()).await, LedState::Blinking { frequency_ms: 400 });
+
+        let response = app.oneshot(request(Method::POST, "/api/leds/amber/off")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.leds.state(Led::new(7).unwrap()).await, LedState::Off);
+        state.leds.all_off().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn all_blink_runs_one_task_until_all_off() {
+        let state = test_state(None);
+        let app = create_router(state.clone());
+
+        let blink = json_request(Method::POST, "/api/leds/all/blink", r#"{"frequency_ms": 0}"#);
+        assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::BAD_REQUEST);
+
+        let blink = json_request(Method::POST, "/api/leds/all/blink", r#"{"frequency_ms": 300}"#);
+        assert_eq!(app.clone().oneshot(blink).await.unwrap().status(), StatusCode::OK);
+        for (led, led_state) in state.leds.get_all_states().await {
+            assert_eq!(led_state, LedState::Blinking { frequency_ms: 300 }, "LED {}", led);
+        }
+
+        let response = app.oneshot(request(Method::POST, "/api/leds/all/off")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        for (led, led_state) in state.leds.get_all_states().await {
+            assert_eq!(led_state, LedState::Off, "LED {}", led);
+        }
+    }
+
+    #[tokio::test]
+    async fn schedules_can_be_added_listed_and_cancelled() {
+        let app = create_router(test_state(None));
+
+        let add = json_request(Method::POST, "/api/schedule", r#"{"at": "2099-01-01T09:00:00Z", "action": {"command": "on", "led": 5}, "every_secs": 3600}"#);
+        let response = app.clone().oneshot(add).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let scheduled: ScheduledCommand = serde_json::from_slice(&body).unwrap();
+        assert_eq!(scheduled.at.to_rfc3339(), "2099-01-01T09:00:00+00:00");
+        assert_eq!(scheduled.every_secs, Some(3600));
+
+        let response = app.clone().oneshot(request(Method::GET, "/api/schedule")).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<Vec<ScheduledCommand>>(&body).unwrap(), vec![scheduled.clone()]);
+
+        let uri = format!("/api/schedule/{}", scheduled.id);
+        assert_eq!(app.clone().oneshot(request(Method::DELETE, &uri)).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(app.oneshot(request(Method::DELETE, &uri)).await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+}
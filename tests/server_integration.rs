@@ -0,0 +1,125 @@
+//! End-to-end tests of the HTTP API against a mock backend, so they run without a Pi
+
+use axum::body::{self, Body};
+use axum::http::{header, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+use train::{create_router, AppState, BlinkRequest, ErrorResponse, LedController, MockBackend, StatusResponse, DEFAULT_BODY_LIMIT};
+
+fn app() -> axum::Router {
+    create_router(AppState {
+        leds: Arc::new(LedController::with_backend(Arc::new(MockBackend::new()))),
+        api_token: None,
+        self_test: None,
+        rate_limit_rps: None,
+        compression: false,
+        leases: Default::default(),
+        stats: Default::default(),
+        body_limit: DEFAULT_BODY_LIMIT,
+        audit: Default::default(),
+    })
+}
+
+/// Send a request, returning the status and the JSON body
+async fn send(app: &axum::Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn root_and_led_listing() {
+    let app = app();
+
+    let (status, body) = send(&app, Method::GET, "/", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+
+    let (status, body) = send(&app, Method::GET, "/api/leds", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let leds = body.as_array().unwrap();
+    assert_eq!(leds.len(), 24);
+    assert_eq!(leds[0], json!({ "led": 1, "state": "off", "lit": false }));
+
+    let (status, body) = send(&app, Method::GET, "/api/leds/24", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "led": 24, "state": "off", "lit": false }));
+
+    let (status, body) = send(&app, Method::GET, "/api/leds/25", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "LED_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn on_off_and_all_off() {
+    let app = app();
+
+    let (status, body) = send(&app, Method::POST, "/api/leds/5/on", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    let (_, body) = send(&app, Method::GET, "/api/leds/5", None).await;
+    assert_eq!(body["state"], "on");
+
+    let (status, _) = send(&app, Method::POST, "/api/leds/5/off", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(&app, Method::GET, "/api/leds/5", None).await;
+    assert_eq!(body["state"], "off");
+
+    let (status, body) = send(&app, Method::POST, "/api/leds/0/on", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["status"], "error");
+
+    send(&app, Method::POST, "/api/leds/6/on", None).await;
+    send(&app, Method::POST, "/api/leds/7/blink", Some(json!({ "frequency_ms": 200 }))).await;
+    let (status, body) = send(&app, Method::POST, "/api/leds/all/off", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    let (_, body) = send(&app, Method::GET, "/api/leds", None).await;
+    assert!(body.as_array().unwrap().iter().all(|led| led["state"] == "off"));
+}
+
+#[tokio::test]
+async fn blink_validates_frequency_and_led() {
+    let app = app();
+
+    let (status, _) = send(&app, Method::POST, "/api/leds/3/blink", Some(json!({ "frequency_ms": 250 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = send(&app, Method::GET, "/api/leds/3", None).await;
+    assert_eq!(body["state"], "blinking");
+    assert_eq!(body["frequency_ms"], 250);
+
+    let (status, body) = send(&app, Method::POST, "/api/leds/4/blink", Some(json!({ "frequency_ms": 0 }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "INVALID_FREQUENCY");
+
+    let (status, body) = send(&app, Method::POST, "/api/leds/99/blink", Some(json!({ "frequency_ms": 250 }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "LED_NOT_FOUND");
+
+    send(&app, Method::POST, "/api/leds/all/off", None).await;
+}
+
+#[tokio::test]
+async fn client_request_and_response_types_match_the_server() {
+    let app = app();
+
+    let request = BlinkRequest { frequency_ms: Some(300), ..Default::default() };
+    let (status, body) = send(&app, Method::POST, "/api/leds/8/blink", Some(serde_json::to_value(request).unwrap())).await;
+    assert_eq!(status, StatusCode::OK);
+    let response: StatusResponse = serde_json::from_value(body).unwrap();
+    assert_eq!(response.status, "ok");
+
+    let request = BlinkRequest { frequency_ms: Some(0), ..Default::default() };
+    let (_, body) = send(&app, Method::POST, "/api/leds/8/blink", Some(serde_json::to_value(request).unwrap())).await;
+    let error: ErrorResponse = serde_json::from_value(body).unwrap();
+    assert_eq!(error.code, "INVALID_FREQUENCY");
+
+    send(&app, Method::POST, "/api/leds/all/off", None).await;
+}